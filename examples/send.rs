@@ -68,17 +68,13 @@ fn print_destinations() {
 }
 
 fn create_note_on(channel: u8, note: u8, velocity: u8) -> coremidi::DynPacketBuffer {
-    let data = vec![
-        0x90 | (channel & 0x0f),
-        note & 0x7f,
-        velocity & 0x7f];
-    coremidi::PacketBuffer::from_data(0, data)
+    let mut packet_buf = coremidi::PacketBuffer::dyn();
+    packet_buf.push_message(0, &coremidi::MidiMessage::NoteOn { channel: channel, note: note, velocity: velocity });
+    packet_buf
 }
 
 fn create_note_off(channel: u8, note: u8, velocity: u8) -> coremidi::DynPacketBuffer {
-    let data = vec![
-        0x80 | (channel & 0x0f),
-        note & 0x7f,
-        velocity & 0x7f];
-    coremidi::PacketBuffer::from_data(0, data)
+    let mut packet_buf = coremidi::PacketBuffer::dyn();
+    packet_buf.push_message(0, &coremidi::MidiMessage::NoteOff { channel: channel, note: note, velocity: velocity });
+    packet_buf
 }
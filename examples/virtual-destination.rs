@@ -7,7 +7,7 @@ fn main() {
         println!("{}", packet_list);
     };
 
-    client.virtual_destination("example-destination", callback).unwrap();
+    let _destination = client.virtual_destination("example-destination", callback).unwrap();
 
     let mut input_line = String::new();
     println!("Press [Intro] to finish ...");
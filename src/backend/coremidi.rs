@@ -0,0 +1,353 @@
+use core_foundation::string::{CFString, CFStringRef};
+use core_foundation::base::{TCFType, OSStatus};
+use core_foundation::data::{CFData, CFDataRef};
+
+use coremidi_sys::*;
+
+use coremidi_sys_ext::{
+    MIDIInputPortCreate, MIDIDestinationCreate, MIDISend, MIDIReceived
+};
+
+use std::mem;
+use std::ptr;
+
+use PacketListRef;
+use backend::Backend;
+use properties::PropertyKey;
+use sys::MIDIReadProc;
+
+/// The backend calling the CoreMIDI framework.
+///
+pub struct CoreMidiBackend;
+
+impl Backend for CoreMidiBackend {
+    unsafe fn client_create(&self, name: &str, notify_proc: MIDINotifyProc, notify_ref_con: *mut ::libc::c_void) -> Result<MIDIClientRef, OSStatus> {
+        let client_name = CFString::new(name);
+        let mut client_ref: MIDIClientRef = 0;
+        let status = MIDIClientCreate(
+            client_name.as_concrete_TypeRef(),
+            notify_proc,
+            notify_ref_con,
+            &mut client_ref);
+        if status == 0 { Ok(client_ref) } else { Err(status) }
+    }
+
+    fn client_dispose(&self, client: MIDIClientRef) -> OSStatus {
+        unsafe { MIDIClientDispose(client) }
+    }
+
+    fn output_port_create(&self, client: MIDIClientRef, name: &str) -> Result<MIDIPortRef, OSStatus> {
+        let port_name = CFString::new(name);
+        let mut port_ref: MIDIPortRef = 0;
+        let status = unsafe { MIDIOutputPortCreate(
+            client,
+            port_name.as_concrete_TypeRef(),
+            &mut port_ref)
+        };
+        if status == 0 { Ok(port_ref) } else { Err(status) }
+    }
+
+    unsafe fn input_port_create(&self, client: MIDIClientRef, name: &str, read_proc: MIDIReadProc, ref_con: *mut ::libc::c_void) -> Result<MIDIPortRef, OSStatus> {
+        let port_name = CFString::new(name);
+        let mut port_ref: MIDIPortRef = 0;
+        let status = MIDIInputPortCreate(
+            client,
+            port_name.as_concrete_TypeRef(),
+            read_proc,
+            ref_con,
+            &mut port_ref);
+        if status == 0 { Ok(port_ref) } else { Err(status) }
+    }
+
+    fn port_dispose(&self, port: MIDIPortRef) -> OSStatus {
+        unsafe { MIDIPortDispose(port) }
+    }
+
+    fn port_connect_source(&self, port: MIDIPortRef, source: MIDIEndpointRef) -> OSStatus {
+        unsafe { MIDIPortConnectSource(port, source, ptr::null_mut()) }
+    }
+
+    fn port_disconnect_source(&self, port: MIDIPortRef, source: MIDIEndpointRef) -> OSStatus {
+        unsafe { MIDIPortDisconnectSource(port, source) }
+    }
+
+    fn source_create(&self, client: MIDIClientRef, name: &str) -> Result<MIDIEndpointRef, OSStatus> {
+        let source_name = CFString::new(name);
+        let mut source_ref: MIDIEndpointRef = 0;
+        let status = unsafe { MIDISourceCreate(
+            client,
+            source_name.as_concrete_TypeRef(),
+            &mut source_ref)
+        };
+        if status == 0 { Ok(source_ref) } else { Err(status) }
+    }
+
+    unsafe fn destination_create(&self, client: MIDIClientRef, name: &str, read_proc: MIDIReadProc, ref_con: *mut ::libc::c_void) -> Result<MIDIEndpointRef, OSStatus> {
+        let destination_name = CFString::new(name);
+        let mut destination_ref: MIDIEndpointRef = 0;
+        let status = MIDIDestinationCreate(
+            client,
+            destination_name.as_concrete_TypeRef(),
+            read_proc,
+            ref_con,
+            &mut destination_ref);
+        if status == 0 { Ok(destination_ref) } else { Err(status) }
+    }
+
+    fn endpoint_dispose(&self, endpoint: MIDIEndpointRef) -> OSStatus {
+        unsafe { MIDIEndpointDispose(endpoint) }
+    }
+
+    fn send(&self, port: MIDIPortRef, destination: MIDIEndpointRef, packet_list: PacketListRef) -> OSStatus {
+        unsafe { MIDISend(port, destination, packet_list.as_ptr()) }
+    }
+
+    fn received(&self, source: MIDIEndpointRef, packet_list: PacketListRef) -> OSStatus {
+        unsafe { MIDIReceived(source, packet_list.as_ptr()) }
+    }
+
+    fn flush_output(&self, destination: MIDIEndpointRef) -> OSStatus {
+        unsafe { MIDIFlushOutput(destination) }
+    }
+
+    fn restart(&self) -> OSStatus {
+        unsafe { MIDIRestart() }
+    }
+
+    fn number_of_sources(&self) -> usize {
+        unsafe { MIDIGetNumberOfSources() as usize }
+    }
+
+    fn source(&self, index: usize) -> MIDIEndpointRef {
+        unsafe { MIDIGetSource(index as ItemCount) }
+    }
+
+    fn number_of_destinations(&self) -> usize {
+        unsafe { MIDIGetNumberOfDestinations() as usize }
+    }
+
+    fn destination(&self, index: usize) -> MIDIEndpointRef {
+        unsafe { MIDIGetDestination(index as ItemCount) }
+    }
+
+    fn number_of_devices(&self) -> usize {
+        unsafe { MIDIGetNumberOfDevices() as usize }
+    }
+
+    fn device(&self, index: usize) -> MIDIDeviceRef {
+        unsafe { MIDIGetDevice(index as ItemCount) }
+    }
+
+    fn device_number_of_entities(&self, device: MIDIDeviceRef) -> usize {
+        unsafe { MIDIDeviceGetNumberOfEntities(device) as usize }
+    }
+
+    fn device_entity(&self, device: MIDIDeviceRef, index: usize) -> MIDIEntityRef {
+        unsafe { MIDIDeviceGetEntity(device, index as ItemCount) }
+    }
+
+    fn entity_number_of_sources(&self, entity: MIDIEntityRef) -> usize {
+        unsafe { MIDIEntityGetNumberOfSources(entity) as usize }
+    }
+
+    fn entity_source(&self, entity: MIDIEntityRef, index: usize) -> MIDIEndpointRef {
+        unsafe { MIDIEntityGetSource(entity, index as ItemCount) }
+    }
+
+    fn entity_number_of_destinations(&self, entity: MIDIEntityRef) -> usize {
+        unsafe { MIDIEntityGetNumberOfDestinations(entity) as usize }
+    }
+
+    fn entity_destination(&self, entity: MIDIEntityRef, index: usize) -> MIDIEndpointRef {
+        unsafe { MIDIEntityGetDestination(entity, index as ItemCount) }
+    }
+
+    fn entity_device(&self, entity: MIDIEntityRef) -> Result<MIDIDeviceRef, OSStatus> {
+        let mut device_ref: MIDIDeviceRef = 0;
+        let status = unsafe { MIDIEntityGetDevice(entity, &mut device_ref) };
+        if status == 0 { Ok(device_ref) } else { Err(status) }
+    }
+
+    fn endpoint_entity(&self, endpoint: MIDIEndpointRef) -> Result<MIDIEntityRef, OSStatus> {
+        let mut entity_ref: MIDIEntityRef = 0;
+        let status = unsafe { MIDIEndpointGetEntity(endpoint, &mut entity_ref) };
+        if status == 0 { Ok(entity_ref) } else { Err(status) }
+    }
+
+    fn device_add_entity(&self, device: MIDIDeviceRef, name: &str, embedded: bool, sources: usize, destinations: usize) -> Result<MIDIEntityRef, OSStatus> {
+        let entity_name = CFString::new(name);
+        let mut entity_ref: MIDIEntityRef = 0;
+        let status = unsafe { MIDIDeviceAddEntity(
+            device,
+            entity_name.as_concrete_TypeRef(),
+            embedded as u8,
+            sources as ItemCount,
+            destinations as ItemCount,
+            &mut entity_ref)
+        };
+        if status == 0 { Ok(entity_ref) } else { Err(status) }
+    }
+
+    fn number_of_external_devices(&self) -> usize {
+        unsafe { MIDIGetNumberOfExternalDevices() as usize }
+    }
+
+    fn external_device(&self, index: usize) -> MIDIDeviceRef {
+        unsafe { MIDIGetExternalDevice(index as ItemCount) }
+    }
+
+    fn external_device_create(&self, name: &str, manufacturer: &str, model: &str) -> Result<MIDIDeviceRef, OSStatus> {
+        let device_name = CFString::new(name);
+        let device_manufacturer = CFString::new(manufacturer);
+        let device_model = CFString::new(model);
+        let mut device_ref: MIDIDeviceRef = 0;
+        let status = unsafe { MIDIExternalDeviceCreate(
+            device_name.as_concrete_TypeRef(),
+            device_manufacturer.as_concrete_TypeRef(),
+            device_model.as_concrete_TypeRef(),
+            &mut device_ref)
+        };
+        if status == 0 { Ok(device_ref) } else { Err(status) }
+    }
+
+    fn setup_add_external_device(&self, device: MIDIDeviceRef) -> OSStatus {
+        unsafe { MIDISetupAddExternalDevice(device) }
+    }
+
+    fn setup_remove_external_device(&self, device: MIDIDeviceRef) -> OSStatus {
+        unsafe { MIDISetupRemoveExternalDevice(device) }
+    }
+
+    fn get_string_property(&self, object: MIDIObjectRef, key: &PropertyKey) -> Result<String, OSStatus> {
+        let key = property_key(key);
+        let mut string_ref: CFStringRef = ptr::null();
+        let status = unsafe { MIDIObjectGetStringProperty(object, key.as_concrete_TypeRef(), &mut string_ref) };
+        if status == 0 {
+            let string: CFString = unsafe { TCFType::wrap_under_create_rule(string_ref) };
+            Ok(format!("{}", string))
+        }
+        else { Err(status) }
+    }
+
+    fn set_string_property(&self, object: MIDIObjectRef, key: &PropertyKey, value: &str) -> OSStatus {
+        let key = property_key(key);
+        let string = CFString::new(value);
+        unsafe { MIDIObjectSetStringProperty(object, key.as_concrete_TypeRef(), string.as_concrete_TypeRef()) }
+    }
+
+    fn get_integer_property(&self, object: MIDIObjectRef, key: &PropertyKey) -> Result<i32, OSStatus> {
+        let key = property_key(key);
+        let mut value: SInt32 = 0;
+        let status = unsafe { MIDIObjectGetIntegerProperty(object, key.as_concrete_TypeRef(), &mut value) };
+        if status == 0 { Ok(value) } else { Err(status) }
+    }
+
+    fn set_integer_property(&self, object: MIDIObjectRef, key: &PropertyKey, value: i32) -> OSStatus {
+        let key = property_key(key);
+        unsafe { MIDIObjectSetIntegerProperty(object, key.as_concrete_TypeRef(), value) }
+    }
+
+    fn object_find_by_unique_id(&self, unique_id: MIDIUniqueID) -> Result<(MIDIObjectRef, MIDIObjectType), OSStatus> {
+        let mut object_ref: MIDIObjectRef = 0;
+        let mut object_type: MIDIObjectType = 0;
+        let status = unsafe { MIDIObjectFindByUniqueID(unique_id, &mut object_ref, &mut object_type) };
+        if status == 0 { Ok((object_ref, object_type)) } else { Err(status) }
+    }
+
+    fn thru_connection_create(&self, persistent_owner_id: Option<&str>, params: &[u8]) -> Result<MIDIThruConnectionRef, OSStatus> {
+        let owner_id = persistent_owner_id.map(CFString::new);
+        let params = CFData::from_buffer(params);
+        let mut connection_ref: MIDIThruConnectionRef = 0;
+        let status = unsafe { MIDIThruConnectionCreate(
+            owner_id.as_ref().map_or(ptr::null(), |owner_id| owner_id.as_concrete_TypeRef()),
+            params.as_concrete_TypeRef(),
+            &mut connection_ref)
+        };
+        if status == 0 { Ok(connection_ref) } else { Err(status) }
+    }
+
+    fn thru_connection_dispose(&self, connection: MIDIThruConnectionRef) -> OSStatus {
+        unsafe { MIDIThruConnectionDispose(connection) }
+    }
+
+    fn thru_connection_get_params(&self, connection: MIDIThruConnectionRef) -> Result<Vec<u8>, OSStatus> {
+        let mut data_ref: CFDataRef = ptr::null();
+        let status = unsafe { MIDIThruConnectionGetParams(connection, &mut data_ref) };
+        if status == 0 {
+            let data: CFData = unsafe { TCFType::wrap_under_create_rule(data_ref) };
+            Ok(data.bytes().to_vec())
+        }
+        else { Err(status) }
+    }
+
+    fn thru_connection_set_params(&self, connection: MIDIThruConnectionRef, params: &[u8]) -> OSStatus {
+        let params = CFData::from_buffer(params);
+        unsafe { MIDIThruConnectionSetParams(connection, params.as_concrete_TypeRef()) }
+    }
+
+    fn thru_connection_find(&self, persistent_owner_id: &str) -> Result<Vec<MIDIThruConnectionRef>, OSStatus> {
+        let owner_id = CFString::new(persistent_owner_id);
+        let mut data_ref: CFDataRef = ptr::null();
+        let status = unsafe { MIDIThruConnectionFind(owner_id.as_concrete_TypeRef(), &mut data_ref) };
+        if status == 0 {
+            // An array of MIDIThruConnectionRef
+            let data: CFData = unsafe { TCFType::wrap_under_create_rule(data_ref) };
+            Ok(data.bytes().chunks(mem::size_of::<MIDIThruConnectionRef>())
+                .map(|bytes| MIDIThruConnectionRef::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+                .collect())
+        }
+        else { Err(status) }
+    }
+}
+
+// The CoreMIDI constant of a property key, or a new string for the custom ones.
+fn property_key(key: &PropertyKey) -> CFString {
+    let key_ref = unsafe {
+        match *key {
+            PropertyKey::Name => kMIDIPropertyName,
+            PropertyKey::Manufacturer => kMIDIPropertyManufacturer,
+            PropertyKey::Model => kMIDIPropertyModel,
+            PropertyKey::UniqueID => kMIDIPropertyUniqueID,
+            PropertyKey::DeviceID => kMIDIPropertyDeviceID,
+            PropertyKey::ReceiveChannels => kMIDIPropertyReceiveChannels,
+            PropertyKey::TransmitChannels => kMIDIPropertyTransmitChannels,
+            PropertyKey::MaxSysExSpeed => kMIDIPropertyMaxSysExSpeed,
+            PropertyKey::AdvanceScheduleTimeMuSec => kMIDIPropertyAdvanceScheduleTimeMuSec,
+            PropertyKey::IsEmbeddedEntity => kMIDIPropertyIsEmbeddedEntity,
+            PropertyKey::IsBroadcast => kMIDIPropertyIsBroadcast,
+            PropertyKey::SingleRealtimeEntity => kMIDIPropertySingleRealtimeEntity,
+            PropertyKey::ConnectionUniqueID => kMIDIPropertyConnectionUniqueID,
+            PropertyKey::Offline => kMIDIPropertyOffline,
+            PropertyKey::Private => kMIDIPropertyPrivate,
+            PropertyKey::DriverOwner => kMIDIPropertyDriverOwner,
+            PropertyKey::DriverVersion => kMIDIPropertyDriverVersion,
+            PropertyKey::SupportsGeneralMIDI => kMIDIPropertySupportsGeneralMIDI,
+            PropertyKey::SupportsMMC => kMIDIPropertySupportsMMC,
+            PropertyKey::CanRoute => kMIDIPropertyCanRoute,
+            PropertyKey::ReceivesClock => kMIDIPropertyReceivesClock,
+            PropertyKey::ReceivesMTC => kMIDIPropertyReceivesMTC,
+            PropertyKey::ReceivesNotes => kMIDIPropertyReceivesNotes,
+            PropertyKey::ReceivesProgramChanges => kMIDIPropertyReceivesProgramChanges,
+            PropertyKey::ReceivesBankSelectMSB => kMIDIPropertyReceivesBankSelectMSB,
+            PropertyKey::ReceivesBankSelectLSB => kMIDIPropertyReceivesBankSelectLSB,
+            PropertyKey::TransmitsBankSelectMSB => kMIDIPropertyTransmitsBankSelectMSB,
+            PropertyKey::TransmitsBankSelectLSB => kMIDIPropertyTransmitsBankSelectLSB,
+            PropertyKey::TransmitsClock => kMIDIPropertyTransmitsClock,
+            PropertyKey::TransmitsMTC => kMIDIPropertyTransmitsMTC,
+            PropertyKey::TransmitsNotes => kMIDIPropertyTransmitsNotes,
+            PropertyKey::TransmitsProgramChanges => kMIDIPropertyTransmitsProgramChanges,
+            PropertyKey::PanDisruptsStereo => kMIDIPropertyPanDisruptsStereo,
+            PropertyKey::IsSampler => kMIDIPropertyIsSampler,
+            PropertyKey::IsDrumMachine => kMIDIPropertyIsDrumMachine,
+            PropertyKey::IsMixer => kMIDIPropertyIsMixer,
+            PropertyKey::IsEffectUnit => kMIDIPropertyIsEffectUnit,
+            PropertyKey::MaxReceiveChannels => kMIDIPropertyMaxReceiveChannels,
+            PropertyKey::MaxTransmitChannels => kMIDIPropertyMaxTransmitChannels,
+            PropertyKey::DriverDeviceEditorApp => kMIDIPropertyDriverDeviceEditorApp,
+            PropertyKey::SupportsShowControl => kMIDIPropertySupportsShowControl,
+            PropertyKey::DisplayName => kMIDIPropertyDisplayName,
+            PropertyKey::Custom(ref name) => return CFString::new(name)
+        }
+    };
+    unsafe { TCFType::wrap_under_get_rule(key_ref) }
+}
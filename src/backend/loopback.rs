@@ -0,0 +1,628 @@
+use sys::{
+    OSStatus, MIDIObjectRef, MIDIClientRef, MIDIPortRef, MIDIEndpointRef, MIDIDeviceRef, MIDIEntityRef, MIDIThruConnectionRef,
+    MIDINotification, MIDINotifyProc, MIDINotificationMessageID, MIDIObjectAddRemoveNotification, MIDIObjectType, MIDIUniqueID,
+    MIDIReadProc, kMIDIMsgObjectAdded, kMIDIMsgObjectRemoved,
+    kMIDIObjectType_Other, kMIDIObjectType_Source, kMIDIObjectType_Destination
+};
+
+use std::collections::HashMap;
+use std::mem;
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+
+use backend::Backend;
+use backend::memory::MemoryBackend;
+use clock::{Clock, ManualClock};
+use packets::{PacketBuffer, PacketListRef, Timestamp};
+use properties::PropertyKey;
+
+/// An in-memory MIDI server looping back the packets sent through it, for integration tests.
+///
+/// It extends a [MemoryBackend](struct.MemoryBackend.html) (which it derefs to) with:
+///
+/// - A clock, a simulated [ManualClock](struct.ManualClock.html) unless another [Clock](trait.Clock.html)
+///   is given. The packets sent or received with a timestamp in the future are held until the clock
+///   reaches it, see [advance](#method.advance) and [poll](#method.poll). A timestamp of 0 means now,
+///   as in CoreMIDI.
+///   Sending or receiving fails right away when the packets could not be delivered, even the ones
+///   held for later, and the held packets are dropped when their port or endpoint is disposed.
+/// - `Notification::ObjectAdded` and `Notification::ObjectRemoved` being posted to all the clients
+///   when virtual sources and destinations are created and disposed.
+///
+/// ```
+/// use std::sync::Arc;
+/// let loopback = Arc::new(coremidi::Loopback::new());
+/// coremidi::with_backend(loopback.clone(), || {
+///     let client = coremidi::Client::new("example-client").unwrap();
+///     let _destination = client.virtual_destination("example-destination", |packet_list| {
+///         println!("{}", packet_list);
+///     }).unwrap();
+///     let output_port = client.output_port("example-port").unwrap();
+///     let mut packets = coremidi::PacketBuffer::dyn();
+///     packets.push_packet(100, &[0x90, 0x40, 0x7f]);
+///     output_port.send(&coremidi::Destination::from_index(0), packets.as_ref()).unwrap();
+///     assert_eq!(loopback.pending_count(), 1);
+///     loopback.advance(100);
+///     assert_eq!(loopback.pending_count(), 0);
+/// });
+/// ```
+pub struct Loopback {
+    memory: MemoryBackend,
+    clock: Arc<dyn Clock>,
+    // The clock moved by advance and advance_to, when the loopback created it.
+    manual_clock: Option<Arc<ManualClock>>,
+    state: Mutex<State>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Target {
+    Send(MIDIPortRef, MIDIEndpointRef),
+    Received(MIDIEndpointRef),
+}
+
+struct Scheduled {
+    timestamp: Timestamp,
+    target: Target,
+    data: Vec<u8>,
+}
+
+struct State {
+    // Ordered by timestamp, and then by the order in which the packets were scheduled.
+    scheduled: Vec<Scheduled>,
+    virtual_endpoints: HashMap<MIDIEndpointRef, (MIDIClientRef, MIDIObjectType)>,
+}
+
+impl Loopback {
+    /// Create a loopback server with its own simulated clock, starting at 0.
+    ///
+    pub fn new() -> Loopback {
+        let clock = Arc::new(ManualClock::new(0));
+        Loopback::create(clock.clone(), Some(clock))
+    }
+
+    /// Create a loopback server using the given clock for scheduling, like a
+    /// [ManualClock](struct.ManualClock.html) shared with other tested code, or the [HostClock](struct.HostClock.html).
+    ///
+    /// The clock is moved by its owner, and the packets that become due are delivered on the next
+    /// call to [poll](#method.poll).
+    ///
+    pub fn with_clock(clock: Arc<dyn Clock>) -> Loopback {
+        Loopback::create(clock, None)
+    }
+
+    fn create(clock: Arc<dyn Clock>, manual_clock: Option<Arc<ManualClock>>) -> Loopback {
+        Loopback {
+            memory: MemoryBackend::new(),
+            clock: clock,
+            manual_clock: manual_clock,
+            state: Mutex::new(State {
+                scheduled: Vec::new(),
+                virtual_endpoints: HashMap::new(),
+            })
+        }
+    }
+
+    /// The clock used for scheduling.
+    ///
+    pub fn clock(&self) -> Arc<dyn Clock> {
+        self.clock.clone()
+    }
+
+    /// The current time of the clock.
+    ///
+    pub fn now(&self) -> Timestamp {
+        self.clock.now()
+    }
+
+    /// Deliver the packets that are due at the current time of the clock, in the order of their timestamps.
+    ///
+    pub fn poll(&self) {
+        self.deliver_due(self.clock.now());
+    }
+
+    /// Advance the simulated clock by some ticks, delivering the packets that become due.
+    ///
+    /// It panics if the loopback was created [with_clock](#method.with_clock), as only the owner
+    /// of that clock can move it.
+    ///
+    pub fn advance(&self, ticks: u64) {
+        let now = self.manual_clock().advance(ticks);
+        self.deliver_due(now);
+    }
+
+    /// Advance the simulated clock up to a timestamp, delivering the packets that become due
+    /// in the order of their timestamps.
+    ///
+    /// The clock never goes backwards. It panics if the loopback was created [with_clock](#method.with_clock),
+    /// as [advance](#method.advance) does.
+    ///
+    pub fn advance_to(&self, timestamp: Timestamp) {
+        self.manual_clock().set(timestamp);
+        self.poll();
+    }
+
+    fn manual_clock(&self) -> &ManualClock {
+        match self.manual_clock {
+            Some(ref clock) => clock,
+            None => panic!("The clock given to the loopback can only be moved by its owner, use poll instead"),
+        }
+    }
+
+    /// The number of packets waiting for the simulated clock to reach their timestamp.
+    ///
+    pub fn pending_count(&self) -> usize {
+        self.state.lock().unwrap().scheduled.len()
+    }
+
+    fn deliver_due(&self, now: Timestamp) {
+        let due = {
+            let mut state = self.state.lock().unwrap();
+            let count = state.scheduled.iter().take_while(|scheduled| scheduled.timestamp <= now).count();
+            state.scheduled.drain(..count).collect::<Vec<_>>()
+        };
+        for scheduled in due {
+            let mut packet_buffer = PacketBuffer::dyn();
+            packet_buffer.push_packet(scheduled.timestamp, &scheduled.data);
+            self.deliver(scheduled.target, packet_buffer.as_ref());
+        }
+    }
+
+    // The status of delivering to a target, without delivering anything.
+    fn target_status(&self, target: Target) -> OSStatus {
+        match target {
+            Target::Send(port, destination) => self.memory.send_status(port, destination),
+            Target::Received(source) => self.memory.received_status(source),
+        }
+    }
+
+    fn deliver(&self, target: Target, packet_list: PacketListRef) -> OSStatus {
+        match target {
+            Target::Send(port, destination) => self.memory.send(port, destination, packet_list),
+            Target::Received(source) => self.memory.received(source, packet_list),
+        }
+    }
+
+    // Deliver the packets that are due now, and hold the rest until their time comes.
+    // The target is checked first, so the packets held are not lost without an error.
+    fn schedule(&self, target: Target, packet_list: PacketListRef) -> OSStatus {
+        let status = self.target_status(target);
+        if status != 0 {
+            return status;
+        }
+        let mut now_buffer = PacketBuffer::dyn();
+        {
+            let now = self.clock.now();
+            let mut state = self.state.lock().unwrap();
+            for packet in packet_list.iter() {
+                let timestamp = packet.timestamp();
+                if timestamp == 0 || timestamp <= now {
+                    now_buffer.push_packet(timestamp, packet.data());
+                }
+                else {
+                    let index = state.scheduled.iter().take_while(|scheduled| scheduled.timestamp <= timestamp).count();
+                    state.scheduled.insert(index, Scheduled { timestamp: timestamp, target: target, data: packet.data().to_vec() });
+                }
+            }
+        }
+        if now_buffer.packet_count() > 0 { self.deliver(target, now_buffer.as_ref()) } else { 0 }
+    }
+
+    fn post_added_removed(&self, message_id: u32, child: MIDIEndpointRef, child_type: MIDIObjectType) {
+        let notification = MIDIObjectAddRemoveNotification {
+            messageID: message_id as MIDINotificationMessageID,
+            messageSize: mem::size_of::<MIDIObjectAddRemoveNotification>() as u32,
+            parent: 0,
+            parentType: kMIDIObjectType_Other,
+            child: child,
+            childType: child_type,
+        };
+        self.memory.post_notification(unsafe { &*(&notification as *const _ as *const MIDINotification) });
+    }
+
+    fn endpoint_created(&self, client: MIDIClientRef, endpoint: MIDIEndpointRef, endpoint_type: MIDIObjectType) {
+        self.state.lock().unwrap().virtual_endpoints.insert(endpoint, (client, endpoint_type));
+        self.post_added_removed(kMIDIMsgObjectAdded, endpoint, endpoint_type);
+    }
+
+    fn endpoint_removed(&self, endpoint: MIDIEndpointRef, endpoint_type: MIDIObjectType) {
+        self.state.lock().unwrap().scheduled.retain(|scheduled| match scheduled.target {
+            Target::Send(_, destination) => destination != endpoint,
+            Target::Received(source) => source != endpoint,
+        });
+        self.post_added_removed(kMIDIMsgObjectRemoved, endpoint, endpoint_type);
+    }
+}
+
+impl Default for Loopback {
+    fn default() -> Self {
+        Loopback::new()
+    }
+}
+
+impl Deref for Loopback {
+    type Target = MemoryBackend;
+
+    fn deref(&self) -> &MemoryBackend {
+        &self.memory
+    }
+}
+
+impl Backend for Loopback {
+    unsafe fn client_create(&self, name: &str, notify_proc: MIDINotifyProc, notify_ref_con: *mut ::libc::c_void) -> Result<MIDIClientRef, OSStatus> {
+        self.memory.client_create(name, notify_proc, notify_ref_con)
+    }
+
+    fn client_dispose(&self, client: MIDIClientRef) -> OSStatus {
+        let status = self.memory.client_dispose(client);
+        if status == 0 {
+            // The ports and virtual endpoints of a client go away with it
+            let removed = {
+                let mut state = self.state.lock().unwrap();
+                state.scheduled.retain(|scheduled| self.target_status(scheduled.target) == 0);
+                let removed = state.virtual_endpoints.iter()
+                    .filter(|&(_, &(owner, _))| owner == client)
+                    .map(|(&endpoint, &(_, endpoint_type))| (endpoint, endpoint_type))
+                    .collect::<Vec<_>>();
+                for &(endpoint, _) in &removed {
+                    state.virtual_endpoints.remove(&endpoint);
+                }
+                removed
+            };
+            for (endpoint, endpoint_type) in removed {
+                self.endpoint_removed(endpoint, endpoint_type);
+            }
+        }
+        status
+    }
+
+    fn output_port_create(&self, client: MIDIClientRef, name: &str) -> Result<MIDIPortRef, OSStatus> {
+        self.memory.output_port_create(client, name)
+    }
+
+    unsafe fn input_port_create(&self, client: MIDIClientRef, name: &str, read_proc: MIDIReadProc, ref_con: *mut ::libc::c_void) -> Result<MIDIPortRef, OSStatus> {
+        self.memory.input_port_create(client, name, read_proc, ref_con)
+    }
+
+    fn port_dispose(&self, port: MIDIPortRef) -> OSStatus {
+        let status = self.memory.port_dispose(port);
+        if status == 0 {
+            self.state.lock().unwrap().scheduled.retain(|scheduled| match scheduled.target {
+                Target::Send(sender, _) => sender != port,
+                Target::Received(_) => true,
+            });
+        }
+        status
+    }
+
+    fn port_connect_source(&self, port: MIDIPortRef, source: MIDIEndpointRef) -> OSStatus {
+        self.memory.port_connect_source(port, source)
+    }
+
+    fn port_disconnect_source(&self, port: MIDIPortRef, source: MIDIEndpointRef) -> OSStatus {
+        self.memory.port_disconnect_source(port, source)
+    }
+
+    fn source_create(&self, client: MIDIClientRef, name: &str) -> Result<MIDIEndpointRef, OSStatus> {
+        let source = self.memory.source_create(client, name)?;
+        self.endpoint_created(client, source, kMIDIObjectType_Source);
+        Ok(source)
+    }
+
+    unsafe fn destination_create(&self, client: MIDIClientRef, name: &str, read_proc: MIDIReadProc, ref_con: *mut ::libc::c_void) -> Result<MIDIEndpointRef, OSStatus> {
+        let destination = self.memory.destination_create(client, name, read_proc, ref_con)?;
+        self.endpoint_created(client, destination, kMIDIObjectType_Destination);
+        Ok(destination)
+    }
+
+    fn endpoint_dispose(&self, endpoint: MIDIEndpointRef) -> OSStatus {
+        let status = self.memory.endpoint_dispose(endpoint);
+        if status == 0 {
+            let removed = self.state.lock().unwrap().virtual_endpoints.remove(&endpoint);
+            if let Some((_, endpoint_type)) = removed {
+                self.endpoint_removed(endpoint, endpoint_type);
+            }
+        }
+        status
+    }
+
+    fn send(&self, port: MIDIPortRef, destination: MIDIEndpointRef, packet_list: PacketListRef) -> OSStatus {
+        self.schedule(Target::Send(port, destination), packet_list)
+    }
+
+    fn received(&self, source: MIDIEndpointRef, packet_list: PacketListRef) -> OSStatus {
+        self.schedule(Target::Received(source), packet_list)
+    }
+
+    fn flush_output(&self, destination: MIDIEndpointRef) -> OSStatus {
+        let status = self.memory.flush_output(destination);
+        if status == 0 {
+            self.state.lock().unwrap().scheduled.retain(|scheduled| match scheduled.target {
+                Target::Send(_, scheduled_destination) => destination != 0 && scheduled_destination != destination,
+                Target::Received(_) => true,
+            });
+        }
+        status
+    }
+
+    fn restart(&self) -> OSStatus {
+        self.memory.restart()
+    }
+
+    fn number_of_sources(&self) -> usize {
+        self.memory.number_of_sources()
+    }
+
+    fn source(&self, index: usize) -> MIDIEndpointRef {
+        self.memory.source(index)
+    }
+
+    fn number_of_destinations(&self) -> usize {
+        self.memory.number_of_destinations()
+    }
+
+    fn destination(&self, index: usize) -> MIDIEndpointRef {
+        self.memory.destination(index)
+    }
+
+    fn number_of_devices(&self) -> usize {
+        self.memory.number_of_devices()
+    }
+
+    fn device(&self, index: usize) -> MIDIDeviceRef {
+        self.memory.device(index)
+    }
+
+    fn device_number_of_entities(&self, device: MIDIDeviceRef) -> usize {
+        self.memory.device_number_of_entities(device)
+    }
+
+    fn device_entity(&self, device: MIDIDeviceRef, index: usize) -> MIDIEntityRef {
+        self.memory.device_entity(device, index)
+    }
+
+    fn entity_number_of_sources(&self, entity: MIDIEntityRef) -> usize {
+        self.memory.entity_number_of_sources(entity)
+    }
+
+    fn entity_source(&self, entity: MIDIEntityRef, index: usize) -> MIDIEndpointRef {
+        self.memory.entity_source(entity, index)
+    }
+
+    fn entity_number_of_destinations(&self, entity: MIDIEntityRef) -> usize {
+        self.memory.entity_number_of_destinations(entity)
+    }
+
+    fn entity_destination(&self, entity: MIDIEntityRef, index: usize) -> MIDIEndpointRef {
+        self.memory.entity_destination(entity, index)
+    }
+
+    fn entity_device(&self, entity: MIDIEntityRef) -> Result<MIDIDeviceRef, OSStatus> {
+        self.memory.entity_device(entity)
+    }
+
+    fn endpoint_entity(&self, endpoint: MIDIEndpointRef) -> Result<MIDIEntityRef, OSStatus> {
+        self.memory.endpoint_entity(endpoint)
+    }
+
+    fn device_add_entity(&self, device: MIDIDeviceRef, name: &str, embedded: bool, sources: usize, destinations: usize) -> Result<MIDIEntityRef, OSStatus> {
+        self.memory.device_add_entity(device, name, embedded, sources, destinations)
+    }
+
+    fn number_of_external_devices(&self) -> usize {
+        self.memory.number_of_external_devices()
+    }
+
+    fn external_device(&self, index: usize) -> MIDIDeviceRef {
+        self.memory.external_device(index)
+    }
+
+    fn external_device_create(&self, name: &str, manufacturer: &str, model: &str) -> Result<MIDIDeviceRef, OSStatus> {
+        self.memory.external_device_create(name, manufacturer, model)
+    }
+
+    fn setup_add_external_device(&self, device: MIDIDeviceRef) -> OSStatus {
+        self.memory.setup_add_external_device(device)
+    }
+
+    fn setup_remove_external_device(&self, device: MIDIDeviceRef) -> OSStatus {
+        self.memory.setup_remove_external_device(device)
+    }
+
+    fn get_string_property(&self, object: MIDIObjectRef, key: &PropertyKey) -> Result<String, OSStatus> {
+        self.memory.get_string_property(object, key)
+    }
+
+    fn set_string_property(&self, object: MIDIObjectRef, key: &PropertyKey, value: &str) -> OSStatus {
+        self.memory.set_string_property(object, key, value)
+    }
+
+    fn get_integer_property(&self, object: MIDIObjectRef, key: &PropertyKey) -> Result<i32, OSStatus> {
+        self.memory.get_integer_property(object, key)
+    }
+
+    fn set_integer_property(&self, object: MIDIObjectRef, key: &PropertyKey, value: i32) -> OSStatus {
+        self.memory.set_integer_property(object, key, value)
+    }
+
+    fn object_find_by_unique_id(&self, unique_id: MIDIUniqueID) -> Result<(MIDIObjectRef, MIDIObjectType), OSStatus> {
+        self.memory.object_find_by_unique_id(unique_id)
+    }
+
+    fn thru_connection_create(&self, persistent_owner_id: Option<&str>, params: &[u8]) -> Result<MIDIThruConnectionRef, OSStatus> {
+        self.memory.thru_connection_create(persistent_owner_id, params)
+    }
+
+    fn thru_connection_dispose(&self, connection: MIDIThruConnectionRef) -> OSStatus {
+        self.memory.thru_connection_dispose(connection)
+    }
+
+    fn thru_connection_get_params(&self, connection: MIDIThruConnectionRef) -> Result<Vec<u8>, OSStatus> {
+        self.memory.thru_connection_get_params(connection)
+    }
+
+    fn thru_connection_set_params(&self, connection: MIDIThruConnectionRef, params: &[u8]) -> OSStatus {
+        self.memory.thru_connection_set_params(connection, params)
+    }
+
+    fn thru_connection_find(&self, persistent_owner_id: &str) -> Result<Vec<MIDIThruConnectionRef>, OSStatus> {
+        self.memory.thru_connection_find(persistent_owner_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use backend::with_backend;
+    use backend::loopback::Loopback;
+    use clock::{Clock, ManualClock};
+    use notifications::AddedRemovedInfo;
+    use object::ObjectType;
+    use {Client, Destination, Endpoint, Error, Notification, Object, PacketBuffer, PacketList, Source};
+
+    fn packets(packets: &[(u64, &[u8])]) -> PacketList {
+        let mut packet_buffer = PacketBuffer::dyn();
+        for &(timestamp, data) in packets {
+            packet_buffer.push_packet(timestamp, data);
+        }
+        packet_buffer.as_ref().to_packet_list()
+    }
+
+    fn timestamps(packet_lists: &[PacketList]) -> Vec<u64> {
+        packet_lists.iter().flat_map(|packet_list| packet_list.iter().map(|packet| packet.timestamp())).collect()
+    }
+
+    #[test]
+    fn scheduled_sends_follow_the_clock() {
+        let loopback = Arc::new(Loopback::new());
+        with_backend(loopback.clone(), || {
+            let received = Arc::new(Mutex::new(Vec::new()));
+            let client = Client::new("client").unwrap();
+            let callback_received = received.clone();
+            let _destination = client.virtual_destination("destination", move |packet_list| {
+                callback_received.lock().unwrap().push(packet_list.to_packet_list());
+            }).unwrap();
+            let output_port = client.output_port("port").unwrap();
+
+            output_port.send(&Destination::from_index(0), packets(&[(0, &[0xfa]), (30, &[0xf8]), (10, &[0xf8])]).as_ref()).unwrap();
+            assert_eq!(timestamps(&received.lock().unwrap()), vec![0]);
+            assert_eq!(loopback.pending_count(), 2);
+
+            loopback.advance(10);
+            assert_eq!(timestamps(&received.lock().unwrap()), vec![0, 10]);
+
+            output_port.send(&Destination::from_index(0), packets(&[(5, &[0xf8]), (20, &[0xf8])]).as_ref()).unwrap();
+            loopback.advance_to(25);
+            assert_eq!(timestamps(&received.lock().unwrap()), vec![0, 10, 5, 20]);
+            assert_eq!(loopback.now(), 25);
+
+            ::flush().unwrap();
+            assert_eq!(loopback.pending_count(), 0);
+            loopback.advance(100);
+            assert_eq!(timestamps(&received.lock().unwrap()), vec![0, 10, 5, 20]);
+        });
+    }
+
+    #[test]
+    fn shared_clock() {
+        let clock = Arc::new(ManualClock::new(1000));
+        let loopback = Arc::new(Loopback::with_clock(clock.clone()));
+        let external = loopback.add_destination("external");
+        with_backend(loopback.clone(), || {
+            let client = Client::new("client").unwrap();
+            let output_port = client.output_port("port").unwrap();
+            output_port.send(&Destination::from_index(0), packets(&[(clock.after(10), &[0xf8])]).as_ref()).unwrap();
+            assert_eq!(loopback.pending_count(), 1);
+
+            clock.advance(10);
+            assert_eq!(loopback.pending_count(), 1);
+            loopback.poll();
+            assert_eq!(loopback.pending_count(), 0);
+        });
+        assert_eq!(loopback.sent(external), vec![packets(&[(1010, &[0xf8])])]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn shared_clock_is_not_advanced() {
+        let loopback = Loopback::with_clock(Arc::new(ManualClock::new(1000)));
+        loopback.advance(10);
+    }
+
+    #[test]
+    fn scheduled_receives_reach_input_ports() {
+        let loopback = Arc::new(Loopback::new());
+        with_backend(loopback.clone(), || {
+            let received = Arc::new(Mutex::new(Vec::new()));
+            let client = Client::new("client").unwrap();
+            let source = client.virtual_source("source").unwrap();
+            let callback_received = received.clone();
+            let input_port = client.input_port("port", move |packet_list| {
+                callback_received.lock().unwrap().push(packet_list.to_packet_list());
+            }).unwrap();
+            input_port.connect_source(&Source::from_index(0)).unwrap();
+
+            source.received(packets(&[(20, &[0x90, 0x40, 0x7f]), (40, &[0x80, 0x40, 0x00])]).as_ref()).unwrap();
+            loopback.advance(20);
+            assert_eq!(*received.lock().unwrap(), vec![packets(&[(20, &[0x90, 0x40, 0x7f])])]);
+
+            drop(source);
+            assert_eq!(loopback.pending_count(), 0);
+        });
+    }
+
+    #[test]
+    fn scheduling_reports_delivery_errors() {
+        let loopback = Arc::new(Loopback::new());
+        let external = loopback.add_source("external");
+        with_backend(loopback.clone(), || {
+            let client = Client::new("client").unwrap();
+            let output_port = client.output_port("port").unwrap();
+            let destination = client.virtual_destination("destination", |_| {}).unwrap();
+            let future = packets(&[(10, &[0xf8])]);
+
+            let not_a_destination = Destination { endpoint: Endpoint { object: destination.object.related(external) } };
+            assert_eq!(output_port.send(&not_a_destination, future.as_ref()), Err(Error::WrongEndpointType));
+            assert_eq!(loopback.pending_count(), 0);
+
+            output_port.send(&Destination::from_index(0), future.as_ref()).unwrap();
+            assert_eq!(loopback.pending_count(), 1);
+            drop(client);
+            assert_eq!(loopback.pending_count(), 0);
+
+            assert_eq!(output_port.send(&Destination::from_index(0), future.as_ref()), Err(Error::InvalidPort));
+            drop(destination);
+        });
+    }
+
+    #[test]
+    fn virtual_endpoints_are_notified() {
+        let loopback = Arc::new(Loopback::new());
+        with_backend(loopback, || {
+            let observer = Client::new_with_notification_subscribers("observer").unwrap();
+            let notifications = observer.notification_receiver();
+
+            let client = Client::new("client").unwrap();
+            let source = client.virtual_source("source").unwrap();
+            let source_object = source.object.clone();
+            let destination = client.virtual_destination("destination", |_| {}).unwrap();
+            let destination_object = destination.object.clone();
+            drop(source);
+            drop(client);
+            drop(destination);
+
+            let info = |child: &Object, child_type| AddedRemovedInfo {
+                parent: child.related(0),
+                parent_type: ObjectType::Other,
+                child: child.clone(),
+                child_type: child_type
+            };
+            assert_eq!(notifications.try_iter().collect::<Vec<_>>(), vec![
+                Notification::ObjectAdded(info(&source_object, ObjectType::Source)),
+                Notification::ObjectAdded(info(&destination_object, ObjectType::Destination)),
+                Notification::ObjectRemoved(info(&source_object, ObjectType::Source)),
+                Notification::ObjectRemoved(info(&destination_object, ObjectType::Destination)),
+            ]);
+        });
+    }
+}
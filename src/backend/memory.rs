@@ -0,0 +1,938 @@
+use sys::{
+    OSStatus, MIDIObjectRef, MIDIClientRef, MIDIPortRef, MIDIEndpointRef, MIDIDeviceRef, MIDIEntityRef, MIDIThruConnectionRef,
+    MIDIObjectType, MIDIUniqueID,
+    kMIDIObjectType_Device, kMIDIObjectType_Entity, kMIDIObjectType_Source, kMIDIObjectType_Destination,
+    kMIDIObjectType_ExternalDevice, kMIDIObjectType_ExternalEntity, kMIDIObjectType_ExternalSource,
+    kMIDIObjectType_ExternalDestination,
+    MIDINotification, MIDINotifyProc, MIDIReadProc
+};
+
+use std::collections::HashMap;
+use std::ptr;
+use std::sync::{Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread::{self, ThreadId};
+
+use backend::Backend;
+use error::{
+    kMIDIInvalidClient, kMIDIInvalidPort, kMIDIUnknownEndpoint, kMIDINoConnection,
+    kMIDIUnknownProperty, kMIDIWrongPropertyType, kMIDIWrongEndpointType, kMIDIObjectNotFound, kMIDIIDNotUnique
+};
+use packets::{PacketList, PacketListRef};
+use properties::PropertyKey;
+
+/// A backend that keeps all the MIDI objects in memory, for tests.
+///
+/// It behaves like the MIDI server for the objects created through the crate:
+/// packets received by a virtual source reach the input ports connected to it, and packets sent to
+/// a virtual destination reach its callback, both synchronously on the calling thread.
+/// The packets sent to any other destination are kept, and can be inspected with
+/// [sent](#method.sent).
+///
+/// External sources, destinations and devices can be added to simulate the ones in the system,
+/// as well as the entities of the devices and their sources and destinations.
+/// Every object gets a unique id, which is its reference. As in CoreMIDI, it can be changed,
+/// but not to the unique id of another object.
+/// Thru connections are kept with their params, but they don't route any packet.
+///
+/// As in CoreMIDI, disposing a client, a port or an endpoint waits for its callbacks running
+/// on other threads to return, so their reference constants can be freed right after.
+///
+pub struct MemoryBackend {
+    state: Mutex<State>,
+    // Signalled whenever a callback returns
+    callback_returned: Condvar,
+}
+
+#[derive(Clone, Copy)]
+struct RefCon(*mut ::libc::c_void);
+
+// The reference constants are only passed back to the callbacks, as CoreMIDI does.
+unsafe impl Send for RefCon {}
+
+enum Property {
+    String(String),
+    Integer(i32),
+}
+
+struct Port {
+    client: MIDIClientRef,
+    read_proc: MIDIReadProc,
+    ref_con: RefCon,
+    sources: Vec<MIDIEndpointRef>,
+}
+
+struct Endpoint {
+    client: Option<MIDIClientRef>,
+    entity: Option<MIDIEntityRef>,
+    read_proc: MIDIReadProc,
+    ref_con: RefCon,
+    sent: Vec<PacketList>,
+}
+
+struct Entity {
+    device: MIDIDeviceRef,
+    sources: Vec<MIDIEndpointRef>,
+    destinations: Vec<MIDIEndpointRef>,
+}
+
+struct ThruConnection {
+    persistent_owner_id: Option<String>,
+    params: Vec<u8>,
+}
+
+struct State {
+    next_ref: MIDIObjectRef,
+    clients: HashMap<MIDIClientRef, (MIDINotifyProc, RefCon)>,
+    ports: HashMap<MIDIPortRef, Port>,
+    endpoints: HashMap<MIDIEndpointRef, Endpoint>,
+    sources: Vec<MIDIEndpointRef>,
+    destinations: Vec<MIDIEndpointRef>,
+    devices: Vec<MIDIDeviceRef>,
+    external_devices: Vec<MIDIDeviceRef>,
+    device_entities: HashMap<MIDIDeviceRef, Vec<MIDIEntityRef>>,
+    entities: HashMap<MIDIEntityRef, Entity>,
+    thru_connections: HashMap<MIDIThruConnectionRef, ThruConnection>,
+    properties: HashMap<(MIDIObjectRef, PropertyKey), Property>,
+    unique_ids: HashMap<MIDIUniqueID, MIDIObjectRef>,
+    object_types: HashMap<MIDIObjectRef, MIDIObjectType>,
+    // The objects whose callbacks are being called, and the threads calling them
+    calling: Vec<(MIDIObjectRef, ThreadId)>,
+}
+
+// Marks a callback of an object as being called, until it is dropped
+struct Calling<'a> {
+    backend: &'a MemoryBackend,
+    object: MIDIObjectRef,
+}
+
+impl<'a> Drop for Calling<'a> {
+    fn drop(&mut self) {
+        // It can be dropped while unwinding from a panicking callback
+        let mut state = self.backend.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let current = thread::current().id();
+        if let Some(index) = state.calling.iter().position(|&calling| calling == (self.object, current)) {
+            state.calling.remove(index);
+        }
+        self.backend.callback_returned.notify_all();
+    }
+}
+
+impl State {
+    fn new_ref(&mut self) -> MIDIObjectRef {
+        // The refs are also the unique ids of new objects, so the ones set as the unique id of another object are skipped
+        self.next_ref += 1;
+        while self.unique_ids.contains_key(&(self.next_ref as MIDIUniqueID)) {
+            self.next_ref += 1;
+        }
+        self.next_ref
+    }
+
+    fn new_object(&mut self, name: &str) -> MIDIObjectRef {
+        let object = self.new_ref();
+        self.properties.insert((object, PropertyKey::Name), Property::String(name.to_string()));
+        self.set_property(object, PropertyKey::UniqueID, Property::Integer(object as i32));
+        object
+    }
+
+    fn new_typed_object(&mut self, name: &str, object_type: MIDIObjectType) -> MIDIObjectRef {
+        let object = self.new_object(name);
+        self.object_types.insert(object, object_type);
+        object
+    }
+
+    fn is_external(&self, object: MIDIObjectRef) -> bool {
+        match self.object_types.get(&object) {
+            Some(&object_type) => object_type == kMIDIObjectType_ExternalDevice || object_type == kMIDIObjectType_ExternalEntity,
+            None => false
+        }
+    }
+
+    fn new_entity(&mut self, device: MIDIDeviceRef, name: &str) -> MIDIEntityRef {
+        let object_type = if self.is_external(device) { kMIDIObjectType_ExternalEntity } else { kMIDIObjectType_Entity };
+        let entity = self.new_typed_object(name, object_type);
+        self.entities.insert(entity, Entity {
+            device: device,
+            sources: Vec::new(),
+            destinations: Vec::new(),
+        });
+        self.device_entities.entry(device).or_default().push(entity);
+        entity
+    }
+
+    fn new_endpoint(&mut self, client: Option<MIDIClientRef>, object_type: MIDIObjectType, name: &str, read_proc: MIDIReadProc, ref_con: RefCon) -> MIDIEndpointRef {
+        let endpoint = self.new_typed_object(name, object_type);
+        self.endpoints.insert(endpoint, Endpoint {
+            client: client,
+            entity: None,
+            read_proc: read_proc,
+            ref_con: ref_con,
+            sent: Vec::new(),
+        });
+        endpoint
+    }
+
+    // Sets a property, keeping the index of the unique ids up to date
+    fn set_property(&mut self, object: MIDIObjectRef, key: PropertyKey, property: Property) {
+        if key == PropertyKey::UniqueID {
+            self.remove_unique_id(object);
+            if let Property::Integer(unique_id) = property {
+                self.unique_ids.insert(unique_id, object);
+            }
+        }
+        self.properties.insert((object, key), property);
+    }
+
+    fn remove_unique_id(&mut self, object: MIDIObjectRef) {
+        if let Some(&Property::Integer(unique_id)) = self.properties.get(&(object, PropertyKey::UniqueID)) {
+            self.unique_ids.remove(&unique_id);
+        }
+    }
+
+    fn remove_properties(&mut self, object: MIDIObjectRef) {
+        self.remove_unique_id(object);
+        self.properties.retain(|&(property_object, _), _| property_object != object);
+    }
+
+    fn send_status(&self, port: MIDIPortRef, destination: MIDIEndpointRef) -> OSStatus {
+        if !self.ports.contains_key(&port) {
+            kMIDIInvalidPort
+        }
+        else if !self.destinations.contains(&destination) {
+            kMIDIWrongEndpointType
+        }
+        else { 0 }
+    }
+
+    fn received_status(&self, source: MIDIEndpointRef) -> OSStatus {
+        if self.sources.contains(&source) { 0 } else { kMIDIWrongEndpointType }
+    }
+
+    fn remove_endpoint(&mut self, endpoint: MIDIEndpointRef) {
+        self.endpoints.remove(&endpoint);
+        self.sources.retain(|source| *source != endpoint);
+        self.destinations.retain(|destination| *destination != endpoint);
+        for entity in self.entities.values_mut() {
+            entity.sources.retain(|source| *source != endpoint);
+            entity.destinations.retain(|destination| *destination != endpoint);
+        }
+        for port in self.ports.values_mut() {
+            port.sources.retain(|source| *source != endpoint);
+        }
+        self.remove_properties(endpoint);
+        self.object_types.remove(&endpoint);
+    }
+}
+
+impl MemoryBackend {
+    pub fn new() -> MemoryBackend {
+        MemoryBackend {
+            state: Mutex::new(State {
+                next_ref: 0,
+                clients: HashMap::new(),
+                ports: HashMap::new(),
+                endpoints: HashMap::new(),
+                sources: Vec::new(),
+                destinations: Vec::new(),
+                devices: Vec::new(),
+                external_devices: Vec::new(),
+                device_entities: HashMap::new(),
+                entities: HashMap::new(),
+                thru_connections: HashMap::new(),
+                properties: HashMap::new(),
+                unique_ids: HashMap::new(),
+                object_types: HashMap::new(),
+                calling: Vec::new(),
+            }),
+            callback_returned: Condvar::new(),
+        }
+    }
+
+    // Marks a callback of an object as being called from the current thread, while the state is locked
+    // so it can't be disposed in between.
+    fn calling<'a>(&'a self, state: &mut State, object: MIDIObjectRef) -> Calling<'a> {
+        state.calling.push((object, thread::current().id()));
+        Calling { backend: self, object: object }
+    }
+
+    // Waits for the callbacks of some objects being called from other threads to return.
+    // The ones called from the current thread are the ones disposing the objects, so they are not waited for.
+    fn wait_for_callbacks(&self, mut state: MutexGuard<'_, State>, objects: &[MIDIObjectRef]) {
+        let current = thread::current().id();
+        while state.calling.iter().any(|&(object, thread)| thread != current && objects.contains(&object)) {
+            state = self.callback_returned.wait(state).unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+
+    /// Add a source that is not owned by any client, like the ones from the devices in the system.
+    ///
+    /// Packets can be injected from it with [Backend::received](trait.Backend.html#tymethod.received).
+    ///
+    pub fn add_source(&self, name: &str) -> MIDIEndpointRef {
+        let mut state = self.state.lock().unwrap();
+        let source = state.new_endpoint(None, kMIDIObjectType_Source, name, None, RefCon(ptr::null_mut()));
+        state.sources.push(source);
+        source
+    }
+
+    /// Add a destination that is not owned by any client, like the ones from the devices in the system.
+    ///
+    /// The packets sent to it are kept, see [sent](#method.sent).
+    ///
+    pub fn add_destination(&self, name: &str) -> MIDIEndpointRef {
+        let mut state = self.state.lock().unwrap();
+        let destination = state.new_endpoint(None, kMIDIObjectType_Destination, name, None, RefCon(ptr::null_mut()));
+        state.destinations.push(destination);
+        destination
+    }
+
+    /// Add a device to the system.
+    ///
+    pub fn add_device(&self, name: &str) -> MIDIDeviceRef {
+        let mut state = self.state.lock().unwrap();
+        let device = state.new_typed_object(name, kMIDIObjectType_Device);
+        state.devices.push(device);
+        device
+    }
+
+    /// Add an entity to a device.
+    ///
+    pub fn add_entity(&self, device: MIDIDeviceRef, name: &str) -> MIDIEntityRef {
+        self.state.lock().unwrap().new_entity(device, name)
+    }
+
+    /// Add a source to an entity, which is also one of the sources in the system.
+    ///
+    pub fn add_entity_source(&self, entity: MIDIEntityRef, name: &str) -> MIDIEndpointRef {
+        let source = self.add_source(name);
+        let mut state = self.state.lock().unwrap();
+        state.endpoints.get_mut(&source).unwrap().entity = Some(entity);
+        state.entities.get_mut(&entity).expect("unknown entity").sources.push(source);
+        source
+    }
+
+    /// Add a destination to an entity, which is also one of the destinations in the system.
+    ///
+    pub fn add_entity_destination(&self, entity: MIDIEntityRef, name: &str) -> MIDIEndpointRef {
+        let destination = self.add_destination(name);
+        let mut state = self.state.lock().unwrap();
+        state.endpoints.get_mut(&destination).unwrap().entity = Some(entity);
+        state.entities.get_mut(&entity).expect("unknown entity").destinations.push(destination);
+        destination
+    }
+
+    /// Take the packet lists sent so far to a destination without a callback.
+    ///
+    pub fn sent(&self, destination: MIDIEndpointRef) -> Vec<PacketList> {
+        let mut state = self.state.lock().unwrap();
+        match state.endpoints.get_mut(&destination) {
+            Some(endpoint) => endpoint.sent.drain(..).collect(),
+            None => Vec::new()
+        }
+    }
+
+    /// Deliver a notification to all the clients.
+    ///
+    pub fn post_notification(&self, notification: &MIDINotification) {
+        let notify_procs = {
+            let mut state = self.state.lock().unwrap();
+            let clients = state.clients.iter()
+                .map(|(&client, &(notify_proc, ref_con))| (client, notify_proc, ref_con))
+                .collect::<Vec<_>>();
+            clients.into_iter()
+                .map(|(client, notify_proc, ref_con)| (self.calling(&mut state, client), notify_proc, ref_con))
+                .collect::<Vec<_>>()
+        };
+        for (_calling, notify_proc, ref_con) in notify_procs {
+            if let Some(notify_proc) = notify_proc {
+                notify_proc(notification, ref_con.0);
+            }
+        }
+    }
+
+    /// The status a [send](trait.Backend.html#tymethod.send) through a port to a destination would return,
+    /// without sending anything.
+    ///
+    pub(crate) fn send_status(&self, port: MIDIPortRef, destination: MIDIEndpointRef) -> OSStatus {
+        self.state.lock().unwrap().send_status(port, destination)
+    }
+
+    /// The status a [received](trait.Backend.html#tymethod.received) by a source would return,
+    /// without distributing anything.
+    ///
+    pub(crate) fn received_status(&self, source: MIDIEndpointRef) -> OSStatus {
+        self.state.lock().unwrap().received_status(source)
+    }
+
+    fn deliver(callbacks: Vec<(Calling, MIDIReadProc, RefCon)>, packet_list: PacketListRef) {
+        for (_calling, read_proc, ref_con) in callbacks {
+            if let Some(read_proc) = read_proc {
+                read_proc(packet_list.as_ptr(), ref_con.0, ptr::null_mut());
+            }
+        }
+    }
+}
+
+impl Default for MemoryBackend {
+    fn default() -> Self {
+        MemoryBackend::new()
+    }
+}
+
+impl Backend for MemoryBackend {
+    unsafe fn client_create(&self, name: &str, notify_proc: MIDINotifyProc, notify_ref_con: *mut ::libc::c_void) -> Result<MIDIClientRef, OSStatus> {
+        let mut state = self.state.lock().unwrap();
+        let client = state.new_object(name);
+        state.clients.insert(client, (notify_proc, RefCon(notify_ref_con)));
+        Ok(client)
+    }
+
+    fn client_dispose(&self, client: MIDIClientRef) -> OSStatus {
+        let mut state = self.state.lock().unwrap();
+        if state.clients.remove(&client).is_none() {
+            return kMIDIInvalidClient;
+        }
+        let mut disposed = vec![client];
+        disposed.extend(state.ports.iter().filter(|&(_, port)| port.client == client).map(|(port, _)| *port));
+        state.ports.retain(|_, port| port.client != client);
+        let endpoints = state.endpoints.iter()
+            .filter(|&(_, endpoint)| endpoint.client == Some(client))
+            .map(|(endpoint, _)| *endpoint)
+            .collect::<Vec<_>>();
+        for &endpoint in &endpoints {
+            state.remove_endpoint(endpoint);
+        }
+        disposed.extend(endpoints);
+        state.remove_properties(client);
+        self.wait_for_callbacks(state, &disposed);
+        0
+    }
+
+    fn output_port_create(&self, client: MIDIClientRef, name: &str) -> Result<MIDIPortRef, OSStatus> {
+        unsafe { self.input_port_create(client, name, None, ptr::null_mut()) }
+    }
+
+    unsafe fn input_port_create(&self, client: MIDIClientRef, name: &str, read_proc: MIDIReadProc, ref_con: *mut ::libc::c_void) -> Result<MIDIPortRef, OSStatus> {
+        let mut state = self.state.lock().unwrap();
+        if !state.clients.contains_key(&client) {
+            return Err(kMIDIInvalidClient);
+        }
+        let port = state.new_object(name);
+        state.ports.insert(port, Port {
+            client: client,
+            read_proc: read_proc,
+            ref_con: RefCon(ref_con),
+            sources: Vec::new(),
+        });
+        Ok(port)
+    }
+
+    fn port_dispose(&self, port: MIDIPortRef) -> OSStatus {
+        let mut state = self.state.lock().unwrap();
+        if state.ports.remove(&port).is_none() {
+            return kMIDIInvalidPort;
+        }
+        state.remove_properties(port);
+        self.wait_for_callbacks(state, &[port]);
+        0
+    }
+
+    fn port_connect_source(&self, port: MIDIPortRef, source: MIDIEndpointRef) -> OSStatus {
+        let mut state = self.state.lock().unwrap();
+        if !state.sources.contains(&source) {
+            return kMIDIUnknownEndpoint;
+        }
+        match state.ports.get_mut(&port) {
+            Some(ref mut port) if port.read_proc.is_some() => {
+                if !port.sources.contains(&source) {
+                    port.sources.push(source);
+                }
+                0
+            },
+            _ => kMIDIInvalidPort
+        }
+    }
+
+    fn port_disconnect_source(&self, port: MIDIPortRef, source: MIDIEndpointRef) -> OSStatus {
+        let mut state = self.state.lock().unwrap();
+        match state.ports.get_mut(&port) {
+            Some(port) => {
+                let connections = port.sources.len();
+                port.sources.retain(|connected| *connected != source);
+                if port.sources.len() < connections { 0 } else { kMIDINoConnection }
+            },
+            None => kMIDIInvalidPort
+        }
+    }
+
+    fn source_create(&self, client: MIDIClientRef, name: &str) -> Result<MIDIEndpointRef, OSStatus> {
+        let mut state = self.state.lock().unwrap();
+        if !state.clients.contains_key(&client) {
+            return Err(kMIDIInvalidClient);
+        }
+        let source = state.new_endpoint(Some(client), kMIDIObjectType_Source, name, None, RefCon(ptr::null_mut()));
+        state.sources.push(source);
+        Ok(source)
+    }
+
+    unsafe fn destination_create(&self, client: MIDIClientRef, name: &str, read_proc: MIDIReadProc, ref_con: *mut ::libc::c_void) -> Result<MIDIEndpointRef, OSStatus> {
+        let mut state = self.state.lock().unwrap();
+        if !state.clients.contains_key(&client) {
+            return Err(kMIDIInvalidClient);
+        }
+        let destination = state.new_endpoint(Some(client), kMIDIObjectType_Destination, name, read_proc, RefCon(ref_con));
+        state.destinations.push(destination);
+        Ok(destination)
+    }
+
+    fn endpoint_dispose(&self, endpoint: MIDIEndpointRef) -> OSStatus {
+        let mut state = self.state.lock().unwrap();
+        if !state.endpoints.contains_key(&endpoint) {
+            return kMIDIUnknownEndpoint;
+        }
+        state.remove_endpoint(endpoint);
+        self.wait_for_callbacks(state, &[endpoint]);
+        0
+    }
+
+    fn send(&self, port: MIDIPortRef, destination: MIDIEndpointRef, packet_list: PacketListRef) -> OSStatus {
+        let callbacks = {
+            let mut state = self.state.lock().unwrap();
+            let status = state.send_status(port, destination);
+            if status != 0 {
+                return status;
+            }
+            let endpoint = state.endpoints.get_mut(&destination).unwrap();
+            if endpoint.read_proc.is_none() {
+                endpoint.sent.push(packet_list.to_packet_list());
+                return 0;
+            }
+            let callback = (endpoint.read_proc, endpoint.ref_con);
+            vec![(self.calling(&mut state, destination), callback.0, callback.1)]
+        };
+        Self::deliver(callbacks, packet_list);
+        0
+    }
+
+    fn received(&self, source: MIDIEndpointRef, packet_list: PacketListRef) -> OSStatus {
+        let callbacks = {
+            let mut state = self.state.lock().unwrap();
+            let status = state.received_status(source);
+            if status != 0 {
+                return status;
+            }
+            let ports = state.ports.iter()
+                .filter(|&(_, port)| port.sources.contains(&source))
+                .map(|(&port_ref, port)| (port_ref, port.read_proc, port.ref_con))
+                .collect::<Vec<_>>();
+            ports.into_iter()
+                .map(|(port, read_proc, ref_con)| (self.calling(&mut state, port), read_proc, ref_con))
+                .collect::<Vec<_>>()
+        };
+        Self::deliver(callbacks, packet_list);
+        0
+    }
+
+    fn flush_output(&self, destination: MIDIEndpointRef) -> OSStatus {
+        let state = self.state.lock().unwrap();
+        if destination == 0 || state.destinations.contains(&destination) { 0 } else { kMIDIUnknownEndpoint }
+    }
+
+    fn restart(&self) -> OSStatus {
+        0
+    }
+
+    fn number_of_sources(&self) -> usize {
+        self.state.lock().unwrap().sources.len()
+    }
+
+    fn source(&self, index: usize) -> MIDIEndpointRef {
+        self.state.lock().unwrap().sources.get(index).cloned().unwrap_or(0)
+    }
+
+    fn number_of_destinations(&self) -> usize {
+        self.state.lock().unwrap().destinations.len()
+    }
+
+    fn destination(&self, index: usize) -> MIDIEndpointRef {
+        self.state.lock().unwrap().destinations.get(index).cloned().unwrap_or(0)
+    }
+
+    fn number_of_devices(&self) -> usize {
+        self.state.lock().unwrap().devices.len()
+    }
+
+    fn device(&self, index: usize) -> MIDIDeviceRef {
+        self.state.lock().unwrap().devices.get(index).cloned().unwrap_or(0)
+    }
+
+    fn device_number_of_entities(&self, device: MIDIDeviceRef) -> usize {
+        self.state.lock().unwrap().device_entities.get(&device).map_or(0, |entities| entities.len())
+    }
+
+    fn device_entity(&self, device: MIDIDeviceRef, index: usize) -> MIDIEntityRef {
+        let state = self.state.lock().unwrap();
+        state.device_entities.get(&device).and_then(|entities| entities.get(index)).cloned().unwrap_or(0)
+    }
+
+    fn entity_number_of_sources(&self, entity: MIDIEntityRef) -> usize {
+        self.state.lock().unwrap().entities.get(&entity).map_or(0, |entity| entity.sources.len())
+    }
+
+    fn entity_source(&self, entity: MIDIEntityRef, index: usize) -> MIDIEndpointRef {
+        let state = self.state.lock().unwrap();
+        state.entities.get(&entity).and_then(|entity| entity.sources.get(index)).cloned().unwrap_or(0)
+    }
+
+    fn entity_number_of_destinations(&self, entity: MIDIEntityRef) -> usize {
+        self.state.lock().unwrap().entities.get(&entity).map_or(0, |entity| entity.destinations.len())
+    }
+
+    fn entity_destination(&self, entity: MIDIEntityRef, index: usize) -> MIDIEndpointRef {
+        let state = self.state.lock().unwrap();
+        state.entities.get(&entity).and_then(|entity| entity.destinations.get(index)).cloned().unwrap_or(0)
+    }
+
+    fn entity_device(&self, entity: MIDIEntityRef) -> Result<MIDIDeviceRef, OSStatus> {
+        let state = self.state.lock().unwrap();
+        state.entities.get(&entity).map(|entity| entity.device).ok_or(kMIDIObjectNotFound)
+    }
+
+    fn endpoint_entity(&self, endpoint: MIDIEndpointRef) -> Result<MIDIEntityRef, OSStatus> {
+        let state = self.state.lock().unwrap();
+        match state.endpoints.get(&endpoint) {
+            // Like CoreMIDI, virtual endpoints don't have an entity
+            Some(endpoint) => endpoint.entity.ok_or(kMIDIObjectNotFound),
+            None => Err(kMIDIUnknownEndpoint)
+        }
+    }
+
+    fn device_add_entity(&self, device: MIDIDeviceRef, name: &str, embedded: bool, sources: usize, destinations: usize) -> Result<MIDIEntityRef, OSStatus> {
+        let mut state = self.state.lock().unwrap();
+        let entity = state.new_entity(device, name);
+        let (source_type, destination_type) = if state.is_external(device) {
+            (kMIDIObjectType_ExternalSource, kMIDIObjectType_ExternalDestination)
+        } else {
+            (kMIDIObjectType_Source, kMIDIObjectType_Destination)
+        };
+        state.properties.insert((entity, PropertyKey::IsEmbeddedEntity), Property::Integer(embedded as i32));
+        // Unlike the ones added with add_entity_source, these are not listed with the sources in the system,
+        // as it happens with the endpoints of the external devices.
+        for _ in 0..sources {
+            let source = state.new_endpoint(None, source_type, name, None, RefCon(ptr::null_mut()));
+            state.endpoints.get_mut(&source).unwrap().entity = Some(entity);
+            state.entities.get_mut(&entity).unwrap().sources.push(source);
+        }
+        for _ in 0..destinations {
+            let destination = state.new_endpoint(None, destination_type, name, None, RefCon(ptr::null_mut()));
+            state.endpoints.get_mut(&destination).unwrap().entity = Some(entity);
+            state.entities.get_mut(&entity).unwrap().destinations.push(destination);
+        }
+        Ok(entity)
+    }
+
+    fn number_of_external_devices(&self) -> usize {
+        self.state.lock().unwrap().external_devices.len()
+    }
+
+    fn external_device(&self, index: usize) -> MIDIDeviceRef {
+        self.state.lock().unwrap().external_devices.get(index).cloned().unwrap_or(0)
+    }
+
+    fn external_device_create(&self, name: &str, manufacturer: &str, model: &str) -> Result<MIDIDeviceRef, OSStatus> {
+        let mut state = self.state.lock().unwrap();
+        let device = state.new_typed_object(name, kMIDIObjectType_ExternalDevice);
+        state.properties.insert((device, PropertyKey::Manufacturer), Property::String(manufacturer.to_string()));
+        state.properties.insert((device, PropertyKey::Model), Property::String(model.to_string()));
+        Ok(device)
+    }
+
+    fn setup_add_external_device(&self, device: MIDIDeviceRef) -> OSStatus {
+        let mut state = self.state.lock().unwrap();
+        if !state.external_devices.contains(&device) {
+            state.external_devices.push(device);
+        }
+        0
+    }
+
+    fn setup_remove_external_device(&self, device: MIDIDeviceRef) -> OSStatus {
+        let mut state = self.state.lock().unwrap();
+        let count = state.external_devices.len();
+        state.external_devices.retain(|external_device| *external_device != device);
+        if state.external_devices.len() < count { 0 } else { kMIDIObjectNotFound }
+    }
+
+    fn get_string_property(&self, object: MIDIObjectRef, key: &PropertyKey) -> Result<String, OSStatus> {
+        let state = self.state.lock().unwrap();
+        let property = state.properties.get(&(object, key.clone())).or_else(|| {
+            // Like CoreMIDI, the display name defaults to the name
+            if *key == PropertyKey::DisplayName { state.properties.get(&(object, PropertyKey::Name)) } else { None }
+        });
+        match property {
+            Some(Property::String(value)) => Ok(value.clone()),
+            Some(Property::Integer(_)) => Err(kMIDIWrongPropertyType),
+            None => Err(kMIDIUnknownProperty)
+        }
+    }
+
+    fn set_string_property(&self, object: MIDIObjectRef, key: &PropertyKey, value: &str) -> OSStatus {
+        let mut state = self.state.lock().unwrap();
+        state.set_property(object, key.clone(), Property::String(value.to_string()));
+        0
+    }
+
+    fn get_integer_property(&self, object: MIDIObjectRef, key: &PropertyKey) -> Result<i32, OSStatus> {
+        let state = self.state.lock().unwrap();
+        match state.properties.get(&(object, key.clone())) {
+            Some(Property::Integer(value)) => Ok(*value),
+            Some(Property::String(_)) => Err(kMIDIWrongPropertyType),
+            None => Err(kMIDIUnknownProperty)
+        }
+    }
+
+    fn set_integer_property(&self, object: MIDIObjectRef, key: &PropertyKey, value: i32) -> OSStatus {
+        let mut state = self.state.lock().unwrap();
+        if *key == PropertyKey::UniqueID && matches!(state.unique_ids.get(&value), Some(&other) if other != object) {
+            return kMIDIIDNotUnique;
+        }
+        state.set_property(object, key.clone(), Property::Integer(value));
+        0
+    }
+
+    fn object_find_by_unique_id(&self, unique_id: MIDIUniqueID) -> Result<(MIDIObjectRef, MIDIObjectType), OSStatus> {
+        let state = self.state.lock().unwrap();
+        let object = *state.unique_ids.get(&unique_id).ok_or(kMIDIObjectNotFound)?;
+        match state.object_types.get(&object) {
+            Some(&object_type) => Ok((object, object_type)),
+            None => Err(kMIDIObjectNotFound)
+        }
+    }
+
+    fn thru_connection_create(&self, persistent_owner_id: Option<&str>, params: &[u8]) -> Result<MIDIThruConnectionRef, OSStatus> {
+        let mut state = self.state.lock().unwrap();
+        let connection = state.new_ref();
+        state.thru_connections.insert(connection, ThruConnection {
+            persistent_owner_id: persistent_owner_id.map(|owner_id| owner_id.to_string()),
+            params: params.to_vec(),
+        });
+        Ok(connection)
+    }
+
+    fn thru_connection_dispose(&self, connection: MIDIThruConnectionRef) -> OSStatus {
+        let mut state = self.state.lock().unwrap();
+        if state.thru_connections.remove(&connection).is_none() {
+            return kMIDIObjectNotFound;
+        }
+        state.remove_properties(connection);
+        0
+    }
+
+    fn thru_connection_get_params(&self, connection: MIDIThruConnectionRef) -> Result<Vec<u8>, OSStatus> {
+        let state = self.state.lock().unwrap();
+        state.thru_connections.get(&connection)
+            .map(|thru_connection| thru_connection.params.clone())
+            .ok_or(kMIDIObjectNotFound)
+    }
+
+    fn thru_connection_set_params(&self, connection: MIDIThruConnectionRef, params: &[u8]) -> OSStatus {
+        let mut state = self.state.lock().unwrap();
+        match state.thru_connections.get_mut(&connection) {
+            Some(thru_connection) => {
+                thru_connection.params = params.to_vec();
+                0
+            },
+            None => kMIDIObjectNotFound
+        }
+    }
+
+    fn thru_connection_find(&self, persistent_owner_id: &str) -> Result<Vec<MIDIThruConnectionRef>, OSStatus> {
+        let state = self.state.lock().unwrap();
+        let mut connections = state.thru_connections.iter()
+            .filter(|&(_, thru_connection)| thru_connection.persistent_owner_id.as_deref() == Some(persistent_owner_id))
+            .map(|(connection, _)| *connection)
+            .collect::<Vec<_>>();
+        connections.sort();
+        Ok(connections)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sys::{MIDINotification, MIDINotificationMessageID, kMIDIMsgSetupChanged};
+
+    use std::sync::{Arc, Mutex};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    use backend::with_backend;
+    use backend::memory::MemoryBackend;
+    use {Client, Destination, Destinations, Device, Devices, Error, Notification, PacketBuffer, Properties,
+         PropertyGetter, PropertySetter, Source, Sources, TimestampedMessage};
+
+    fn packets(timestamp: u64, data: &[u8]) -> PacketBuffer<::packets::DynStorage> {
+        let mut packet_buffer = PacketBuffer::dyn();
+        packet_buffer.push_packet(timestamp, data);
+        packet_buffer
+    }
+
+    #[test]
+    fn virtual_source_reaches_connected_input_ports() {
+        let backend = Arc::new(MemoryBackend::new());
+        with_backend(backend, || {
+            let received = Arc::new(Mutex::new(Vec::new()));
+            let client = Client::new("client").unwrap();
+            let source = client.virtual_source("source").unwrap();
+            let callback_received = received.clone();
+            let input_port = client.input_port("port", move |packet_list| {
+                callback_received.lock().unwrap().push(packet_list.to_packet_list());
+            }).unwrap();
+
+            source.received(packets(1, &[0x90, 0x40, 0x7f]).as_ref()).unwrap();
+            assert!(received.lock().unwrap().is_empty());
+
+            input_port.connect_source(&Source::from_index(0)).unwrap();
+            source.received(packets(2, &[0x80, 0x40, 0x00]).as_ref()).unwrap();
+            input_port.disconnect_source(&Source::from_index(0)).unwrap();
+            source.received(packets(3, &[0xf8]).as_ref()).unwrap();
+
+            assert_eq!(*received.lock().unwrap(), vec![packets(2, &[0x80, 0x40, 0x00]).as_ref().to_packet_list()]);
+            assert_eq!(input_port.disconnect_source(&Source::from_index(0)), Err(Error::NoConnection));
+        });
+    }
+
+    #[test]
+    fn output_port_reaches_destinations() {
+        let backend = Arc::new(MemoryBackend::new());
+        let external = backend.add_destination("external");
+        with_backend(backend.clone(), || {
+            let received = Arc::new(Mutex::new(Vec::new()));
+            let client = Client::new("client").unwrap();
+            let callback_received = received.clone();
+            let _destination = client.virtual_destination("destination", move |packet_list| {
+                callback_received.lock().unwrap().push(packet_list.to_packet_list());
+            }).unwrap();
+            let output_port = client.output_port("port").unwrap();
+
+            assert_eq!(Destinations::count(), 2);
+            output_port.send(&Destination::from_index(0), packets(1, &[0xfa]).as_ref()).unwrap();
+            output_port.send(&Destination::from_index(1), packets(2, &[0xfc]).as_ref()).unwrap();
+
+            assert_eq!(*received.lock().unwrap(), vec![packets(2, &[0xfc]).as_ref().to_packet_list()]);
+        });
+        assert_eq!(backend.sent(external), vec![packets(1, &[0xfa]).as_ref().to_packet_list()]);
+        assert!(backend.sent(external).is_empty());
+    }
+
+    #[test]
+    fn receivers_get_the_packets() {
+        let backend = Arc::new(MemoryBackend::new());
+        with_backend(backend, || {
+            let client = Client::new("client").unwrap();
+            let source = client.virtual_source("source").unwrap();
+            let (input_port, from_source) = client.input_port_with_receiver("port").unwrap();
+            input_port.connect_source(&Source::from_index(0)).unwrap();
+            let (_destination, from_port) = client.virtual_destination_with_receiver_capacity("destination", 0).unwrap();
+            let output_port = client.output_port("port").unwrap();
+
+            source.received(packets(1, &[0x90, 0x40, 0x7f]).as_ref()).unwrap();
+            output_port.send(&Destination::from_index(0), packets(2, &[0xfa]).as_ref()).unwrap();
+            output_port.send(&Destination::from_index(0), packets(3, &[0xfc]).as_ref()).unwrap();
+
+            let message = |timestamp, data: &[u8]| TimestampedMessage { timestamp: timestamp, data: data.to_vec() };
+            assert_eq!(from_source.try_iter().collect::<Vec<_>>(), vec![message(1, &[0x90, 0x40, 0x7f])]);
+            assert_eq!(from_port.try_iter().collect::<Vec<_>>(), vec![message(2, &[0xfa])]);
+            assert_eq!(from_port.overflow_count(), 1);
+        });
+    }
+
+    #[test]
+    fn enumeration_and_properties() {
+        let backend = Arc::new(MemoryBackend::new());
+        backend.add_source("external source");
+        backend.add_device("device");
+        with_backend(backend, || {
+            let client = Client::new("client").unwrap();
+            let source = client.virtual_source("virtual source").unwrap();
+
+            let names = Sources.into_iter().map(|source| source.display_name()).collect::<Vec<_>>();
+            assert_eq!(names, vec![Some("external source".to_string()), Some("virtual source".to_string())]);
+            assert_eq!(Devices::count(), 1);
+            assert_eq!(Device::from_index(0).name(), Some("device".to_string()));
+
+            source.set_property_integer("custom", 42).unwrap();
+            assert_eq!(source.get_property_integer("custom"), Ok(42));
+            assert_eq!(source.get_property_string("custom"), Err(Error::WrongPropertyType));
+            assert_eq!(source.get_property_boolean("missing"), Err(Error::UnknownProperty));
+
+            Properties::model().set_value(&source, "model").unwrap();
+            let model: String = Properties::model().value_from(&source).unwrap();
+            assert_eq!(model, "model");
+        });
+    }
+
+    #[test]
+    fn disposing_waits_for_the_callbacks() {
+        let backend = Arc::new(MemoryBackend::new());
+        with_backend(backend, || {
+            let client = Client::new("client").unwrap();
+            let (started_sender, started) = mpsc::channel();
+            let finished = Arc::new(AtomicBool::new(false));
+            let callback_finished = finished.clone();
+            let virtual_destination = client.virtual_destination("destination", move |_| {
+                started_sender.send(()).unwrap();
+                thread::sleep(Duration::from_millis(50));
+                callback_finished.store(true, Ordering::SeqCst);
+            }).unwrap();
+            let output_port = client.output_port("port").unwrap();
+            let destination = Destination::from_index(0);
+            let sender = thread::spawn(move || {
+                output_port.send(&destination, packets(1, &[0xf8]).as_ref()).unwrap();
+            });
+
+            started.recv().unwrap();
+            drop(virtual_destination);
+            assert!(finished.load(Ordering::SeqCst));
+            sender.join().unwrap();
+        });
+    }
+
+    #[test]
+    fn notifications_reach_clients() {
+        let backend = Arc::new(MemoryBackend::new());
+        with_backend(backend.clone(), || {
+            let received = Arc::new(Mutex::new(Vec::new()));
+            let callback_received = received.clone();
+            let _client = Client::new_with_notifications("client", move |notification| {
+                callback_received.lock().unwrap().push(notification.clone());
+            }).unwrap();
+
+            backend.post_notification(&MIDINotification {
+                messageID: kMIDIMsgSetupChanged as MIDINotificationMessageID,
+                messageSize: 8
+            });
+
+            assert_eq!(*received.lock().unwrap(), vec![Notification::SetupChanged]);
+        });
+    }
+
+    #[test]
+    fn notifications_reach_the_subscribers() {
+        let backend = Arc::new(MemoryBackend::new());
+        with_backend(backend.clone(), || {
+            let has_notify_proc = || backend.state.lock().unwrap().clients.values().any(|client| client.0.is_some());
+            let client = Client::new("client").unwrap();
+            assert!(!has_notify_proc());
+            assert!(client.notification_receiver().recv().is_err());
+
+            let subscribed = Client::new_with_notification_subscribers("subscribed").unwrap();
+            assert!(has_notify_proc());
+            let notifications = subscribed.notification_receiver();
+            backend.post_notification(&MIDINotification {
+                messageID: kMIDIMsgSetupChanged as MIDINotificationMessageID,
+                messageSize: 8
+            });
+            assert_eq!(notifications.try_iter().collect::<Vec<_>>(), vec![Notification::SetupChanged]);
+        });
+    }
+}
@@ -0,0 +1,286 @@
+use sys::{
+    OSStatus, MIDIObjectRef, MIDIClientRef, MIDIPortRef, MIDIEndpointRef, MIDIDeviceRef, MIDIEntityRef,
+    MIDIThruConnectionRef, MIDINotifyProc, MIDIObjectType, MIDIUniqueID, MIDIReadProc
+};
+
+use std::cell::RefCell;
+use std::sync::{Arc, OnceLock};
+
+use PacketListRef;
+use properties::PropertyKey;
+
+#[cfg(target_os = "macos")]
+mod coremidi;
+pub mod memory;
+pub mod loopback;
+
+#[cfg(target_os = "macos")]
+pub use self::coremidi::CoreMidiBackend;
+
+/// The operations of the MIDI server used by the rest of the crate.
+///
+/// By default they are implemented by [CoreMidiBackend](struct.CoreMidiBackend.html) on macOS,
+/// and by an empty [MemoryBackend](struct.MemoryBackend.html) on the other platforms, where CoreMIDI
+/// is not available. They can be replaced, for example by a [MemoryBackend](struct.MemoryBackend.html)
+/// to run tests without the MIDI server. See [with_backend](fn.with_backend.html).
+///
+/// The callbacks and their reference constants are passed through as in CoreMIDI,
+/// and the errors are the CoreMIDI status codes.
+///
+/// # Safety
+///
+/// The methods taking raw pointers are unsafe: the reference constants must stay valid while the
+/// callbacks can be called, which is until the objects are disposed.
+///
+pub trait Backend: Send + Sync {
+    /// See [MIDIClientCreate](https://developer.apple.com/reference/coremidi/1495360-midiclientcreate).
+    ///
+    /// # Safety
+    ///
+    /// `notify_ref_con` is passed to `notify_proc` with every notification, so it must be valid for it
+    /// until [client_dispose](#tymethod.client_dispose) returns. Implementations must not call the
+    /// `notify_proc` from other threads after that.
+    unsafe fn client_create(&self, name: &str, notify_proc: MIDINotifyProc, notify_ref_con: *mut ::libc::c_void) -> Result<MIDIClientRef, OSStatus>;
+
+    /// See [MIDIClientDispose](https://developer.apple.com/reference/coremidi/1494259-midiclientdispose).
+    fn client_dispose(&self, client: MIDIClientRef) -> OSStatus;
+
+    /// See [MIDIOutputPortCreate](https://developer.apple.com/reference/coremidi/1495166-midioutputportcreate).
+    fn output_port_create(&self, client: MIDIClientRef, name: &str) -> Result<MIDIPortRef, OSStatus>;
+
+    /// See [MIDIInputPortCreate](https://developer.apple.com/reference/coremidi/1495225-midiinputportcreate).
+    ///
+    /// # Safety
+    ///
+    /// `ref_con` is passed to `read_proc` with every packet list received, so it must be valid for it until
+    /// [port_dispose](#tymethod.port_dispose), or the disposal of the client, returns.
+    /// Implementations must not call the `read_proc` from other threads after that.
+    unsafe fn input_port_create(&self, client: MIDIClientRef, name: &str, read_proc: MIDIReadProc, ref_con: *mut ::libc::c_void) -> Result<MIDIPortRef, OSStatus>;
+
+    /// See [MIDIPortDispose](https://developer.apple.com/reference/coremidi/1494877-midiportdispose).
+    fn port_dispose(&self, port: MIDIPortRef) -> OSStatus;
+
+    /// See [MIDIPortConnectSource](https://developer.apple.com/reference/coremidi/1495213-midiportconnectsource).
+    fn port_connect_source(&self, port: MIDIPortRef, source: MIDIEndpointRef) -> OSStatus;
+
+    /// See [MIDIPortDisconnectSource](https://developer.apple.com/reference/coremidi/1494953-midiportdisconnectsource).
+    fn port_disconnect_source(&self, port: MIDIPortRef, source: MIDIEndpointRef) -> OSStatus;
+
+    /// See [MIDISourceCreate](https://developer.apple.com/reference/coremidi/1495212-midisourcecreate).
+    fn source_create(&self, client: MIDIClientRef, name: &str) -> Result<MIDIEndpointRef, OSStatus>;
+
+    /// See [MIDIDestinationCreate](https://developer.apple.com/reference/coremidi/1495347-mididestinationcreate).
+    ///
+    /// # Safety
+    ///
+    /// `ref_con` is passed to `read_proc` with every packet list sent to the destination, so it must be valid
+    /// for it until [endpoint_dispose](#tymethod.endpoint_dispose), or the disposal of the client, returns.
+    /// Implementations must not call the `read_proc` from other threads after that.
+    unsafe fn destination_create(&self, client: MIDIClientRef, name: &str, read_proc: MIDIReadProc, ref_con: *mut ::libc::c_void) -> Result<MIDIEndpointRef, OSStatus>;
+
+    /// See [MIDIEndpointDispose](https://developer.apple.com/reference/coremidi/1495134-midiendpointdispose).
+    fn endpoint_dispose(&self, endpoint: MIDIEndpointRef) -> OSStatus;
+
+    /// See [MIDISend](https://developer.apple.com/reference/coremidi/1495289-midisend).
+    fn send(&self, port: MIDIPortRef, destination: MIDIEndpointRef, packet_list: PacketListRef) -> OSStatus;
+
+    /// See [MIDIReceived](https://developer.apple.com/reference/coremidi/1495276-midireceived).
+    fn received(&self, source: MIDIEndpointRef, packet_list: PacketListRef) -> OSStatus;
+
+    /// See [MIDIFlushOutput](https://developer.apple.com/reference/coremidi/1495312-midiflushoutput).
+    fn flush_output(&self, destination: MIDIEndpointRef) -> OSStatus;
+
+    /// See [MIDIRestart](https://developer.apple.com/reference/coremidi/1495146-midirestart).
+    fn restart(&self) -> OSStatus;
+
+    /// See [MIDIGetNumberOfSources](https://developer.apple.com/reference/coremidi/1495116-midigetnumberofsources).
+    fn number_of_sources(&self) -> usize;
+
+    /// See [MIDIGetSource](https://developer.apple.com/reference/coremidi/1495168-midigetsource).
+    fn source(&self, index: usize) -> MIDIEndpointRef;
+
+    /// See [MIDIGetNumberOfDestinations](https://developer.apple.com/reference/coremidi/1495309-midigetnumberofdestinations).
+    fn number_of_destinations(&self) -> usize;
+
+    /// See [MIDIGetDestination](https://developer.apple.com/reference/coremidi/1495108-midigetdestination).
+    fn destination(&self, index: usize) -> MIDIEndpointRef;
+
+    /// See [MIDIGetNumberOfDevices](https://developer.apple.com/reference/coremidi/1495164-midigetnumberofdevices).
+    fn number_of_devices(&self) -> usize;
+
+    /// See [MIDIGetDevice](https://developer.apple.com/reference/coremidi/1494834-midigetdevice).
+    fn device(&self, index: usize) -> MIDIDeviceRef;
+
+    /// See [MIDIDeviceGetNumberOfEntities](https://developer.apple.com/reference/coremidi/mididevicegetnumberofentities).
+    fn device_number_of_entities(&self, device: MIDIDeviceRef) -> usize;
+
+    /// See [MIDIDeviceGetEntity](https://developer.apple.com/reference/coremidi/mididevicegetentity).
+    fn device_entity(&self, device: MIDIDeviceRef, index: usize) -> MIDIEntityRef;
+
+    /// See [MIDIEntityGetNumberOfSources](https://developer.apple.com/reference/coremidi/midientitygetnumberofsources).
+    fn entity_number_of_sources(&self, entity: MIDIEntityRef) -> usize;
+
+    /// See [MIDIEntityGetSource](https://developer.apple.com/reference/coremidi/midientitygetsource).
+    fn entity_source(&self, entity: MIDIEntityRef, index: usize) -> MIDIEndpointRef;
+
+    /// See [MIDIEntityGetNumberOfDestinations](https://developer.apple.com/reference/coremidi/midientitygetnumberofdestinations).
+    fn entity_number_of_destinations(&self, entity: MIDIEntityRef) -> usize;
+
+    /// See [MIDIEntityGetDestination](https://developer.apple.com/reference/coremidi/midientitygetdestination).
+    fn entity_destination(&self, entity: MIDIEntityRef, index: usize) -> MIDIEndpointRef;
+
+    /// See [MIDIEntityGetDevice](https://developer.apple.com/reference/coremidi/midientitygetdevice).
+    fn entity_device(&self, entity: MIDIEntityRef) -> Result<MIDIDeviceRef, OSStatus>;
+
+    /// See [MIDIEndpointGetEntity](https://developer.apple.com/reference/coremidi/midiendpointgetentity).
+    fn endpoint_entity(&self, endpoint: MIDIEndpointRef) -> Result<MIDIEntityRef, OSStatus>;
+
+    /// See [MIDIDeviceAddEntity](https://developer.apple.com/reference/coremidi/midideviceaddentity).
+    fn device_add_entity(&self, device: MIDIDeviceRef, name: &str, embedded: bool, sources: usize, destinations: usize) -> Result<MIDIEntityRef, OSStatus>;
+
+    /// See [MIDIGetNumberOfExternalDevices](https://developer.apple.com/reference/coremidi/midigetnumberofexternaldevices).
+    fn number_of_external_devices(&self) -> usize;
+
+    /// See [MIDIGetExternalDevice](https://developer.apple.com/reference/coremidi/midigetexternaldevice).
+    fn external_device(&self, index: usize) -> MIDIDeviceRef;
+
+    /// See [MIDIExternalDeviceCreate](https://developer.apple.com/reference/coremidi/midiexternaldevicecreate).
+    fn external_device_create(&self, name: &str, manufacturer: &str, model: &str) -> Result<MIDIDeviceRef, OSStatus>;
+
+    /// See [MIDISetupAddExternalDevice](https://developer.apple.com/reference/coremidi/midisetupaddexternaldevice).
+    fn setup_add_external_device(&self, device: MIDIDeviceRef) -> OSStatus;
+
+    /// See [MIDISetupRemoveExternalDevice](https://developer.apple.com/reference/coremidi/midisetupremoveexternaldevice).
+    fn setup_remove_external_device(&self, device: MIDIDeviceRef) -> OSStatus;
+
+    /// See [MIDIObjectGetStringProperty](https://developer.apple.com/reference/coremidi/1495305-midiobjectgetstringproperty).
+    fn get_string_property(&self, object: MIDIObjectRef, key: &PropertyKey) -> Result<String, OSStatus>;
+
+    /// See [MIDIObjectSetStringProperty](https://developer.apple.com/reference/coremidi/1495144-midiobjectsetstringproperty).
+    fn set_string_property(&self, object: MIDIObjectRef, key: &PropertyKey, value: &str) -> OSStatus;
+
+    /// See [MIDIObjectGetIntegerProperty](https://developer.apple.com/reference/coremidi/1495281-midiobjectgetintegerproperty).
+    fn get_integer_property(&self, object: MIDIObjectRef, key: &PropertyKey) -> Result<i32, OSStatus>;
+
+    /// See [MIDIObjectSetIntegerProperty](https://developer.apple.com/reference/coremidi/1495355-midiobjectsetintegerproperty).
+    fn set_integer_property(&self, object: MIDIObjectRef, key: &PropertyKey, value: i32) -> OSStatus;
+
+    /// See [MIDIObjectFindByUniqueID](https://developer.apple.com/reference/coremidi/midiobjectfindbyuniqueid).
+    fn object_find_by_unique_id(&self, unique_id: MIDIUniqueID) -> Result<(MIDIObjectRef, MIDIObjectType), OSStatus>;
+
+    /// See [MIDIThruConnectionCreate](https://developer.apple.com/reference/coremidi/midithruconnectioncreate).
+    ///
+    /// The params are the bytes of a `MIDIThruConnectionParams`, see [ThruConnectionParams](struct.ThruConnectionParams.html).
+    fn thru_connection_create(&self, persistent_owner_id: Option<&str>, params: &[u8]) -> Result<MIDIThruConnectionRef, OSStatus>;
+
+    /// See [MIDIThruConnectionDispose](https://developer.apple.com/reference/coremidi/midithruconnectiondispose).
+    fn thru_connection_dispose(&self, connection: MIDIThruConnectionRef) -> OSStatus;
+
+    /// See [MIDIThruConnectionGetParams](https://developer.apple.com/reference/coremidi/midithruconnectiongetparams).
+    fn thru_connection_get_params(&self, connection: MIDIThruConnectionRef) -> Result<Vec<u8>, OSStatus>;
+
+    /// See [MIDIThruConnectionSetParams](https://developer.apple.com/reference/coremidi/midithruconnectionsetparams).
+    fn thru_connection_set_params(&self, connection: MIDIThruConnectionRef, params: &[u8]) -> OSStatus;
+
+    /// See [MIDIThruConnectionFind](https://developer.apple.com/reference/coremidi/midithruconnectionfind).
+    fn thru_connection_find(&self, persistent_owner_id: &str) -> Result<Vec<MIDIThruConnectionRef>, OSStatus>;
+}
+
+static DEFAULT_BACKEND: OnceLock<Arc<dyn Backend>> = OnceLock::new();
+
+thread_local! {
+    static CURRENT_BACKEND: RefCell<Option<Arc<dyn Backend>>> = RefCell::new(None);
+}
+
+/// Set the backend used by the whole process, instead of CoreMIDI.
+///
+/// It can only be set once, and before any other MIDI operation, otherwise the backend is given back.
+///
+pub fn set_default_backend(backend: Arc<dyn Backend>) -> Result<(), Arc<dyn Backend>> {
+    DEFAULT_BACKEND.set(backend)
+}
+
+/// Use a backend for all the MIDI operations of the current thread while running `f`.
+///
+/// The objects created within `f` keep using that backend, even when they are used or dropped outside of it:
+///
+/// ```
+/// use std::sync::Arc;
+/// let backend = Arc::new(coremidi::MemoryBackend::new());
+/// coremidi::with_backend(backend, || {
+///     let client = coremidi::Client::new("example-client").unwrap();
+///     let source = client.virtual_source("example-source").unwrap();
+///     assert_eq!(coremidi::Sources::count(), 1);
+///     assert_eq!(source.display_name(), Some("example-source".to_string()));
+/// });
+/// ```
+pub fn with_backend<F, T>(backend: Arc<dyn Backend>, f: F) -> T where F: FnOnce() -> T {
+    struct Restore(Option<Arc<dyn Backend>>);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            let previous = self.0.take();
+            CURRENT_BACKEND.with(|current| *current.borrow_mut() = previous);
+        }
+    }
+
+    let previous = CURRENT_BACKEND.with(|current| current.borrow_mut().replace(backend));
+    let _restore = Restore(previous);
+    f()
+}
+
+/// The backend for the MIDI operations of the current thread.
+///
+pub fn current() -> Arc<dyn Backend> {
+    CURRENT_BACKEND.with(|current| current.borrow().clone())
+        .unwrap_or_else(|| DEFAULT_BACKEND.get_or_init(default_backend).clone())
+}
+
+#[cfg(target_os = "macos")]
+fn default_backend() -> Arc<dyn Backend> {
+    Arc::new(CoreMidiBackend)
+}
+
+#[cfg(not(target_os = "macos"))]
+fn default_backend() -> Arc<dyn Backend> {
+    Arc::new(memory::MemoryBackend::new())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use backend::{current, with_backend, Backend};
+    use backend::memory::MemoryBackend;
+    use Client;
+
+    #[test]
+    fn with_backend_is_scoped() {
+        let backend = Arc::new(MemoryBackend::new());
+        backend.add_source("outer");
+        let inner = Arc::new(MemoryBackend::new());
+
+        with_backend(backend, || {
+            assert_eq!(current().number_of_sources(), 1);
+            with_backend(inner, || {
+                assert_eq!(current().number_of_sources(), 0);
+            });
+            assert_eq!(current().number_of_sources(), 1);
+        });
+    }
+
+    #[test]
+    fn objects_keep_their_backend() {
+        let backend = Arc::new(MemoryBackend::new());
+        let (client, source) = with_backend(backend.clone(), || {
+            let client = Client::new("client").unwrap();
+            let source = client.virtual_source("source").unwrap();
+            (client, source)
+        });
+
+        assert_eq!(source.name(), Some("source".to_string()));
+        drop(source);
+        assert_eq!(backend.number_of_sources(), 0);
+        drop(client);
+    }
+}
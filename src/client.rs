@@ -1,21 +1,15 @@
-use core_foundation::string::CFString;
-use core_foundation::base::{OSStatus, TCFType};
+use sys::MIDINotification;
 
-use coremidi_sys::{
-    MIDIClientRef, MIDIClientCreate, MIDIClientDispose, MIDINotification,
-    MIDIPortRef, MIDIOutputPortCreate, MIDIEndpointRef, MIDISourceCreate
-};
-
-use coremidi_sys_ext::{
-    MIDIPacketList, MIDIInputPortCreate, MIDIDestinationCreate
-};
+use sys::MIDIPacketList;
 
 use std::ops::Deref;
-use std::mem;
 use std::ptr;
+use std::sync::Arc;
 
 use Object;
+use Error;
 use Client;
+use Device;
 use Port;
 use OutputPort;
 use InputPort;
@@ -24,142 +18,289 @@ use VirtualSource;
 use VirtualDestination;
 use PacketListRef;
 use BoxedCallback;
-use notifications::Notification;
+use NotifyCallback;
+use backend;
+use notifications::{Notification, NotificationSubscribers};
+use receiver::{self, Receiver, TimestampedMessage, DEFAULT_RECEIVER_CAPACITY};
+#[cfg(feature = "async")]
+use stream::{self, PacketStream, OverflowPolicy, DEFAULT_STREAM_CAPACITY};
+#[cfg(feature = "async")]
+use notifications::NotificationStream;
+
+// The callback given to new_with_notifications
+type NotificationCallback = Box<dyn FnMut(&Notification) + Send>;
 
 impl Client {
     /// Creates a new CoreMIDI client with support for notifications.
     /// See [MIDIClientCreate](https://developer.apple.com/reference/coremidi/1495360-midiclientcreate).
     ///
-    pub fn new_with_notifications<F>(name: &str, callback: F) -> Result<Client, OSStatus>
+    /// The notifications are also delivered to the receivers and streams created with
+    /// [notification_receiver](#method.notification_receiver) and `notifications`.
+    ///
+    pub fn new_with_notifications<F>(name: &str, callback: F) -> Result<Client, Error>
         where F: FnMut(&Notification) + Send + 'static
     {
-        let client_name = CFString::new(name);
-        let mut client_ref: MIDIClientRef = unsafe { mem::uninitialized() };
-        let mut boxed_callback = BoxedCallback::new(Box::new(callback) as Box<FnMut(&Notification) + Send>);
-        let status = unsafe { MIDIClientCreate(
-            client_name.as_concrete_TypeRef(),
+        Self::create(name, Some(Box::new(callback)))
+    }
+
+    /// Creates a new CoreMIDI client whose notifications are only delivered to the receivers and streams
+    /// created with [notification_receiver](#method.notification_receiver) and `notifications`.
+    /// See [MIDIClientCreate](https://developer.apple.com/reference/coremidi/1495360-midiclientcreate).
+    ///
+    /// ```rust,no_run
+    /// let client = coremidi::Client::new_with_notification_subscribers("example-client").unwrap();
+    /// let notifications = client.notification_receiver();
+    /// ```
+    ///
+    pub fn new_with_notification_subscribers(name: &str) -> Result<Client, Error> {
+        Self::create(name, None)
+    }
+
+    /// Creates a new CoreMIDI client, without support for notifications.
+    /// See [MIDIClientCreate](https://developer.apple.com/reference/coremidi/1495360-midiclientcreate).
+    ///
+    pub fn new(name: &str) -> Result<Client, Error> {
+        let backend = backend::current();
+        let result = unsafe { backend.client_create(name, None, ptr::null_mut()) };
+        match result {
+            Ok(client_ref) => Ok(Client {
+                object: Object(client_ref, backend),
+                _callback: BoxedCallback::null(),
+                notification_subscribers: None,
+            }),
+            Err(status) => Err(Error::from(status))
+        }
+    }
+
+    // Creates a client with a notify proc, dispatching the notifications to the callback and to the subscribers.
+    fn create(name: &str, mut callback: Option<NotificationCallback>) -> Result<Client, Error> {
+        let backend = backend::current();
+        let notification_subscribers = Arc::new(NotificationSubscribers::new());
+        let subscribers = notification_subscribers.clone();
+        let notification_backend = backend.clone();
+        let dispatch = move |notification: &MIDINotification| {
+            // Unknown notifications are skipped
+            if let Ok(notification) = Notification::from_backend(notification, &notification_backend) {
+                if let Some(ref mut callback) = callback {
+                    callback(&notification);
+                }
+                subscribers.dispatch(&notification);
+            }
+        };
+
+        let mut boxed_callback = BoxedCallback::new(Box::new(dispatch) as NotifyCallback);
+        let result = unsafe { backend.client_create(
+            name,
             Some(Self::notify_proc as extern "C" fn(_, _)),
-            boxed_callback.raw_ptr(),
-            &mut client_ref)
+            boxed_callback.raw_ptr())
         };
-        if status == 0 {
-            Ok(Client { object: Object(client_ref), _callback: boxed_callback })
-        } else {
-            Err(status)
+        match result {
+            Ok(client_ref) => Ok(Client {
+                object: Object(client_ref, backend),
+                _callback: boxed_callback,
+                notification_subscribers: Some(notification_subscribers),
+            }),
+            Err(status) => Err(Error::from(status))
         }
     }
 
-    /// Creates a new CoreMIDI client.
-    /// See [MIDIClientCreate](https://developer.apple.com/reference/coremidi/1495360-midiclientcreate).
+    /// Creates a receiver for the notifications of this client.
     ///
-    pub fn new(name: &str) -> Result<Client, OSStatus> {
-        let client_name = CFString::new(name);
-        let mut client_ref: MIDIClientRef = unsafe { mem::uninitialized() };
-        let status = unsafe { MIDIClientCreate(
-            client_name.as_concrete_TypeRef(),
-            None, ptr::null_mut(),
-            &mut client_ref)
-        };
-        if status == 0 {
-            Ok(Client { object: Object(client_ref), _callback: BoxedCallback::null() })
-        } else {
-            Err(status)
+    /// Notifications are delivered on the run loop of the thread that created the client,
+    /// so that thread needs to be running its run loop for the receiver to get any of them.
+    /// If the receiver is not drained, the notifications beyond `DEFAULT_RECEIVER_CAPACITY` are dropped.
+    ///
+    /// Only the clients created with [new_with_notifications](#method.new_with_notifications) or
+    /// [new_with_notification_subscribers](#method.new_with_notification_subscribers) get notifications.
+    /// For any other client the receiver is disconnected from the start.
+    ///
+    pub fn notification_receiver(&self) -> Receiver<Notification> {
+        match self.notification_subscribers {
+            Some(ref subscribers) => subscribers.receiver(DEFAULT_RECEIVER_CAPACITY),
+            None => receiver::channel(1).1
+        }
+    }
+
+    /// Creates a stream of the notifications of this client.
+    ///
+    /// As with [notification_receiver](#method.notification_receiver), the thread that created the
+    /// client needs to be running its run loop for the stream to get any notifications, and the
+    /// client needs to support them, otherwise the stream ends right away.
+    /// If the stream is not consumed, the notifications beyond `DEFAULT_STREAM_CAPACITY` are dropped.
+    ///
+    #[cfg(feature = "async")]
+    pub fn notifications(&self) -> NotificationStream {
+        match self.notification_subscribers {
+            Some(ref subscribers) => subscribers.stream(DEFAULT_STREAM_CAPACITY),
+            None => NotificationSubscribers::new().stream(1)
         }
     }
 
     /// Creates an output port through which the client may send outgoing MIDI messages to any MIDI destination.
     /// See [MIDIOutputPortCreate](https://developer.apple.com/reference/coremidi/1495166-midioutputportcreate).
     ///
-    pub fn output_port(&self, name: &str) -> Result<OutputPort, OSStatus> {
-        let port_name = CFString::new(name);
-        let mut port_ref: MIDIPortRef = unsafe { mem::uninitialized() };
-        let status = unsafe { MIDIOutputPortCreate(
-            self.object.0,
-            port_name.as_concrete_TypeRef(),
-            &mut port_ref)
-        };
-        if status == 0 { Ok(OutputPort { port: Port { object: Object(port_ref) } }) } else { Err(status) }
+    pub fn output_port(&self, name: &str) -> Result<OutputPort, Error> {
+        match self.object.backend().output_port_create(self.object.0, name) {
+            Ok(port_ref) => Ok(OutputPort { port: Port { object: self.object.related(port_ref) } }),
+            Err(status) => Err(Error::from(status))
+        }
     }
 
     /// Creates an input port through which the client may receive incoming MIDI messages from any MIDI source.
     /// See [MIDIInputPortCreate](https://developer.apple.com/reference/coremidi/1495225-midiinputportcreate).
     ///
-    pub fn input_port<F>(&self, name: &str, callback: F) -> Result<InputPort, OSStatus>
+    pub fn input_port<F>(&self, name: &str, callback: F) -> Result<InputPort, Error>
             where F: FnMut(PacketListRef) + Send + 'static {
 
-        let port_name = CFString::new(name);
-        let mut port_ref: MIDIPortRef = unsafe { mem::uninitialized() };
         let mut box_callback = BoxedCallback::new(Box::new(callback) as Box<FnMut(PacketListRef) + Send>);
-        let status = unsafe { MIDIInputPortCreate(
+        let result = unsafe { self.object.backend().input_port_create(
             self.object.0,
-            port_name.as_concrete_TypeRef(),
+            name,
             Some(Self::read_proc as extern "C" fn(_, _, _)),
-            box_callback.raw_ptr(),
-            &mut port_ref)
+            box_callback.raw_ptr())
         };
-        if status == 0 {
-            Ok(InputPort {
-                port: Port { object: Object(port_ref) },
+        match result {
+            Ok(port_ref) => Ok(InputPort {
+                port: Port { object: self.object.related(port_ref) },
                 _callback: box_callback,
-            })
-        } else {
-            Err(status)
+            }),
+            Err(status) => Err(Error::from(status))
         }
     }
 
+    /// Creates an input port that copies the incoming packets into a bounded channel,
+    /// so they can be received from any other thread.
+    ///
+    /// If the channel is full the packets are dropped, instead of blocking the CoreMIDI thread,
+    /// and counted in [Receiver::overflow_count](struct.Receiver.html#method.overflow_count).
+    ///
+    pub fn input_port_with_receiver(&self, name: &str) -> Result<(InputPort, Receiver<TimestampedMessage>), Error> {
+        self.input_port_with_receiver_capacity(name, DEFAULT_RECEIVER_CAPACITY)
+    }
+
+    /// Like [input_port_with_receiver](#method.input_port_with_receiver), but with a channel
+    /// that can hold `capacity` packets. A capacity of 0 is taken as 1.
+    ///
+    pub fn input_port_with_receiver_capacity(&self, name: &str, capacity: usize) -> Result<(InputPort, Receiver<TimestampedMessage>), Error> {
+        let (sender, receiver) = receiver::channel(capacity);
+        let input_port = self.input_port(name, move |packet_list| {
+            for packet in packet_list.iter() {
+                sender.send(TimestampedMessage::from(packet));
+            }
+        })?;
+        Ok((input_port, receiver))
+    }
+
+    /// Creates an input port together with a stream of the packets it receives.
+    ///
+    /// The stream holds up to `DEFAULT_STREAM_CAPACITY` packets, dropping the incoming ones when it is full.
+    /// Every packet is copied into the stream on the CoreMIDI thread, under a short lock and with an allocation,
+    /// see [packet_ring](fn.packet_ring.html) for a wait-free alternative.
+    ///
+    #[cfg(feature = "async")]
+    pub fn input_stream(&self, name: &str) -> Result<(InputPort, PacketStream), Error> {
+        self.input_stream_with_policy(name, DEFAULT_STREAM_CAPACITY, OverflowPolicy::DropNewest)
+    }
+
+    /// Like [input_stream](#method.input_stream), but with a stream that can hold `capacity` packets,
+    /// and the given policy for when it is full. A capacity of 0 is taken as 1.
+    ///
+    #[cfg(feature = "async")]
+    pub fn input_stream_with_policy(&self, name: &str, capacity: usize, policy: OverflowPolicy) -> Result<(InputPort, PacketStream), Error> {
+        let (sender, stream) = stream::channel(capacity, policy);
+        let input_port = self.input_port(name, move |packet_list| {
+            for packet in packet_list.iter() {
+                sender.send(packet);
+            }
+        })?;
+        Ok((input_port, stream))
+    }
+
     /// Creates a virtual source in the client.
     /// See [MIDISourceCreate](https://developer.apple.com/reference/coremidi/1495212-midisourcecreate).
     ///
-    pub fn virtual_source(&self, name: &str) -> Result<VirtualSource, OSStatus> {
-        let virtual_source_name = CFString::new(name);
-        let mut virtual_source: MIDIEndpointRef = unsafe { mem::uninitialized() };
-        let status = unsafe { MIDISourceCreate(
-            self.object.0,
-            virtual_source_name.as_concrete_TypeRef(),
-            &mut virtual_source)
-        };
-        if status == 0 { Ok(VirtualSource { endpoint: Endpoint { object: Object(virtual_source) } }) } else { Err(status) }
+    pub fn virtual_source(&self, name: &str) -> Result<VirtualSource, Error> {
+        match self.object.backend().source_create(self.object.0, name) {
+            Ok(virtual_source) => Ok(VirtualSource { endpoint: Endpoint { object: self.object.related(virtual_source) } }),
+            Err(status) => Err(Error::from(status))
+        }
     }
 
     /// Creates a virtual destination in the client.
     /// See [MIDIDestinationCreate](https://developer.apple.com/reference/coremidi/1495347-mididestinationcreate).
     ///
-    pub fn virtual_destination<F>(&self, name: &str, callback: F) -> Result<VirtualDestination, OSStatus>
+    pub fn virtual_destination<F>(&self, name: &str, callback: F) -> Result<VirtualDestination, Error>
             where F: FnMut(PacketListRef) + Send + 'static {
 
-        let virtual_destination_name = CFString::new(name);
-        let mut virtual_destination: MIDIEndpointRef = unsafe { mem::uninitialized() };
         let mut boxed_callback = BoxedCallback::new(Box::new(callback) as Box<FnMut(PacketListRef)>);
-        let status = unsafe { MIDIDestinationCreate(
+        let result = unsafe { self.object.backend().destination_create(
             self.object.0,
-            virtual_destination_name.as_concrete_TypeRef(),
+            name,
             Some(Self::read_proc as extern "C" fn(_, _, _)),
-            boxed_callback.raw_ptr(),
-            &mut virtual_destination)
+            boxed_callback.raw_ptr())
         };
-        if status == 0 {
-            Ok(VirtualDestination {
+        match result {
+            Ok(virtual_destination) => Ok(VirtualDestination {
                 endpoint: Endpoint {
-                    object: Object(virtual_destination),
+                    object: self.object.related(virtual_destination),
                 },
                 _callback: boxed_callback,
-            })
-        } else {
-            Err(status)
+            }),
+            Err(status) => Err(Error::from(status))
         }
     }
 
+    /// Creates a virtual destination that copies the incoming packets into a bounded channel,
+    /// so they can be received from any other thread.
+    ///
+    /// As with [input_port_with_receiver](#method.input_port_with_receiver), the packets are dropped
+    /// when the channel is full.
+    ///
+    pub fn virtual_destination_with_receiver(&self, name: &str) -> Result<(VirtualDestination, Receiver<TimestampedMessage>), Error> {
+        self.virtual_destination_with_receiver_capacity(name, DEFAULT_RECEIVER_CAPACITY)
+    }
+
+    /// Like [virtual_destination_with_receiver](#method.virtual_destination_with_receiver), but with a channel
+    /// that can hold `capacity` packets. A capacity of 0 is taken as 1.
+    ///
+    pub fn virtual_destination_with_receiver_capacity(&self, name: &str, capacity: usize) -> Result<(VirtualDestination, Receiver<TimestampedMessage>), Error> {
+        let (sender, receiver) = receiver::channel(capacity);
+        let virtual_destination = self.virtual_destination(name, move |packet_list| {
+            for packet in packet_list.iter() {
+                sender.send(TimestampedMessage::from(packet));
+            }
+        })?;
+        Ok((virtual_destination, receiver))
+    }
+
+    /// Creates an external device and adds it to the system.
+    /// See [MIDIExternalDeviceCreate](https://developer.apple.com/reference/coremidi/midiexternaldevicecreate)
+    /// and [MIDISetupAddExternalDevice](https://developer.apple.com/reference/coremidi/midisetupaddexternaldevice).
+    ///
+    /// Its entities can be added with [Device::add_entity](struct.Device.html#method.add_entity), and then
+    /// connected to the ones of a driver with [Entity::connect](struct.Entity.html#method.connect).
+    ///
+    pub fn create_external_device(&self, name: &str, manufacturer: &str, model: &str) -> Result<Device, Error> {
+        let backend = self.object.backend();
+        let device_ref = backend.external_device_create(name, manufacturer, model).map_err(Error::from)?;
+        let status = backend.setup_add_external_device(device_ref);
+        if status == 0 { Ok(Device { object: self.object.related(device_ref) }) } else { Err(Error::from(status)) }
+    }
+
+    /// Removes an external device from the system.
+    /// See [MIDISetupRemoveExternalDevice](https://developer.apple.com/reference/coremidi/midisetupremoveexternaldevice).
+    ///
+    pub fn remove_external_device(&self, device: &Device) -> Result<(), Error> {
+        let status = self.object.backend().setup_remove_external_device(device.object.0);
+        if status == 0 { Ok(()) } else { Err(Error::from(status)) }
+    }
+
     extern "C" fn notify_proc(
             notification_ptr: *const MIDINotification,
             ref_con: *mut ::libc::c_void) {
 
         let _ = ::std::panic::catch_unwind(|| unsafe {
-            match Notification::from(&*notification_ptr) {
-                Ok(notification) => {
-                    BoxedCallback::<Box<FnMut(&Notification)>>::call_from_raw_ptr(ref_con, &notification);
-                },
-                Err(_) => {} // Skip unknown notifications
-            }
+            BoxedCallback::<Box<dyn FnMut(&MIDINotification)>>::call_from_raw_ptr(ref_con, &*notification_ptr);
         });
     }
 
@@ -185,6 +326,6 @@ impl Deref for Client {
 
 impl Drop for Client {
     fn drop(&mut self) {
-        unsafe { MIDIClientDispose(self.object.0) };
+        self.object.backend().client_dispose(self.object.0);
     }
 }
@@ -0,0 +1,103 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use sys::mach_absolute_time;
+use packets::Timestamp;
+
+/// A source of the current host time, against which packet timestamps are scheduled.
+///
+/// CoreMIDI delivers packets with a timestamp in the future when the host time reaches it,
+/// and packets with a timestamp of 0 immediately. Code that schedules packets can take a clock
+/// instead of reading the host time directly, so it can be driven by a [ManualClock](struct.ManualClock.html)
+/// in tests.
+///
+pub trait Clock: Send + Sync {
+    /// The current host time.
+    ///
+    fn now(&self) -> Timestamp;
+
+    /// The host time some ticks from now.
+    ///
+    fn after(&self, ticks: u64) -> Timestamp {
+        self.now().saturating_add(ticks)
+    }
+}
+
+/// The clock of the host, in the same ticks as the timestamps of CoreMIDI.
+/// See [mach_absolute_time](https://developer.apple.com/documentation/kernel/1462446-mach_absolute_time).
+///
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HostClock;
+
+impl Clock for HostClock {
+    fn now(&self) -> Timestamp {
+        unsafe { mach_absolute_time() }
+    }
+}
+
+/// A clock that only moves when told to, for deterministic tests.
+///
+/// ```
+/// use coremidi::{Clock, ManualClock};
+/// let clock = ManualClock::new(1000);
+/// let timestamp = clock.after(10);
+/// clock.advance(10);
+/// assert_eq!(clock.now(), timestamp);
+/// ```
+#[derive(Debug, Default)]
+pub struct ManualClock {
+    now: AtomicU64,
+}
+
+impl ManualClock {
+    /// Create a clock starting at the given time.
+    ///
+    pub fn new(now: Timestamp) -> ManualClock {
+        ManualClock { now: AtomicU64::new(now) }
+    }
+
+    /// Set the current time. The clock never goes backwards, so earlier times are ignored.
+    ///
+    pub fn set(&self, now: Timestamp) {
+        self.now.fetch_max(now, Ordering::SeqCst);
+    }
+
+    /// Move the current time forward by some ticks, and return the new time.
+    ///
+    pub fn advance(&self, ticks: u64) -> Timestamp {
+        let previous = self.now.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |now| Some(now.saturating_add(ticks)));
+        previous.unwrap().saturating_add(ticks)
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Timestamp {
+        self.now.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clock::{Clock, HostClock, ManualClock};
+
+    #[test]
+    fn manual_clock() {
+        let clock = ManualClock::new(100);
+        assert_eq!(clock.now(), 100);
+        assert_eq!(clock.after(5), 105);
+        assert_eq!(clock.advance(5), 105);
+        clock.set(50);
+        assert_eq!(clock.now(), 105);
+        clock.set(200);
+        assert_eq!(clock.now(), 200);
+        assert_eq!(clock.advance(u64::MAX), u64::MAX);
+        assert_eq!(clock.after(1), u64::MAX);
+    }
+
+    #[test]
+    fn host_clock_is_monotonic() {
+        let clock = HostClock;
+        let first = clock.now();
+        assert!(first > 0);
+        assert!(clock.now() >= first);
+    }
+}
@@ -7,15 +7,7 @@ use coremidi_sys::{
     MIDIClientRef, MIDIEndpointRef, MIDIPortRef,
 };
 
-pub type MIDIReadProc =
-    ::std::option::Option<extern "C" fn(pktlist: *const MIDIPacketList,
-                                        readProcRefCon: *mut ::libc::c_void,
-                                        srcConnRefCon: *mut ::libc::c_void)
-                              -> ()>;
-
-// Should only be used in a pointer
-#[repr(C)]
-pub struct MIDIPacketList(u8);
+use sys::{MIDIReadProc, MIDIPacketList};
 
 extern "C" {
     pub fn MIDISend(port: MIDIPortRef, dest: MIDIEndpointRef,
@@ -33,3 +25,16 @@ extern "C" {
                                 refCon: *mut ::libc::c_void,
                                 outDest: *mut MIDIEndpointRef) -> OSStatus;
 }
+
+// From mach/mach_time.h
+#[repr(C)]
+pub struct mach_timebase_info_data {
+    pub numer: u32,
+    pub denom: u32,
+}
+
+extern "C" {
+    pub fn mach_absolute_time() -> u64;
+
+    pub fn mach_timebase_info(info: *mut mach_timebase_info_data) -> ::libc::c_int;
+}
@@ -1,16 +1,58 @@
 use Object;
+use ObjectType;
+use Error;
 use Device;
+use Entity;
+use backend::{self, Backend};
+use entities::EntitiesIterator;
 
+use std::convert::TryFrom;
 use std::ops::Deref;
-
-use coremidi_sys::{
-    MIDIGetNumberOfDevices, MIDIGetDevice, ItemCount
-};
+use std::sync::Arc;
 
 impl Device {
     pub fn from_index(index: usize) -> Device {
-        let device_ref = unsafe { MIDIGetDevice(index as ItemCount) };
-        Device { object: Object(device_ref) }
+        let backend = backend::current();
+        Device { object: Object(backend.device(index), backend) }
+    }
+
+    /// Create an external device from its index.
+    /// See [MIDIGetExternalDevice](https://developer.apple.com/reference/coremidi/midigetexternaldevice).
+    ///
+    pub fn external_from_index(index: usize) -> Device {
+        let backend = backend::current();
+        Device { object: Object(backend.external_device(index), backend) }
+    }
+
+    /// Get the number of entities of the device.
+    /// See [MIDIDeviceGetNumberOfEntities](https://developer.apple.com/reference/coremidi/mididevicegetnumberofentities).
+    ///
+    pub fn entity_count(&self) -> usize {
+        self.object.backend().device_number_of_entities(self.object.0)
+    }
+
+    /// Iterate over the entities of the device.
+    ///
+    /// ```rust,no_run
+    /// for device in coremidi::Devices {
+    ///   for entity in device.entities() {
+    ///     println!("{} has {} sources", entity.display_name().unwrap(), entity.source_count());
+    ///   }
+    /// }
+    /// ```
+    ///
+    pub fn entities(&self) -> EntitiesIterator {
+        EntitiesIterator::new(self.object.clone())
+    }
+
+    /// Add an entity with some sources and destinations to the device, usually an external one.
+    /// See [MIDIDeviceAddEntity](https://developer.apple.com/reference/coremidi/midideviceaddentity).
+    ///
+    pub fn add_entity(&self, name: &str, embedded: bool, sources: usize, destinations: usize) -> Result<Entity, Error> {
+        match self.object.backend().device_add_entity(self.object.0, name, embedded, sources, destinations) {
+            Ok(entity_ref) => Ok(Entity { object: self.object.related(entity_ref) }),
+            Err(status) => Err(Error::from(status))
+        }
     }
 }
 
@@ -22,12 +64,26 @@ impl Deref for Device {
     }
 }
 
+/// Converts an object into a device, also an external one, when that is its type.
+/// Any other object is given back.
+///
+impl TryFrom<Object> for Device {
+    type Error = Object;
+
+    fn try_from(object: Object) -> Result<Device, Object> {
+        match object.object_type() {
+            Some(ObjectType::Device) | Some(ObjectType::ExternalDevice) => Ok(Device { object: object }),
+            _ => Err(object)
+        }
+    }
+}
+
 
 pub struct Devices;
 
 impl Devices {
     pub fn count() -> usize {
-        unsafe { MIDIGetNumberOfDevices() as usize }
+        backend::current().number_of_devices()
     }
 }
 
@@ -36,11 +92,14 @@ impl IntoIterator for Devices {
     type IntoIter = DevicesIterator;
 
     fn into_iter(self) -> Self::IntoIter {
-        DevicesIterator { index: 0, count: Self::count() }
+        let backend = backend::current();
+        let count = backend.number_of_devices();
+        DevicesIterator { backend: backend, index: 0, count: count }
     }
 }
 
 pub struct DevicesIterator {
+    backend: Arc<dyn Backend>,
     index: usize,
     count: usize
 }
@@ -50,9 +109,62 @@ impl Iterator for DevicesIterator {
 
     fn next(&mut self) -> Option<Device> {
         if self.index < self.count {
-            let source = Some(Device::from_index(self.index));
+            let device_ref = self.backend.device(self.index);
+            self.index += 1;
+            Some(Device { object: Object(device_ref, self.backend.clone()) })
+        } else {
+            None
+        }
+    }
+}
+
+/// External devices in the system, the ones that were added to describe what is connected
+/// to the devices of the drivers, see [Client::create_external_device](struct.Client.html#method.create_external_device).
+///
+/// The external devices in the system can be iterated as:
+///
+/// ```rust,no_run
+/// for device in coremidi::ExternalDevices {
+///   println!("{}", device.display_name().unwrap());
+/// }
+/// ```
+///
+pub struct ExternalDevices;
+
+impl ExternalDevices {
+    /// Get the number of external devices in the system.
+    /// See [MIDIGetNumberOfExternalDevices](https://developer.apple.com/reference/coremidi/midigetnumberofexternaldevices).
+    ///
+    pub fn count() -> usize {
+        backend::current().number_of_external_devices()
+    }
+}
+
+impl IntoIterator for ExternalDevices {
+    type Item = Device;
+    type IntoIter = ExternalDevicesIterator;
+
+    fn into_iter(self) -> Self::IntoIter {
+        let backend = backend::current();
+        let count = backend.number_of_external_devices();
+        ExternalDevicesIterator { backend: backend, index: 0, count: count }
+    }
+}
+
+pub struct ExternalDevicesIterator {
+    backend: Arc<dyn Backend>,
+    index: usize,
+    count: usize
+}
+
+impl Iterator for ExternalDevicesIterator {
+    type Item = Device;
+
+    fn next(&mut self) -> Option<Device> {
+        if self.index < self.count {
+            let device_ref = self.backend.external_device(self.index);
             self.index += 1;
-            source
+            Some(Device { object: Object(device_ref, self.backend.clone()) })
         } else {
             None
         }
@@ -1,21 +1,22 @@
-use coremidi_sys::{
-    MIDIGetNumberOfDestinations, MIDIGetDestination, ItemCount
-};
-
+use std::convert::TryFrom;
 use std::ops::Deref;
+use std::sync::Arc;
 
 use Object;
+use ObjectType;
 use Endpoint;
 use Destination;
 use VirtualDestination;
+use backend::{self, Backend};
+use endpoints::matching;
 
 impl Destination {
     /// Create a destination endpoint from its index.
     /// See [MIDIGetDestination](https://developer.apple.com/reference/coremidi/1495108-midigetdestination)
     ///
     pub fn from_index(index: usize) -> Destination {
-        let endpoint_ref = unsafe { MIDIGetDestination(index as ItemCount) };
-        Destination { endpoint: Endpoint { object: Object(endpoint_ref) } }
+        let backend = backend::current();
+        Destination { endpoint: Endpoint { object: Object(backend.destination(index), backend) } }
     }
 }
 
@@ -27,6 +28,20 @@ impl Deref for Destination {
     }
 }
 
+/// Converts an object into a destination when its type, looked up by its unique id,
+/// is a destination or an external destination. Otherwise the object is given back.
+///
+impl TryFrom<Object> for Destination {
+    type Error = Object;
+
+    fn try_from(object: Object) -> Result<Destination, Object> {
+        match object.object_type() {
+            Some(ObjectType::Destination) | Some(ObjectType::ExternalDestination) => Ok(Destination { endpoint: Endpoint { object: object } }),
+            _ => Err(object)
+        }
+    }
+}
+
 /// Destination endpoints available in the system.
 ///
 /// The number of destinations available in the system can be retrieved with:
@@ -50,7 +65,38 @@ impl Destinations {
     /// See [MIDIGetNumberOfDestinations](https://developer.apple.com/reference/coremidi/1495309-midigetnumberofdestinations).
     ///
     pub fn count() -> usize {
-        unsafe { MIDIGetNumberOfDestinations() as usize }
+        backend::current().number_of_destinations()
+    }
+
+    /// Find the first destination in the system for which the predicate is true.
+    ///
+    pub fn find<P>(predicate: P) -> Option<Destination> where P: FnMut(&Destination) -> bool {
+        Destinations.into_iter().find(predicate)
+    }
+
+    /// Find the first destination in the system with the given name.
+    ///
+    pub fn by_name(name: &str) -> Option<Destination> {
+        Self::find(|destination| destination.name().as_deref() == Some(name))
+    }
+
+    /// Find the first destination in the system with the given display name.
+    ///
+    pub fn by_display_name(display_name: &str) -> Option<Destination> {
+        Self::find(|destination| destination.display_name().as_deref() == Some(display_name))
+    }
+
+    /// Get the destinations in the system that match a query, like a partial name typed by a user,
+    /// sorted from the best match. See [MatchRank](enum.MatchRank.html) for how they are matched.
+    ///
+    /// ```rust,no_run
+    /// if let Some(destination) = coremidi::Destinations::search("launchpad").into_iter().next() {
+    ///   println!("{}", destination.display_name().unwrap());
+    /// }
+    /// ```
+    ///
+    pub fn search(query: &str) -> Vec<Destination> {
+        matching::search(Destinations, query)
     }
 }
 
@@ -59,11 +105,14 @@ impl IntoIterator for Destinations {
     type IntoIter = DestinationsIterator;
 
     fn into_iter(self) -> Self::IntoIter {
-        DestinationsIterator { index: 0, count: Self::count() }
+        let backend = backend::current();
+        let count = backend.number_of_destinations();
+        DestinationsIterator { backend: backend, index: 0, count: count }
     }
 }
 
 pub struct DestinationsIterator {
+    backend: Arc<dyn Backend>,
     index: usize,
     count: usize
 }
@@ -73,9 +122,9 @@ impl Iterator for DestinationsIterator {
 
     fn next(&mut self) -> Option<Destination> {
         if self.index < self.count {
-            let destination = Some(Destination::from_index(self.index));
+            let endpoint_ref = self.backend.destination(self.index);
             self.index += 1;
-            destination
+            Some(Destination { endpoint: Endpoint { object: Object(endpoint_ref, self.backend.clone()) } })
         }
         else {
             None
@@ -87,6 +136,12 @@ impl VirtualDestination {
 
 }
 
+impl Drop for VirtualDestination {
+    fn drop(&mut self) {
+        self.endpoint.object.backend().endpoint_dispose(self.endpoint.object.0);
+    }
+}
+
 impl Deref for VirtualDestination {
     type Target = Endpoint;
 
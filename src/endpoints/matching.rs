@@ -0,0 +1,168 @@
+use std::ops::Deref;
+
+use Object;
+use properties::{Properties, PropertyGetter};
+
+/// The names of an endpoint that a query is matched against, see [MatchRank](enum.MatchRank.html).
+///
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EndpointNames {
+    pub display_name: Option<String>,
+    pub name: Option<String>,
+    pub manufacturer: Option<String>,
+    pub model: Option<String>,
+}
+
+impl EndpointNames {
+    /// Get the names of an object from its properties.
+    ///
+    pub fn of(object: &Object) -> EndpointNames {
+        EndpointNames {
+            display_name: object.display_name(),
+            name: object.name(),
+            manufacturer: Properties::manufacturer().value_from(object).ok(),
+            model: Properties::model().value_from(object).ok(),
+        }
+    }
+}
+
+/// How well a query matches the names of an endpoint, from the best to the worst match.
+///
+/// The matching ignores case. The display name and the name are tried first, then the
+/// manufacturer and model, so that "novation launchpad" also finds a Launchpad from Novation
+/// whose endpoint is just named "MIDI 1".
+///
+/// ```
+/// use coremidi::{EndpointNames, MatchRank};
+///
+/// let names = EndpointNames { name: Some("Launchpad Mini".to_string()), ..Default::default() };
+/// assert_eq!(MatchRank::of("launchpad", &names), Some(MatchRank::Prefix));
+/// assert_eq!(MatchRank::of("keystation", &names), None);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MatchRank {
+    /// The display name or the name is the query.
+    Exact,
+    /// The display name or the name starts with the query.
+    Prefix,
+    /// The display name or the name contains the query.
+    Substring,
+    /// The manufacturer followed by the model, or any of them, contains the query.
+    ManufacturerModel,
+    /// Every word of the query is contained in some of the names.
+    Words,
+}
+
+impl MatchRank {
+    /// Rank how well a query matches some names, or `None` if it doesn't match them at all.
+    ///
+    pub fn of(query: &str, names: &EndpointNames) -> Option<MatchRank> {
+        let query = query.trim().to_lowercase();
+        if query.is_empty() {
+            return None;
+        }
+        let lowercase = |name: &Option<String>| name.as_ref().map(|name| name.to_lowercase());
+        let endpoint_names = [lowercase(&names.display_name), lowercase(&names.name)];
+        let endpoint_names = endpoint_names.iter().flatten().collect::<Vec<_>>();
+        let manufacturer = lowercase(&names.manufacturer);
+        let model = lowercase(&names.model);
+
+        if endpoint_names.iter().any(|name| **name == query) {
+            return Some(MatchRank::Exact);
+        }
+        if endpoint_names.iter().any(|name| name.starts_with(&query)) {
+            return Some(MatchRank::Prefix);
+        }
+        if endpoint_names.iter().any(|name| name.contains(&query)) {
+            return Some(MatchRank::Substring);
+        }
+        let manufacturer_model = match (&manufacturer, &model) {
+            (Some(manufacturer), Some(model)) => Some(format!("{} {}", manufacturer, model)),
+            _ => None
+        };
+        if manufacturer_model.iter().chain(manufacturer.iter()).chain(model.iter()).any(|name| name.contains(&query)) {
+            return Some(MatchRank::ManufacturerModel);
+        }
+        let all_names = endpoint_names.into_iter().chain(manufacturer.iter()).chain(model.iter()).collect::<Vec<_>>();
+        if query.split_whitespace().all(|word| all_names.iter().any(|name| name.contains(word))) {
+            return Some(MatchRank::Words);
+        }
+        None
+    }
+}
+
+/// Keep the endpoints that match a query, sorted from the best match.
+/// The endpoints with the same rank are kept in their order.
+///
+pub(crate) fn search<T, I>(endpoints: I, query: &str) -> Vec<T>
+        where T: Deref, T::Target: Deref<Target = Object>, I: IntoIterator<Item = T> {
+    let mut matches = endpoints.into_iter()
+        .filter_map(|endpoint| {
+            MatchRank::of(query, &EndpointNames::of(&endpoint)).map(|rank| (rank, endpoint))
+        })
+        .collect::<Vec<_>>();
+    matches.sort_by_key(|&(rank, _)| rank);
+    matches.into_iter().map(|(_, endpoint)| endpoint).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use backend::with_backend;
+    use backend::memory::MemoryBackend;
+    use {Client, Destinations, Properties, PropertySetter, Sources};
+    use super::{EndpointNames, MatchRank};
+
+    fn names(display_name: &str, manufacturer: &str, model: &str) -> EndpointNames {
+        EndpointNames {
+            display_name: Some(display_name.to_string()),
+            name: Some("MIDI 1".to_string()),
+            manufacturer: Some(manufacturer.to_string()),
+            model: Some(model.to_string()),
+        }
+    }
+
+    #[test]
+    fn rank() {
+        let launchpad = names("Launchpad Mini MK3 LPMiniMK3 MIDI", "Focusrite - Novation", "Launchpad Mini MK3");
+        assert_eq!(MatchRank::of("midi 1", &launchpad), Some(MatchRank::Exact));
+        assert_eq!(MatchRank::of("LAUNCHPAD", &launchpad), Some(MatchRank::Prefix));
+        assert_eq!(MatchRank::of(" mini mk3 ", &launchpad), Some(MatchRank::Substring));
+        assert_eq!(MatchRank::of("novation launchpad", &launchpad), Some(MatchRank::ManufacturerModel));
+        assert_eq!(MatchRank::of("focusrite", &launchpad), Some(MatchRank::ManufacturerModel));
+        assert_eq!(MatchRank::of("novation mini", &launchpad), Some(MatchRank::Words));
+        assert_eq!(MatchRank::of("novation keystation", &launchpad), None);
+        assert_eq!(MatchRank::of("", &launchpad), None);
+        assert_eq!(MatchRank::of("midi", &EndpointNames::default()), None);
+        assert!(MatchRank::Exact < MatchRank::Prefix && MatchRank::ManufacturerModel < MatchRank::Words);
+    }
+
+    #[test]
+    fn find_and_search() {
+        let backend = Arc::new(MemoryBackend::new());
+        backend.add_source("Keystation 49");
+        let launchpad = backend.add_source("Launchpad Mini");
+        backend.add_destination("Launchpad Mini");
+        backend.add_destination("IAC Driver Bus 1");
+        with_backend(backend, || {
+            let client = Client::new("client").unwrap();
+            let virtual_source = client.virtual_source("My Launchpad").unwrap();
+            Properties::manufacturer().set_value(&virtual_source, "Novation").unwrap();
+
+            let source = Sources::find(|source| source.name() == Some("Launchpad Mini".to_string())).unwrap();
+            assert_eq!(source.unique_id(), Some(launchpad));
+            assert!(Sources::find(|source| source.name().is_none()).is_none());
+
+            assert_eq!(Destinations::by_name("IAC Driver Bus 1").unwrap().name(), Some("IAC Driver Bus 1".to_string()));
+            assert!(Destinations::by_name("iac driver bus 1").is_none());
+            assert!(Destinations::by_display_name("Launchpad Mini").is_some());
+
+            let found = Sources::search("launchpad").into_iter().map(|source| source.name().unwrap()).collect::<Vec<_>>();
+            assert_eq!(found, vec!["Launchpad Mini".to_string(), "My Launchpad".to_string()]);
+            let found = Sources::search("novation").into_iter().map(|source| source.name().unwrap()).collect::<Vec<_>>();
+            assert_eq!(found, vec!["My Launchpad".to_string()]);
+            assert_eq!(Destinations::search("bus").len(), 1);
+        });
+    }
+}
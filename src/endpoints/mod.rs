@@ -1,19 +1,27 @@
-use core_foundation_sys::base::OSStatus;
-
-use coremidi_sys::MIDIFlushOutput;
-
 use std::ops::Deref;
 
 use Object;
+use Error;
 use Endpoint;
+use Entity;
 
 impl Endpoint {
     /// Unschedules previously-sent packets.
     /// See [MIDIFlushOutput](https://developer.apple.com/reference/coremidi/1495312-midiflushoutput).
     ///
-    pub fn flush(&self) -> Result<(), OSStatus> {
-        let status = unsafe { MIDIFlushOutput(self.object.0) };
-        if status == 0 { Ok(()) } else { Err(status) }
+    pub fn flush(&self) -> Result<(), Error> {
+        let status = self.object.backend().flush_output(self.object.0);
+        if status == 0 { Ok(()) } else { Err(Error::from(status)) }
+    }
+
+    /// Get the entity that owns the endpoint, if any. Virtual sources and destinations don't have one.
+    /// See [MIDIEndpointGetEntity](https://developer.apple.com/reference/coremidi/midiendpointgetentity).
+    ///
+    pub fn entity(&self) -> Option<Entity> {
+        match self.object.backend().endpoint_entity(self.object.0) {
+            Ok(entity_ref) if entity_ref != 0 => Some(Entity { object: self.object.related(entity_ref) }),
+            _ => None
+        }
     }
 }
 
@@ -33,3 +41,4 @@ impl Deref for Endpoint {
 
 pub mod destinations;
 pub mod sources;
+pub mod matching;
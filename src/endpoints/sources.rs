@@ -1,28 +1,24 @@
-use core_foundation_sys::base::OSStatus;
-
-use coremidi_sys::{
-    MIDIGetNumberOfSources, MIDIGetSource, ItemCount
-};
-
-use coremidi_sys_ext::{
-    MIDIReceived
-};
-
+use std::convert::TryFrom;
 use std::ops::Deref;
+use std::sync::Arc;
 
 use Object;
+use ObjectType;
+use Error;
 use Endpoint;
 use Source;
 use VirtualSource;
 use PacketListRef;
+use backend::{self, Backend};
+use endpoints::matching;
 
 impl Source {
     /// Create a source endpoint from its index.
     /// See [MIDIGetSource](https://developer.apple.com/reference/coremidi/1495168-midigetsource)
     ///
     pub fn from_index(index: usize) -> Source {
-        let endpoint_ref = unsafe { MIDIGetSource(index as ItemCount) };
-        Source { endpoint: Endpoint { object: Object(endpoint_ref) } }
+        let backend = backend::current();
+        Source { endpoint: Endpoint { object: Object(backend.source(index), backend) } }
     }
 }
 
@@ -34,6 +30,20 @@ impl Deref for Source {
     }
 }
 
+/// Sources and external sources can be converted from an object of their type, which is looked up by its unique id.
+/// Any other object is given back.
+///
+impl TryFrom<Object> for Source {
+    type Error = Object;
+
+    fn try_from(object: Object) -> Result<Source, Object> {
+        match object.object_type() {
+            Some(ObjectType::Source) | Some(ObjectType::ExternalSource) => Ok(Source { endpoint: Endpoint { object: object } }),
+            _ => Err(object)
+        }
+    }
+}
+
 /// Source endpoints available in the system.
 ///
 /// The number of sources available in the system can be retrieved with:
@@ -57,7 +67,38 @@ impl Sources {
     /// See [MIDIGetNumberOfSources](https://developer.apple.com/reference/coremidi/1495116-midigetnumberofsources).
     ///
     pub fn count() -> usize {
-        unsafe { MIDIGetNumberOfSources() as usize }
+        backend::current().number_of_sources()
+    }
+
+    /// Find the first source in the system for which the predicate is true.
+    ///
+    pub fn find<P>(predicate: P) -> Option<Source> where P: FnMut(&Source) -> bool {
+        Sources.into_iter().find(predicate)
+    }
+
+    /// Find the first source in the system with the given name.
+    ///
+    pub fn by_name(name: &str) -> Option<Source> {
+        Self::find(|source| source.name().as_deref() == Some(name))
+    }
+
+    /// Find the first source in the system with the given display name.
+    ///
+    pub fn by_display_name(display_name: &str) -> Option<Source> {
+        Self::find(|source| source.display_name().as_deref() == Some(display_name))
+    }
+
+    /// Get the sources in the system that match a query, like a partial name typed by a user,
+    /// sorted from the best match. See [MatchRank](enum.MatchRank.html) for how they are matched.
+    ///
+    /// ```rust,no_run
+    /// if let Some(source) = coremidi::Sources::search("launchpad").into_iter().next() {
+    ///   println!("{}", source.display_name().unwrap());
+    /// }
+    /// ```
+    ///
+    pub fn search(query: &str) -> Vec<Source> {
+        matching::search(Sources, query)
     }
 }
 
@@ -66,11 +107,14 @@ impl IntoIterator for Sources {
     type IntoIter = SourcesIterator;
 
     fn into_iter(self) -> Self::IntoIter {
-        SourcesIterator { index: 0, count: Self::count() }
+        let backend = backend::current();
+        let count = backend.number_of_sources();
+        SourcesIterator { backend: backend, index: 0, count: count }
     }
 }
 
 pub struct SourcesIterator {
+    backend: Arc<dyn Backend>,
     index: usize,
     count: usize
 }
@@ -80,9 +124,9 @@ impl Iterator for SourcesIterator {
 
     fn next(&mut self) -> Option<Source> {
         if self.index < self.count {
-            let source = Some(Source::from_index(self.index));
+            let endpoint_ref = self.backend.source(self.index);
             self.index += 1;
-            source
+            Some(Source { endpoint: Endpoint { object: Object(endpoint_ref, self.backend.clone()) } })
         }
         else {
             None
@@ -94,12 +138,17 @@ impl VirtualSource {
     /// Distributes incoming MIDI from a source to the client input ports which are connected to that source.
     /// See [MIDIReceived](https://developer.apple.com/reference/coremidi/1495276-midireceived)
     ///
-    pub fn received(&self, packet_list: PacketListRef) -> Result<(), OSStatus> {
-        let status = unsafe { MIDIReceived(
+    pub fn received(&self, packet_list: PacketListRef) -> Result<(), Error> {
+        let status = self.endpoint.object.backend().received(
             self.endpoint.object.0,
-            packet_list.as_ptr())
-        };
-        if status == 0 { Ok(()) } else { Err(status) }
+            packet_list);
+        if status == 0 { Ok(()) } else { Err(Error::from(status)) }
+    }
+}
+
+impl Drop for VirtualSource {
+    fn drop(&mut self) {
+        self.endpoint.object.backend().endpoint_dispose(self.endpoint.object.0);
     }
 }
 
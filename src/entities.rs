@@ -0,0 +1,244 @@
+use std::convert::TryFrom;
+use std::ops::Deref;
+
+use Object;
+use ObjectType;
+use Error;
+use Device;
+use Entity;
+use Endpoint;
+use Source;
+use Destination;
+use properties::{Properties, PropertySetter};
+
+impl Entity {
+    /// Get the device that owns the entity, if any.
+    /// See [MIDIEntityGetDevice](https://developer.apple.com/reference/coremidi/midientitygetdevice).
+    ///
+    pub fn device(&self) -> Option<Device> {
+        match self.object.backend().entity_device(self.object.0) {
+            Ok(device_ref) if device_ref != 0 => Some(Device { object: self.object.related(device_ref) }),
+            _ => None
+        }
+    }
+
+    /// Get the number of sources of the entity.
+    /// See [MIDIEntityGetNumberOfSources](https://developer.apple.com/reference/coremidi/midientitygetnumberofsources).
+    ///
+    pub fn source_count(&self) -> usize {
+        self.object.backend().entity_number_of_sources(self.object.0)
+    }
+
+    /// Iterate over the sources of the entity.
+    ///
+    pub fn sources(&self) -> EntitySourcesIterator {
+        EntitySourcesIterator { entity: self.object.clone(), index: 0, count: self.source_count() }
+    }
+
+    /// Get the number of destinations of the entity.
+    /// See [MIDIEntityGetNumberOfDestinations](https://developer.apple.com/reference/coremidi/midientitygetnumberofdestinations).
+    ///
+    pub fn destination_count(&self) -> usize {
+        self.object.backend().entity_number_of_destinations(self.object.0)
+    }
+
+    /// Iterate over the destinations of the entity.
+    ///
+    pub fn destinations(&self) -> EntityDestinationsIterator {
+        EntityDestinationsIterator { entity: self.object.clone(), index: 0, count: self.destination_count() }
+    }
+
+    /// Connect the sources and destinations of a driver entity to the ones of an external entity, in order,
+    /// by setting their [connection unique id](struct.Properties.html#method.connection_unique_id).
+    ///
+    /// ```rust,no_run
+    /// let client = coremidi::Client::new("example-client").unwrap();
+    /// let synth = client.create_external_device("Synth", "Manufacturer", "Model").unwrap();
+    /// let synth_ports = synth.add_entity("Synth", false, 1, 1).unwrap();
+    /// let interface = coremidi::Device::from_index(0);
+    /// interface.entities().next().unwrap().connect(&synth_ports).unwrap();
+    /// ```
+    ///
+    pub fn connect(&self, external: &Entity) -> Result<(), Error> {
+        for (source, external_source) in self.sources().zip(external.sources()) {
+            Self::connect_endpoint(&source, &external_source)?;
+        }
+        for (destination, external_destination) in self.destinations().zip(external.destinations()) {
+            Self::connect_endpoint(&destination, &external_destination)?;
+        }
+        Ok(())
+    }
+
+    fn connect_endpoint(endpoint: &Object, external: &Object) -> Result<(), Error> {
+        let unique_id = external.unique_id().ok_or(Error::ObjectNotFound)?;
+        Properties::connection_unique_id().set_value(endpoint, unique_id as i32)
+    }
+}
+
+impl Deref for Entity {
+    type Target = Object;
+
+    fn deref(&self) -> &Object {
+        &self.object
+    }
+}
+
+/// Entities, of devices or of external devices, can be converted from an object of their type.
+/// Any other object is given back.
+///
+impl TryFrom<Object> for Entity {
+    type Error = Object;
+
+    fn try_from(object: Object) -> Result<Entity, Object> {
+        match object.object_type() {
+            Some(ObjectType::Entity) | Some(ObjectType::ExternalEntity) => Ok(Entity { object: object }),
+            _ => Err(object)
+        }
+    }
+}
+
+pub struct EntitiesIterator {
+    device: Object,
+    index: usize,
+    count: usize
+}
+
+impl EntitiesIterator {
+    pub(crate) fn new(device: Object) -> EntitiesIterator {
+        let count = device.backend().device_number_of_entities(device.0);
+        EntitiesIterator { device: device, index: 0, count: count }
+    }
+}
+
+impl Iterator for EntitiesIterator {
+    type Item = Entity;
+
+    fn next(&mut self) -> Option<Entity> {
+        if self.index < self.count {
+            let entity_ref = self.device.backend().device_entity(self.device.0, self.index);
+            self.index += 1;
+            Some(Entity { object: self.device.related(entity_ref) })
+        } else {
+            None
+        }
+    }
+}
+
+pub struct EntitySourcesIterator {
+    entity: Object,
+    index: usize,
+    count: usize
+}
+
+impl Iterator for EntitySourcesIterator {
+    type Item = Source;
+
+    fn next(&mut self) -> Option<Source> {
+        if self.index < self.count {
+            let endpoint_ref = self.entity.backend().entity_source(self.entity.0, self.index);
+            self.index += 1;
+            Some(Source { endpoint: Endpoint { object: self.entity.related(endpoint_ref) } })
+        } else {
+            None
+        }
+    }
+}
+
+pub struct EntityDestinationsIterator {
+    entity: Object,
+    index: usize,
+    count: usize
+}
+
+impl Iterator for EntityDestinationsIterator {
+    type Item = Destination;
+
+    fn next(&mut self) -> Option<Destination> {
+        if self.index < self.count {
+            let endpoint_ref = self.entity.backend().entity_destination(self.entity.0, self.index);
+            self.index += 1;
+            Some(Destination { endpoint: Endpoint { object: self.entity.related(endpoint_ref) } })
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use backend::with_backend;
+    use backend::memory::MemoryBackend;
+    use {Client, Device, ExternalDevices, Properties, PropertyGetter, Source, Sources};
+
+    #[test]
+    fn traverse_device_entities_and_endpoints() {
+        let backend = Arc::new(MemoryBackend::new());
+        let device = backend.add_device("interface");
+        let port_1 = backend.add_entity(device, "port 1");
+        let port_2 = backend.add_entity(device, "port 2");
+        backend.add_entity_source(port_1, "in 1");
+        backend.add_entity_destination(port_1, "out 1");
+        backend.add_entity_source(port_2, "in 2");
+        with_backend(backend, || {
+            let device = Device::from_index(0);
+            let entities = device.entities().collect::<Vec<_>>();
+            let names = entities.iter().map(|entity| entity.name()).collect::<Vec<_>>();
+            assert_eq!(names, vec![Some("port 1".to_string()), Some("port 2".to_string())]);
+
+            let sources = entities[0].sources().map(|source| source.name()).collect::<Vec<_>>();
+            assert_eq!(sources, vec![Some("in 1".to_string())]);
+            let destinations = entities[0].destinations().map(|destination| destination.name()).collect::<Vec<_>>();
+            assert_eq!(destinations, vec![Some("out 1".to_string())]);
+            assert_eq!(entities[1].destination_count(), 0);
+            assert_eq!(entities[1].device(), Some(device.clone()));
+
+            // The entity sources are also sources of the system
+            assert_eq!(Sources::count(), 2);
+            let source = Source::from_index(1);
+            assert_eq!(source.entity(), Some(entities[1].clone()));
+            assert_eq!(source.entity().and_then(|entity| entity.device()), Some(device));
+        });
+    }
+
+    #[test]
+    fn virtual_endpoints_have_no_entity() {
+        let backend = Arc::new(MemoryBackend::new());
+        with_backend(backend, || {
+            let client = Client::new("client").unwrap();
+            let _source = client.virtual_source("source").unwrap();
+            assert_eq!(Source::from_index(0).entity(), None);
+        });
+    }
+
+    #[test]
+    fn external_devices() {
+        let backend = Arc::new(MemoryBackend::new());
+        let interface = backend.add_device("interface");
+        let port = backend.add_entity(interface, "port 1");
+        backend.add_entity_source(port, "in 1");
+        backend.add_entity_destination(port, "out 1");
+        with_backend(backend, || {
+            let client = Client::new("client").unwrap();
+            let synth = client.create_external_device("synth", "manufacturer", "model").unwrap();
+            let synth_ports = synth.add_entity("synth ports", false, 1, 1).unwrap();
+            assert_eq!(ExternalDevices::count(), 1);
+            assert_eq!(ExternalDevices.into_iter().collect::<Vec<_>>(), vec![synth.clone()]);
+            assert_eq!(synth_ports.device(), Some(synth.clone()));
+            // The endpoints of the external devices are not in the system
+            assert_eq!(Sources::count(), 1);
+
+            let port = Device::from_index(0).entities().next().unwrap();
+            port.connect(&synth_ports).unwrap();
+            let connected: i32 = Properties::connection_unique_id().value_from(&port.sources().next().unwrap()).unwrap();
+            assert_eq!(Some(connected as u32), synth_ports.sources().next().unwrap().unique_id());
+            let connected: i32 = Properties::connection_unique_id().value_from(&port.destinations().next().unwrap()).unwrap();
+            assert_eq!(Some(connected as u32), synth_ports.destinations().next().unwrap().unique_id());
+
+            client.remove_external_device(&synth).unwrap();
+            assert_eq!(ExternalDevices::count(), 0);
+            assert!(client.remove_external_device(&synth).is_err());
+        });
+    }
+}
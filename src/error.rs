@@ -0,0 +1,147 @@
+#![allow(non_upper_case_globals)]
+
+use sys::OSStatus;
+
+use std::error;
+use std::fmt;
+
+// The status codes of the MIDI Services Errors, as defined in MIDIServices.h.
+pub(crate) const kMIDIInvalidClient: OSStatus = -10830;
+pub(crate) const kMIDIInvalidPort: OSStatus = -10831;
+pub(crate) const kMIDIWrongEndpointType: OSStatus = -10832;
+pub(crate) const kMIDINoConnection: OSStatus = -10833;
+pub(crate) const kMIDIUnknownEndpoint: OSStatus = -10834;
+pub(crate) const kMIDIUnknownProperty: OSStatus = -10835;
+pub(crate) const kMIDIWrongPropertyType: OSStatus = -10836;
+pub(crate) const kMIDINoCurrentSetup: OSStatus = -10837;
+pub(crate) const kMIDIMessageSendErr: OSStatus = -10838;
+pub(crate) const kMIDIServerStartErr: OSStatus = -10839;
+pub(crate) const kMIDISetupFormatErr: OSStatus = -10840;
+pub(crate) const kMIDIWrongThread: OSStatus = -10841;
+pub(crate) const kMIDIObjectNotFound: OSStatus = -10842;
+pub(crate) const kMIDIIDNotUnique: OSStatus = -10843;
+pub(crate) const kMIDINotPermitted: OSStatus = -10844;
+
+/// An error returned by CoreMIDI.
+/// See [MIDI Services Errors](https://developer.apple.com/reference/coremidi/1495241-midi_services_errors).
+///
+/// Status codes that are not specific to CoreMIDI are kept as `Other`.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    InvalidClient,
+    InvalidPort,
+    WrongEndpointType,
+    NoConnection,
+    UnknownEndpoint,
+    UnknownProperty,
+    WrongPropertyType,
+    NoCurrentSetup,
+    MessageSendErr,
+    ServerStartErr,
+    SetupFormatErr,
+    WrongThread,
+    ObjectNotFound,
+    IDNotUnique,
+    NotPermitted,
+    Other(i32)
+}
+
+impl Error {
+    /// The status code returned by CoreMIDI for this error.
+    ///
+    pub fn code(&self) -> OSStatus {
+        match *self {
+            Error::InvalidClient => kMIDIInvalidClient,
+            Error::InvalidPort => kMIDIInvalidPort,
+            Error::WrongEndpointType => kMIDIWrongEndpointType,
+            Error::NoConnection => kMIDINoConnection,
+            Error::UnknownEndpoint => kMIDIUnknownEndpoint,
+            Error::UnknownProperty => kMIDIUnknownProperty,
+            Error::WrongPropertyType => kMIDIWrongPropertyType,
+            Error::NoCurrentSetup => kMIDINoCurrentSetup,
+            Error::MessageSendErr => kMIDIMessageSendErr,
+            Error::ServerStartErr => kMIDIServerStartErr,
+            Error::SetupFormatErr => kMIDISetupFormatErr,
+            Error::WrongThread => kMIDIWrongThread,
+            Error::ObjectNotFound => kMIDIObjectNotFound,
+            Error::IDNotUnique => kMIDIIDNotUnique,
+            Error::NotPermitted => kMIDINotPermitted,
+            Error::Other(status) => status
+        }
+    }
+}
+
+impl From<OSStatus> for Error {
+    fn from(status: OSStatus) -> Error {
+        match status {
+            kMIDIInvalidClient => Error::InvalidClient,
+            kMIDIInvalidPort => Error::InvalidPort,
+            kMIDIWrongEndpointType => Error::WrongEndpointType,
+            kMIDINoConnection => Error::NoConnection,
+            kMIDIUnknownEndpoint => Error::UnknownEndpoint,
+            kMIDIUnknownProperty => Error::UnknownProperty,
+            kMIDIWrongPropertyType => Error::WrongPropertyType,
+            kMIDINoCurrentSetup => Error::NoCurrentSetup,
+            kMIDIMessageSendErr => Error::MessageSendErr,
+            kMIDIServerStartErr => Error::ServerStartErr,
+            kMIDISetupFormatErr => Error::SetupFormatErr,
+            kMIDIWrongThread => Error::WrongThread,
+            kMIDIObjectNotFound => Error::ObjectNotFound,
+            kMIDIIDNotUnique => Error::IDNotUnique,
+            kMIDINotPermitted => Error::NotPermitted,
+            other => Error::Other(other)
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::InvalidClient => write!(f, "invalid client"),
+            Error::InvalidPort => write!(f, "invalid port"),
+            Error::WrongEndpointType => write!(f, "wrong endpoint type"),
+            Error::NoConnection => write!(f, "no connection"),
+            Error::UnknownEndpoint => write!(f, "unknown endpoint"),
+            Error::UnknownProperty => write!(f, "unknown property"),
+            Error::WrongPropertyType => write!(f, "wrong property type"),
+            Error::NoCurrentSetup => write!(f, "no current setup"),
+            Error::MessageSendErr => write!(f, "error sending the message"),
+            Error::ServerStartErr => write!(f, "error starting the MIDI server"),
+            Error::SetupFormatErr => write!(f, "wrong setup format"),
+            Error::WrongThread => write!(f, "called from the wrong thread"),
+            Error::ObjectNotFound => write!(f, "object not found"),
+            Error::IDNotUnique => write!(f, "unique id not unique"),
+            Error::NotPermitted => write!(f, "operation not permitted"),
+            Error::Other(status) => write!(f, "CoreMIDI error {}", status)
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+#[cfg(test)]
+mod tests {
+    use error::Error;
+
+    #[test]
+    fn error_from_status() {
+        assert_eq!(Error::from(-10830), Error::InvalidClient);
+        assert_eq!(Error::from(-10838), Error::MessageSendErr);
+        assert_eq!(Error::from(-10844), Error::NotPermitted);
+        assert_eq!(Error::from(-50), Error::Other(-50));
+    }
+
+    #[test]
+    fn error_code_round_trip() {
+        for status in -10850..-10820 {
+            assert_eq!(Error::from(status).code(), status);
+        }
+    }
+
+    #[test]
+    fn error_display() {
+        assert_eq!(format!("{}", Error::UnknownProperty), "unknown property");
+        assert_eq!(format!("{}", Error::Other(-50)), "CoreMIDI error -50");
+    }
+}
@@ -0,0 +1,333 @@
+use std::error;
+use std::fmt;
+
+use packets::Timestamp;
+
+// The size of the numberOfPackets(u32) field at the start of a packet list.
+const LIST_HEADER_SIZE: usize = 4;
+
+// The size of the timestamp(u64) and length(u16) fields at the start of a packet.
+const PACKET_HEADER_SIZE: usize = 10;
+
+/// How packets are aligned within a packet list.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacketAlignment {
+    /// Packets follow each other without any padding, as on x86 and x86_64.
+    Packed,
+    /// Packets start at 4 byte boundaries, as on arm and aarch64.
+    FourBytes,
+}
+
+/// The byte order of the packet list header and packet header fields.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteOrder {
+    LittleEndian,
+    BigEndian,
+}
+
+/// The memory layout of a [MIDIPacketList](https://developer.apple.com/reference/coremidi/midipacketlist).
+///
+/// A packet list consists of the number of packets (u32) followed by the packets, and every
+/// packet consists of a timestamp (u64), a data length (u16) and the data bytes. Depending on
+/// the architecture, every packet may need to start at a 4 byte boundary. This type encodes and
+/// decodes packet lists for any of the layouts regardless of the host architecture.
+///
+/// ```
+/// use coremidi::{PacketLayout, PacketAlignment, ByteOrder};
+/// let layout = PacketLayout::new(PacketAlignment::FourBytes, ByteOrder::LittleEndian);
+/// let bytes = layout.encode(&[(0, &[0x90, 0x40, 0x7f][..]), (1, &[0xf8][..])]);
+/// assert_eq!(bytes.len(), 4 + 16 + 12);
+/// assert_eq!(layout.decode(&bytes).unwrap(), vec![(0, &[0x90, 0x40, 0x7f][..]), (1, &[0xf8][..])]);
+/// ```
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PacketLayout {
+    pub alignment: PacketAlignment,
+    pub byte_order: ByteOrder,
+}
+
+/// An error found while decoding a packet list.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LayoutError {
+    /// The bytes are not 4 byte aligned, as required for a packet list.
+    Misaligned,
+    /// There are not enough bytes for the packet list header.
+    MissingHeader,
+    /// The header or the data of the packet with this index go beyond the end of the bytes.
+    PacketOutOfBounds(u32),
+}
+
+impl fmt::Display for LayoutError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LayoutError::Misaligned => write!(f, "misaligned packet list"),
+            LayoutError::MissingHeader => write!(f, "missing packet list header"),
+            LayoutError::PacketOutOfBounds(index) => write!(f, "packet {} out of bounds", index),
+        }
+    }
+}
+
+impl error::Error for LayoutError {}
+
+impl PacketLayout {
+    pub fn new(alignment: PacketAlignment, byte_order: ByteOrder) -> PacketLayout {
+        PacketLayout { alignment: alignment, byte_order: byte_order }
+    }
+
+    /// The layout used by CoreMIDI on the host architecture.
+    ///
+    #[inline(always)]
+    pub fn native() -> PacketLayout {
+        #[cfg(any(target_arch = "aarch64", target_arch = "arm"))]
+        let alignment = PacketAlignment::FourBytes;
+
+        #[cfg(not(any(target_arch = "aarch64", target_arch = "arm")))]
+        let alignment = PacketAlignment::Packed;
+
+        #[cfg(target_endian = "little")]
+        let byte_order = ByteOrder::LittleEndian;
+
+        #[cfg(target_endian = "big")]
+        let byte_order = ByteOrder::BigEndian;
+
+        PacketLayout { alignment: alignment, byte_order: byte_order }
+    }
+
+    /// Round up an offset (or address) to where the next packet can start.
+    ///
+    #[inline(always)]
+    pub fn align(&self, offset: usize) -> usize {
+        match self.alignment {
+            PacketAlignment::Packed => offset,
+            PacketAlignment::FourBytes => (offset + 3) & !3,
+        }
+    }
+
+    /// The number of bytes taken by a packet with `data_length` data bytes, including any padding.
+    ///
+    #[inline(always)]
+    pub fn packet_size(&self, data_length: usize) -> usize {
+        self.align(PACKET_HEADER_SIZE + data_length)
+    }
+
+    /// Write the number of packets into the first 4 bytes of a packet list.
+    ///
+    #[inline(always)]
+    pub fn write_list_header(&self, buf: &mut [u8], num_packets: u32) {
+        self.write_uint(&mut buf[..4], num_packets as u64);
+    }
+
+    /// Read the number of packets from the first 4 bytes of a packet list.
+    ///
+    #[inline(always)]
+    pub fn read_list_header(&self, buf: &[u8]) -> u32 {
+        self.read_uint(&buf[..4]) as u32
+    }
+
+    /// Write the timestamp and the data length into the first 10 bytes of a packet.
+    ///
+    #[inline(always)]
+    pub fn write_packet_header(&self, buf: &mut [u8], timestamp: Timestamp, data_length: u16) {
+        self.write_uint(&mut buf[..8], timestamp);
+        self.write_uint(&mut buf[8..10], data_length as u64);
+    }
+
+    /// Read the timestamp and the data length from the first 10 bytes of a packet.
+    ///
+    #[inline(always)]
+    pub fn read_packet_header(&self, buf: &[u8]) -> (Timestamp, u16) {
+        (self.read_uint(&buf[..8]), self.read_uint(&buf[8..10]) as u16)
+    }
+
+    /// Encode a list of packets. It panics if any of them has more than 65535 data bytes.
+    ///
+    /// Every packet takes [packet_size](#method.packet_size) bytes, including the padding after the last one,
+    /// as in a [PacketBuffer](struct.PacketBuffer.html).
+    ///
+    pub fn encode(&self, packets: &[(Timestamp, &[u8])]) -> Vec<u8> {
+        let mut bytes = vec![0u8; LIST_HEADER_SIZE];
+        self.write_list_header(&mut bytes, packets.len() as u32);
+        for &(timestamp, data) in packets {
+            assert!(data.len() <= u16::MAX as usize);
+            let offset = bytes.len();
+            bytes.resize(offset + self.packet_size(data.len()), 0);
+            self.write_packet_header(&mut bytes[offset..], timestamp, data.len() as u16);
+            bytes[offset + PACKET_HEADER_SIZE..offset + PACKET_HEADER_SIZE + data.len()].copy_from_slice(data);
+        }
+        bytes
+    }
+
+    /// Decode a list of packets, checking that all of them are within the bounds of `bytes`.
+    ///
+    pub fn decode<'a>(&self, bytes: &'a [u8]) -> Result<Vec<(Timestamp, &'a [u8])>, LayoutError> {
+        let mut packets = Vec::new();
+        self.walk(bytes, |timestamp, data| packets.push((timestamp, data)))?;
+        Ok(packets)
+    }
+
+    /// Check that the packet count, the data length of every packet and the padding between
+    /// them are all within the bounds of `bytes`, without allocating. It returns the number of packets.
+    ///
+    pub fn validate(&self, bytes: &[u8]) -> Result<u32, LayoutError> {
+        self.walk(bytes, |_, _| {})
+    }
+
+    fn walk<'a, F>(&self, bytes: &'a [u8], mut f: F) -> Result<u32, LayoutError>
+            where F: FnMut(Timestamp, &'a [u8]) {
+
+        if bytes.len() < LIST_HEADER_SIZE {
+            return Err(LayoutError::MissingHeader);
+        }
+
+        let num_packets = self.read_list_header(bytes);
+        let mut offset = LIST_HEADER_SIZE;
+        for index in 0..num_packets {
+            if bytes.len() < offset + PACKET_HEADER_SIZE {
+                return Err(LayoutError::PacketOutOfBounds(index));
+            }
+            let (timestamp, data_length) = self.read_packet_header(&bytes[offset..]);
+            let data_start = offset + PACKET_HEADER_SIZE;
+            let data_end = data_start + data_length as usize;
+            if bytes.len() < data_end {
+                return Err(LayoutError::PacketOutOfBounds(index));
+            }
+            f(timestamp, &bytes[data_start..data_end]);
+            offset = self.align(data_end);
+        }
+        Ok(num_packets)
+    }
+
+    fn write_uint(&self, buf: &mut [u8], value: u64) {
+        let length = buf.len();
+        for (i, byte) in buf.iter_mut().enumerate() {
+            let shift = match self.byte_order {
+                ByteOrder::LittleEndian => i,
+                ByteOrder::BigEndian => length - 1 - i,
+            };
+            *byte = (value >> (shift * 8)) as u8;
+        }
+    }
+
+    fn read_uint(&self, buf: &[u8]) -> u64 {
+        let length = buf.len();
+        buf.iter().enumerate().fold(0, |value, (i, &byte)| {
+            let shift = match self.byte_order {
+                ByteOrder::LittleEndian => i,
+                ByteOrder::BigEndian => length - 1 - i,
+            };
+            value | ((byte as u64) << (shift * 8))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use layout::{PacketLayout, PacketAlignment, ByteOrder, LayoutError};
+
+    const PACKED_LE: PacketLayout = PacketLayout { alignment: PacketAlignment::Packed, byte_order: ByteOrder::LittleEndian };
+    const ALIGNED_LE: PacketLayout = PacketLayout { alignment: PacketAlignment::FourBytes, byte_order: ByteOrder::LittleEndian };
+    const ALIGNED_BE: PacketLayout = PacketLayout { alignment: PacketAlignment::FourBytes, byte_order: ByteOrder::BigEndian };
+
+    const PACKETS: &'static [(u64, &'static [u8])] = &[
+        (0x0102030405060708, &[0x90, 0x40, 0x7f]),
+        (0x1112131415161718, &[0xf8])];
+
+    #[test]
+    fn encode_packed() {
+        assert_eq!(PACKED_LE.encode(PACKETS), vec![
+            0x02, 0x00, 0x00, 0x00,
+            0x08, 0x07, 0x06, 0x05, 0x04, 0x03, 0x02, 0x01,
+            0x03, 0x00,
+            0x90, 0x40, 0x7f,
+            0x18, 0x17, 0x16, 0x15, 0x14, 0x13, 0x12, 0x11,
+            0x01, 0x00,
+            0xf8]);
+    }
+
+    #[test]
+    fn encode_aligned() {
+        assert_eq!(ALIGNED_LE.encode(PACKETS), vec![
+            0x02, 0x00, 0x00, 0x00,
+            0x08, 0x07, 0x06, 0x05, 0x04, 0x03, 0x02, 0x01,
+            0x03, 0x00,
+            0x90, 0x40, 0x7f,
+            0x00, 0x00, 0x00,
+            0x18, 0x17, 0x16, 0x15, 0x14, 0x13, 0x12, 0x11,
+            0x01, 0x00,
+            0xf8,
+            0x00]);
+    }
+
+    #[test]
+    fn encode_big_endian() {
+        assert_eq!(ALIGNED_BE.encode(PACKETS), vec![
+            0x00, 0x00, 0x00, 0x02,
+            0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08,
+            0x00, 0x03,
+            0x90, 0x40, 0x7f,
+            0x00, 0x00, 0x00,
+            0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17, 0x18,
+            0x00, 0x01,
+            0xf8,
+            0x00]);
+    }
+
+    #[test]
+    fn encode_big_endian_packed() {
+        let layout = PacketLayout::new(PacketAlignment::Packed, ByteOrder::BigEndian);
+        assert_eq!(layout.encode(PACKETS), vec![
+            0x00, 0x00, 0x00, 0x02,
+            0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08,
+            0x00, 0x03,
+            0x90, 0x40, 0x7f,
+            0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17, 0x18,
+            0x00, 0x01,
+            0xf8]);
+    }
+
+    #[test]
+    fn encoded_size() {
+        for layout in &[PACKED_LE, ALIGNED_LE, ALIGNED_BE, PacketLayout::native()] {
+            assert_eq!(layout.encode(PACKETS).len(), 4 + layout.packet_size(3) + layout.packet_size(1));
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        for layout in &[PACKED_LE, ALIGNED_LE, ALIGNED_BE, PacketLayout::native()] {
+            assert_eq!(layout.decode(&layout.encode(PACKETS)).unwrap(), PACKETS.to_vec());
+            assert_eq!(layout.decode(&layout.encode(&[])).unwrap(), vec![]);
+        }
+    }
+
+    #[test]
+    fn packet_size() {
+        assert_eq!(PACKED_LE.packet_size(3), 13);
+        assert_eq!(ALIGNED_LE.packet_size(3), 16);
+        assert_eq!(ALIGNED_LE.packet_size(2), 12);
+    }
+
+    #[test]
+    fn decode_errors() {
+        let bytes = ALIGNED_LE.encode(PACKETS);
+        assert_eq!(ALIGNED_LE.decode(&bytes[..3]), Err(LayoutError::MissingHeader));
+        assert_eq!(ALIGNED_LE.decode(&bytes[..16]), Err(LayoutError::PacketOutOfBounds(0)));
+        assert_eq!(ALIGNED_LE.decode(&bytes[..bytes.len() - 2]), Err(LayoutError::PacketOutOfBounds(1)));
+        // The padding after the last packet is optional
+        assert_eq!(ALIGNED_LE.decode(&bytes[..bytes.len() - 1]).map(|packets| packets.len()), Ok(2));
+        // The packed layout expects the second packet right after the first one
+        assert_eq!(PACKED_LE.decode(&bytes), Err(LayoutError::PacketOutOfBounds(1)));
+    }
+
+    #[test]
+    fn validate() {
+        let bytes = ALIGNED_LE.encode(PACKETS);
+        assert_eq!(ALIGNED_LE.validate(&bytes), Ok(2));
+        assert_eq!(ALIGNED_LE.validate(&bytes[..20]), Err(LayoutError::PacketOutOfBounds(1)));
+        assert_eq!(PACKED_LE.validate(&[0, 0, 0, 0]), Ok(0));
+        assert_eq!(PACKED_LE.validate(&[0xff, 0xff, 0xff, 0xff]), Err(LayoutError::PacketOutOfBounds(0)));
+    }
+}
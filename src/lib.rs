@@ -38,23 +38,30 @@ For handling low level MIDI data you may look into:
 
 */
 
+#[cfg(target_os = "macos")]
 extern crate core_foundation_sys;
+#[cfg(target_os = "macos")]
 extern crate core_foundation;
+#[cfg(target_os = "macos")]
 extern crate coremidi_sys;
 extern crate libc;
+#[cfg(feature = "async")]
+extern crate futures;
 
-use core_foundation_sys::base::OSStatus;
+use sys::{MIDIObjectRef, MIDINotification};
 
-use coremidi_sys::{
-    MIDIObjectRef, MIDIFlushOutput, MIDIRestart
-};
+use std::sync::Arc;
+
+use notifications::NotificationSubscribers;
 
 /// A [MIDI Object](https://developer.apple.com/reference/coremidi/midiobjectref).
 ///
 /// The base class of many CoreMIDI objects.
 ///
-#[derive(PartialEq)]
-pub struct Object(MIDIObjectRef);
+/// An object keeps the backend it was created with, and uses it for all its operations.
+///
+#[derive(Clone)]
+pub struct Object(MIDIObjectRef, Arc<dyn Backend>);
 
 /// A [MIDI client](https://developer.apple.com/reference/coremidi/midiclientref).
 ///
@@ -69,9 +76,14 @@ pub struct Client {
     // Order is important, object needs to be dropped first
     object: Object,
     // Never used once set but needs to stay alive.
-    _callback: BoxedCallback<Box<FnMut(&Notification) + Send>>,
+    _callback: BoxedCallback<NotifyCallback>,
+    // Only for the clients created with a notify proc
+    notification_subscribers: Option<Arc<NotificationSubscribers>>,
 }
 
+// The callback of a client, which gets the raw notifications
+type NotifyCallback = Box<dyn FnMut(&MIDINotification) + Send>;
+
 // Object is Sync.
 // _callback will only be accessed
 //     (a) when receiving a notification (always on the thread that created the
@@ -92,6 +104,9 @@ fn _auto() {
 
     _assert_send::<InputPort>();
     _assert_sync::<InputPort>();
+
+    _assert_send::<PacketProducer>();
+    _assert_send::<PacketConsumer>();
 }
 
 // A lifetime-managed wrapper for callback functions
@@ -242,39 +257,102 @@ pub struct VirtualDestination {
 /// A MIDI device or external device, containing entities.
 ///
 #[derive(Debug)]
+#[derive(Clone)]
 #[derive(PartialEq)]
 pub struct Device { object: Object }
 
+/// A [MIDI entity](https://developer.apple.com/reference/coremidi/midientityref).
+///
+/// A part of a device, owning its sources and destinations, like one of the ports of a multi-port interface.
+///
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(PartialEq)]
+pub struct Entity { object: Object }
+
+/// A [MIDI thru connection](https://developer.apple.com/reference/coremidi/midithruconnectionref).
+///
+/// It sends the messages from some sources to some destinations within the MIDI server, optionally transforming
+/// and filtering them, without the process being involved. See [ThruConnectionParams](struct.ThruConnectionParams.html).
+///
+/// Dropping it doesn't dispose the connection, see [dispose](#method.dispose).
+///
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(PartialEq)]
+pub struct ThruConnection { object: Object }
+
+#[cfg(target_os = "macos")]
 mod coremidi_sys_ext;
+mod sys;
 
+mod error;
 mod object;
 mod devices;
+mod entities;
 mod client;
 mod ports;
 mod packets;
+mod messages;
+mod sysex;
+mod sysex_send;
+mod layout;
 mod properties;
 mod endpoints;
 mod notifications;
-pub use devices::Devices;
+mod backend;
+mod clock;
+mod time;
+mod smf;
+mod receiver;
+mod ring;
+mod thru;
+#[cfg(feature = "async")]
+mod stream;
+pub use error::Error;
+pub use object::ObjectType;
+pub use devices::{Devices, ExternalDevices};
 pub use endpoints::destinations::Destinations;
 pub use endpoints::sources::Sources;
-pub use packets::{PacketBuffer, DynPacketBuffer, FixedPacketBuffer};
-pub use packets::{PacketListRef, PacketListIterator, PacketRef};
-pub use properties::{Properties, PropertyGetter, PropertySetter};
+pub use endpoints::matching::{EndpointNames, MatchRank};
+pub use packets::{PacketBuffer, PacketBufferError, DynPacketBuffer, FixedPacketBuffer};
+pub use packets::{PacketList, PacketListRef, PacketListIterator, PacketRef, Timestamp};
+pub use messages::{MidiMessage, MessageError, MessagesIterator};
+pub use sysex::{SysExAssembler, SysExError, DEFAULT_MAX_SYSEX_SIZE};
+pub use sysex_send::{SysExRequest, SysExPoll, SysExProgress, SysExTransfer, DEFAULT_SYSEX_SPEED, DEFAULT_SYSEX_CHUNK_SIZE};
+pub use layout::{PacketLayout, PacketAlignment, ByteOrder, LayoutError};
+pub use properties::{Properties, PropertyGetter, PropertySetter, PropertyKey};
 pub use notifications::Notification;
+#[cfg(feature = "async")]
+pub use notifications::NotificationStream;
+pub use backend::{Backend, set_default_backend, with_backend};
+#[cfg(target_os = "macos")]
+pub use backend::CoreMidiBackend;
+pub use backend::memory::MemoryBackend;
+pub use backend::loopback::Loopback;
+pub use clock::{Clock, HostClock, ManualClock};
+pub use time::{HostTime, Timebase};
+pub use smf::{Smf, SmfError, SmfFormat, SmfTiming, SmfTrack, SmfEvent, SmfEventKind, MetaEvent, TempoMap, TimedEvent, SmfWriter};
+pub use receiver::{Receiver, TimestampedMessage, DEFAULT_RECEIVER_CAPACITY};
+pub use ring::{packet_ring, PacketProducer, PacketConsumer};
+pub use thru::{ThruConnectionParams, ThruControlTransform, ThruControlType, ThruEndpoint, ThruParamsError, ThruTransform, MAX_THRU_ENDPOINTS};
+#[cfg(feature = "async")]
+pub use stream::{PacketStream, OwnedPacket, OverflowPolicy, DEFAULT_STREAM_CAPACITY};
 
 /// Unschedules previously-sent packets for all the endpoints.
 /// See [MIDIFlushOutput](https://developer.apple.com/reference/coremidi/1495312-midiflushoutput).
 ///
-pub fn flush() -> Result<(), OSStatus> {
-    let status = unsafe { MIDIFlushOutput(0) };
-    if status == 0 { Ok(()) } else { Err(status) }
+/// The packets which timestamp has not been reached yet by the host [Clock](trait.Clock.html) are discarded.
+///
+pub fn flush() -> Result<(), Error> {
+    let status = backend::current().flush_output(0);
+    if status == 0 { Ok(()) } else { Err(Error::from(status)) }
 }
 
 /// Stops and restarts MIDI I/O.
 /// See [MIDIRestart](https://developer.apple.com/reference/coremidi/1495146-midirestart).
 ///
-pub fn restart() -> Result<(), OSStatus> {
-    let status = unsafe { MIDIRestart() };
-    if status == 0 { Ok(()) } else { Err(status) }
+pub fn restart() -> Result<(), Error> {
+    let status = backend::current().restart();
+    if status == 0 { Ok(()) } else { Err(Error::from(status)) }
 }
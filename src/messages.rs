@@ -0,0 +1,415 @@
+use std::error;
+use std::fmt;
+
+/// A [MIDI 1.0](https://www.midi.org/specifications) message decoded from the raw bytes of a packet.
+///
+/// Channels are zero based (0 to 15). The `SysEx` variant borrows the bytes from the packet,
+/// including the leading `F0` and the trailing `F7` when they are present. As a packet may
+/// only contain part of a SysEx message, a `SysEx` message might lack one or both of them.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MidiMessage<'a> {
+    NoteOff { channel: u8, note: u8, velocity: u8 },
+    NoteOn { channel: u8, note: u8, velocity: u8 },
+    PolyPressure { channel: u8, note: u8, pressure: u8 },
+    ControlChange { channel: u8, control: u8, value: u8 },
+    ProgramChange { channel: u8, program: u8 },
+    ChannelPressure { channel: u8, pressure: u8 },
+    PitchBend { channel: u8, value: u16 },
+    TimeCodeQuarterFrame(u8),
+    SongPositionPointer(u16),
+    SongSelect(u8),
+    TuneRequest,
+    TimingClock,
+    Start,
+    Continue,
+    Stop,
+    ActiveSensing,
+    SystemReset,
+    SysEx(&'a [u8]),
+}
+
+impl<'a> MidiMessage<'a> {
+    /// Get the channel for channel messages, or `None` for system messages.
+    ///
+    pub fn channel(&self) -> Option<u8> {
+        match *self {
+            MidiMessage::NoteOff { channel, .. } |
+            MidiMessage::NoteOn { channel, .. } |
+            MidiMessage::PolyPressure { channel, .. } |
+            MidiMessage::ControlChange { channel, .. } |
+            MidiMessage::ProgramChange { channel, .. } |
+            MidiMessage::ChannelPressure { channel, .. } |
+            MidiMessage::PitchBend { channel, .. } => Some(channel),
+            _ => None
+        }
+    }
+
+    /// Whether this is a system real-time message, which may appear anywhere in the stream,
+    /// even in the middle of a SysEx message.
+    ///
+    pub fn is_realtime(&self) -> bool {
+        matches!(*self,
+            MidiMessage::TimingClock |
+            MidiMessage::Start |
+            MidiMessage::Continue |
+            MidiMessage::Stop |
+            MidiMessage::ActiveSensing |
+            MidiMessage::SystemReset)
+    }
+
+    /// Get the number of bytes needed to encode the message.
+    ///
+    pub fn encoded_len(&self) -> usize {
+        match *self {
+            MidiMessage::NoteOff { .. } |
+            MidiMessage::NoteOn { .. } |
+            MidiMessage::PolyPressure { .. } |
+            MidiMessage::ControlChange { .. } |
+            MidiMessage::PitchBend { .. } |
+            MidiMessage::SongPositionPointer(_) => 3,
+            MidiMessage::ProgramChange { .. } |
+            MidiMessage::ChannelPressure { .. } |
+            MidiMessage::TimeCodeQuarterFrame(_) |
+            MidiMessage::SongSelect(_) => 2,
+            MidiMessage::SysEx(data) => data.len(),
+            _ => 1
+        }
+    }
+
+    /// Encode the message into the beginning of `buf`, returning the number of bytes written.
+    /// Channels and data values out of range are masked to their valid number of bits.
+    ///
+    /// It panics if `buf` is shorter than [encoded_len](#method.encoded_len).
+    ///
+    pub fn encode(&self, buf: &mut [u8]) -> usize {
+        let length = self.encoded_len();
+        let buf = &mut buf[..length];
+        match *self {
+            MidiMessage::NoteOff { channel, note, velocity } => Self::encode_short(buf, 0x80 | (channel & 0x0f), note, velocity),
+            MidiMessage::NoteOn { channel, note, velocity } => Self::encode_short(buf, 0x90 | (channel & 0x0f), note, velocity),
+            MidiMessage::PolyPressure { channel, note, pressure } => Self::encode_short(buf, 0xa0 | (channel & 0x0f), note, pressure),
+            MidiMessage::ControlChange { channel, control, value } => Self::encode_short(buf, 0xb0 | (channel & 0x0f), control, value),
+            MidiMessage::ProgramChange { channel, program } => Self::encode_short(buf, 0xc0 | (channel & 0x0f), program, 0),
+            MidiMessage::ChannelPressure { channel, pressure } => Self::encode_short(buf, 0xd0 | (channel & 0x0f), pressure, 0),
+            MidiMessage::PitchBend { channel, value } => Self::encode_short(buf, 0xe0 | (channel & 0x0f), value as u8, (value >> 7) as u8),
+            MidiMessage::TimeCodeQuarterFrame(value) => Self::encode_short(buf, 0xf1, value, 0),
+            MidiMessage::SongPositionPointer(value) => Self::encode_short(buf, 0xf2, value as u8, (value >> 7) as u8),
+            MidiMessage::SongSelect(song) => Self::encode_short(buf, 0xf3, song, 0),
+            MidiMessage::TuneRequest => buf[0] = 0xf6,
+            MidiMessage::TimingClock => buf[0] = 0xf8,
+            MidiMessage::Start => buf[0] = 0xfa,
+            MidiMessage::Continue => buf[0] = 0xfb,
+            MidiMessage::Stop => buf[0] = 0xfc,
+            MidiMessage::ActiveSensing => buf[0] = 0xfe,
+            MidiMessage::SystemReset => buf[0] = 0xff,
+            MidiMessage::SysEx(data) => buf.copy_from_slice(data),
+        }
+        length
+    }
+
+    // Writes as many of the status and data bytes as `buf` has room for.
+    fn encode_short(buf: &mut [u8], status: u8, data1: u8, data2: u8) {
+        let bytes = [status, data1 & 0x7f, data2 & 0x7f];
+        let length = buf.len();
+        buf.copy_from_slice(&bytes[..length]);
+    }
+}
+
+/// An error found while decoding MIDI messages from raw bytes.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageError {
+    /// A data byte was found where a status byte was expected.
+    UnexpectedData(u8),
+    /// An `F7` (End of Exclusive) was found outside of a SysEx message.
+    UnexpectedEndOfSysEx,
+    /// The message with this status byte is missing some of its data bytes.
+    Truncated(u8),
+    /// The status byte is not defined by the MIDI 1.0 specification.
+    UndefinedStatus(u8),
+}
+
+impl fmt::Display for MessageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MessageError::UnexpectedData(byte) => write!(f, "unexpected data byte {:02x}", byte),
+            MessageError::UnexpectedEndOfSysEx => write!(f, "unexpected end of SysEx"),
+            MessageError::Truncated(status) => write!(f, "truncated message with status {:02x}", status),
+            MessageError::UndefinedStatus(status) => write!(f, "undefined status byte {:02x}", status),
+        }
+    }
+}
+
+impl error::Error for MessageError {}
+
+/// An iterator over the MIDI messages contained in the data of a packet.
+///
+/// It doesn't allocate, and it resynchronises with the next status byte after an error.
+/// Data bytes found at the beginning are considered to be the continuation of a SysEx message
+/// started in a previous packet.
+///
+/// See [PacketRef::messages](struct.PacketRef.html#method.messages).
+///
+pub struct MessagesIterator<'a> {
+    data: &'a [u8],
+    in_sysex: bool,
+}
+
+impl<'a> MessagesIterator<'a> {
+    /// Create an iterator over the messages found in some raw MIDI bytes.
+    ///
+    pub fn new(data: &'a [u8]) -> MessagesIterator<'a> {
+        MessagesIterator { data: data, in_sysex: true }
+    }
+
+    fn decode(&mut self) -> (Result<MidiMessage<'a>, MessageError>, usize) {
+        let data = self.data;
+        let status = data[0];
+        match status {
+            0x00 ..= 0x7f => {
+                let length = Self::data_length(data, 0);
+                if self.in_sysex {
+                    self.decode_sysex(data, length)
+                } else {
+                    (Err(MessageError::UnexpectedData(status)), length)
+                }
+            },
+            0xf0 => {
+                let length = 1 + Self::data_length(data, 1);
+                self.in_sysex = true;
+                self.decode_sysex(data, length)
+            },
+            0xf7 => {
+                if self.in_sysex {
+                    self.in_sysex = false;
+                    (Ok(MidiMessage::SysEx(&data[..1])), 1)
+                } else {
+                    (Err(MessageError::UnexpectedEndOfSysEx), 1)
+                }
+            },
+            0xf8 => (Ok(MidiMessage::TimingClock), 1),
+            0xfa => (Ok(MidiMessage::Start), 1),
+            0xfb => (Ok(MidiMessage::Continue), 1),
+            0xfc => (Ok(MidiMessage::Stop), 1),
+            0xfe => (Ok(MidiMessage::ActiveSensing), 1),
+            0xff => (Ok(MidiMessage::SystemReset), 1),
+            0xf9 | 0xfd => (Err(MessageError::UndefinedStatus(status)), 1),
+            _ => {
+                // Any other status byte terminates an unfinished SysEx
+                self.in_sysex = false;
+                Self::decode_short(data)
+            }
+        }
+    }
+
+    fn decode_short(data: &'a [u8]) -> (Result<MidiMessage<'a>, MessageError>, usize) {
+        let status = data[0];
+        let required = match status {
+            0x80 ..= 0xbf | 0xe0 ..= 0xef | 0xf2 => 2,
+            0xc0 ..= 0xdf | 0xf1 | 0xf3 => 1,
+            0xf6 => 0,
+            _ => return (Err(MessageError::UndefinedStatus(status)), 1)
+        };
+
+        let available = Self::data_length(data, 1);
+        if available < required {
+            return (Err(MessageError::Truncated(status)), 1 + available);
+        }
+
+        let channel = status & 0x0f;
+        let message = match status {
+            0x80 ..= 0x8f => MidiMessage::NoteOff { channel: channel, note: data[1], velocity: data[2] },
+            0x90 ..= 0x9f => MidiMessage::NoteOn { channel: channel, note: data[1], velocity: data[2] },
+            0xa0 ..= 0xaf => MidiMessage::PolyPressure { channel: channel, note: data[1], pressure: data[2] },
+            0xb0 ..= 0xbf => MidiMessage::ControlChange { channel: channel, control: data[1], value: data[2] },
+            0xc0 ..= 0xcf => MidiMessage::ProgramChange { channel: channel, program: data[1] },
+            0xd0 ..= 0xdf => MidiMessage::ChannelPressure { channel: channel, pressure: data[1] },
+            0xe0 ..= 0xef => MidiMessage::PitchBend { channel: channel, value: Self::u14(data[1], data[2]) },
+            0xf1 => MidiMessage::TimeCodeQuarterFrame(data[1]),
+            0xf2 => MidiMessage::SongPositionPointer(Self::u14(data[1], data[2])),
+            0xf3 => MidiMessage::SongSelect(data[1]),
+            _ => MidiMessage::TuneRequest,
+        };
+        (Ok(message), 1 + required)
+    }
+
+    // The number of consecutive data bytes starting at `start`.
+    fn data_length(data: &[u8], start: usize) -> usize {
+        data[start..].iter().take_while(|&&b| b < 0x80).count()
+    }
+
+    // A SysEx message (or part of one) with `length` bytes, plus the End of Exclusive if present.
+    fn decode_sysex(&mut self, data: &'a [u8], length: usize) -> (Result<MidiMessage<'a>, MessageError>, usize) {
+        let end = if data.len() > length && data[length] == 0xf7 {
+            self.in_sysex = false;
+            length + 1
+        } else {
+            length
+        };
+        (Ok(MidiMessage::SysEx(&data[..end])), end)
+    }
+
+    #[inline(always)]
+    fn u14(lsb: u8, msb: u8) -> u16 {
+        ((msb as u16) << 7) | (lsb as u16)
+    }
+}
+
+impl<'a> Iterator for MessagesIterator<'a> {
+    type Item = Result<MidiMessage<'a>, MessageError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.data.is_empty() {
+            return None;
+        }
+        let (result, consumed) = self.decode();
+        self.data = &self.data[consumed..];
+        Some(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use messages::{MidiMessage, MessageError, MessagesIterator};
+    use PacketBuffer;
+
+    fn decode<'a>(data: &'a [u8]) -> Vec<Result<MidiMessage<'a>, MessageError>> {
+        MessagesIterator::new(data).collect()
+    }
+
+    #[test]
+    fn decode_channel_messages() {
+        assert_eq!(decode(&[0x80, 0x40, 0x10]),
+                   vec![Ok(MidiMessage::NoteOff { channel: 0, note: 0x40, velocity: 0x10 })]);
+        assert_eq!(decode(&[0x9f, 0x40, 0x7f]),
+                   vec![Ok(MidiMessage::NoteOn { channel: 15, note: 0x40, velocity: 0x7f })]);
+        assert_eq!(decode(&[0xa1, 0x3c, 0x20]),
+                   vec![Ok(MidiMessage::PolyPressure { channel: 1, note: 0x3c, pressure: 0x20 })]);
+        assert_eq!(decode(&[0xb2, 0x07, 0x64]),
+                   vec![Ok(MidiMessage::ControlChange { channel: 2, control: 0x07, value: 0x64 })]);
+        assert_eq!(decode(&[0xc3, 0x05]),
+                   vec![Ok(MidiMessage::ProgramChange { channel: 3, program: 0x05 })]);
+        assert_eq!(decode(&[0xd4, 0x33]),
+                   vec![Ok(MidiMessage::ChannelPressure { channel: 4, pressure: 0x33 })]);
+        assert_eq!(decode(&[0xe5, 0x00, 0x40]),
+                   vec![Ok(MidiMessage::PitchBend { channel: 5, value: 0x2000 })]);
+    }
+
+    #[test]
+    fn decode_system_messages() {
+        assert_eq!(decode(&[0xf1, 0x12, 0xf2, 0x7f, 0x7f, 0xf3, 0x03, 0xf6]), vec![
+            Ok(MidiMessage::TimeCodeQuarterFrame(0x12)),
+            Ok(MidiMessage::SongPositionPointer(0x3fff)),
+            Ok(MidiMessage::SongSelect(0x03)),
+            Ok(MidiMessage::TuneRequest)]);
+        assert_eq!(decode(&[0xf8, 0xfa, 0xfb, 0xfc, 0xfe, 0xff]), vec![
+            Ok(MidiMessage::TimingClock),
+            Ok(MidiMessage::Start),
+            Ok(MidiMessage::Continue),
+            Ok(MidiMessage::Stop),
+            Ok(MidiMessage::ActiveSensing),
+            Ok(MidiMessage::SystemReset)]);
+    }
+
+    #[test]
+    fn decode_multiple_messages() {
+        assert_eq!(decode(&[0x90, 0x40, 0x7f, 0xf8, 0x91, 0x41, 0x7f]), vec![
+            Ok(MidiMessage::NoteOn { channel: 0, note: 0x40, velocity: 0x7f }),
+            Ok(MidiMessage::TimingClock),
+            Ok(MidiMessage::NoteOn { channel: 1, note: 0x41, velocity: 0x7f })]);
+    }
+
+    #[test]
+    fn decode_sysex() {
+        assert_eq!(decode(&[0xf0, 0x7e, 0x00, 0xf7]),
+                   vec![Ok(MidiMessage::SysEx(&[0xf0, 0x7e, 0x00, 0xf7]))]);
+        assert_eq!(decode(&[0xf0, 0x7e, 0x00]),
+                   vec![Ok(MidiMessage::SysEx(&[0xf0, 0x7e, 0x00]))]);
+        assert_eq!(decode(&[0x01, 0x02, 0xf7]),
+                   vec![Ok(MidiMessage::SysEx(&[0x01, 0x02, 0xf7]))]);
+    }
+
+    #[test]
+    fn decode_sysex_with_realtime() {
+        assert_eq!(decode(&[0xf0, 0x01, 0xf8, 0x02, 0xf7, 0x90, 0x40, 0x7f]), vec![
+            Ok(MidiMessage::SysEx(&[0xf0, 0x01])),
+            Ok(MidiMessage::TimingClock),
+            Ok(MidiMessage::SysEx(&[0x02, 0xf7])),
+            Ok(MidiMessage::NoteOn { channel: 0, note: 0x40, velocity: 0x7f })]);
+    }
+
+    #[test]
+    fn decode_errors() {
+        assert_eq!(decode(&[0x90, 0x40, 0x7f, 0x40, 0x7f, 0xf8]), vec![
+            Ok(MidiMessage::NoteOn { channel: 0, note: 0x40, velocity: 0x7f }),
+            Err(MessageError::UnexpectedData(0x40)),
+            Ok(MidiMessage::TimingClock)]);
+        assert_eq!(decode(&[0x90, 0x40, 0xf8]), vec![
+            Err(MessageError::Truncated(0x90)),
+            Ok(MidiMessage::TimingClock)]);
+        assert_eq!(decode(&[0xf4, 0xfd, 0xf8]), vec![
+            Err(MessageError::UndefinedStatus(0xf4)),
+            Err(MessageError::UndefinedStatus(0xfd)),
+            Ok(MidiMessage::TimingClock)]);
+        assert_eq!(decode(&[0xf8, 0xf6, 0xf7]), vec![
+            Ok(MidiMessage::TimingClock),
+            Ok(MidiMessage::TuneRequest),
+            Err(MessageError::UnexpectedEndOfSysEx)]);
+    }
+
+    #[test]
+    fn packet_messages() {
+        let mut packet_buf = PacketBuffer::dyn();
+        packet_buf.push_packet(0, &[0x90, 0x40, 0x7f, 0x80, 0x40, 0x00]);
+        let packet_list = packet_buf.as_ref();
+        let packet = packet_list.iter().next().unwrap();
+        assert_eq!(packet.messages().collect::<Vec<_>>(), vec![
+            Ok(MidiMessage::NoteOn { channel: 0, note: 0x40, velocity: 0x7f }),
+            Ok(MidiMessage::NoteOff { channel: 0, note: 0x40, velocity: 0x00 })]);
+    }
+
+    #[test]
+    fn encode_messages() {
+        let messages = [
+            MidiMessage::NoteOff { channel: 0, note: 0x40, velocity: 0x10 },
+            MidiMessage::NoteOn { channel: 15, note: 0x40, velocity: 0x7f },
+            MidiMessage::PolyPressure { channel: 1, note: 0x3c, pressure: 0x20 },
+            MidiMessage::ControlChange { channel: 2, control: 0x07, value: 0x64 },
+            MidiMessage::ProgramChange { channel: 3, program: 0x05 },
+            MidiMessage::ChannelPressure { channel: 4, pressure: 0x33 },
+            MidiMessage::PitchBend { channel: 5, value: 0x2001 },
+            MidiMessage::TimeCodeQuarterFrame(0x12),
+            MidiMessage::SongPositionPointer(0x3fff),
+            MidiMessage::SongSelect(0x03),
+            MidiMessage::TuneRequest,
+            MidiMessage::TimingClock,
+            MidiMessage::SystemReset,
+            MidiMessage::SysEx(&[0xf0, 0x7e, 0x00, 0xf7])];
+        let mut buf = [0u8; 64];
+        let mut length = 0;
+        for message in messages.iter() {
+            length += message.encode(&mut buf[length..]);
+        }
+        assert_eq!(&buf[..length], &[
+            0x80, 0x40, 0x10, 0x9f, 0x40, 0x7f, 0xa1, 0x3c, 0x20, 0xb2, 0x07, 0x64,
+            0xc3, 0x05, 0xd4, 0x33, 0xe5, 0x01, 0x40, 0xf1, 0x12, 0xf2, 0x7f, 0x7f,
+            0xf3, 0x03, 0xf6, 0xf8, 0xff, 0xf0, 0x7e, 0x00, 0xf7][..]);
+        assert_eq!(decode(&buf[..length]).into_iter().map(Result::unwrap).collect::<Vec<_>>(), messages.to_vec());
+    }
+
+    #[test]
+    fn encode_masks_values() {
+        let mut buf = [0u8; 3];
+        MidiMessage::NoteOn { channel: 0x13, note: 0xc0, velocity: 0xff }.encode(&mut buf);
+        assert_eq!(buf, [0x93, 0x40, 0x7f]);
+    }
+
+    #[test]
+    fn message_channel() {
+        assert_eq!(MidiMessage::ProgramChange { channel: 9, program: 0 }.channel(), Some(9));
+        assert_eq!(MidiMessage::TimingClock.channel(), None);
+        assert!(MidiMessage::ActiveSensing.is_realtime());
+        assert!(!MidiMessage::TuneRequest.is_realtime());
+    }
+}
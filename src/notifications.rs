@@ -1,9 +1,7 @@
 #![allow(non_upper_case_globals)]
 
-use core_foundation::string::{CFString, CFStringRef};
-use core_foundation::base::{TCFType, OSStatus};
-
-use coremidi_sys::{
+use sys::{
+    OSStatus,
     MIDINotification,
     MIDIObjectAddRemoveNotification,
     MIDIObjectPropertyChangeNotification,
@@ -14,14 +12,33 @@ use coremidi_sys::{
     kMIDIMsgPropertyChanged,
     kMIDIMsgThruConnectionsChanged,
     kMIDIMsgSerialPortOwnerChanged,
-    kMIDIMsgIOError
+    kMIDIMsgIOError,
+    string_from_cfstring
 };
 
+#[cfg(feature = "async")]
+use futures::channel::mpsc;
+#[cfg(feature = "async")]
+use futures::{Stream, StreamExt};
+
+use std::sync::{Arc, Mutex};
+#[cfg(feature = "async")]
+use std::cmp;
+#[cfg(feature = "async")]
+use std::pin::Pin;
+#[cfg(feature = "async")]
+use std::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(feature = "async")]
+use std::task::{Context, Poll};
+
 use Object;
+use backend::{self, Backend};
 use object::ObjectType;
 use Device;
+use receiver::{self, Receiver};
 
 #[derive(Debug)]
+#[derive(Clone)]
 #[derive(PartialEq)]
 pub struct AddedRemovedInfo {
     pub parent: Object,
@@ -31,6 +48,7 @@ pub struct AddedRemovedInfo {
 }
 
 #[derive(Debug)]
+#[derive(Clone)]
 #[derive(PartialEq)]
 pub struct PropertyChangedInfo {
     pub object: Object,
@@ -39,6 +57,7 @@ pub struct PropertyChangedInfo {
 }
 
 #[derive(Debug)]
+#[derive(Clone)]
 #[derive(PartialEq)]
 pub struct IOErrorInfo {
     pub driver_device: Device,
@@ -49,6 +68,7 @@ pub struct IOErrorInfo {
 /// See [MIDINotification](https://developer.apple.com/reference/coremidi/midinotification).
 ///
 #[derive(Debug)]
+#[derive(Clone)]
 #[derive(PartialEq)]
 pub enum Notification {
     SetupChanged,
@@ -62,26 +82,32 @@ pub enum Notification {
 
 impl Notification {
     pub fn from(notification: &MIDINotification) -> Result<Notification, i32> {
+        Self::from_backend(notification, &backend::current())
+    }
+
+    /// Like `from`, for a notification of the objects of a backend.
+    ///
+    pub(crate) fn from_backend(notification: &MIDINotification, backend: &Arc<dyn Backend>) -> Result<Notification, i32> {
         match notification.messageID as ::libc::c_uint {
             kMIDIMsgSetupChanged => Ok(Notification::SetupChanged),
-            kMIDIMsgObjectAdded | kMIDIMsgObjectRemoved => Self::from_object_added_removed(notification),
-            kMIDIMsgPropertyChanged => Self::from_property_changed(notification),
+            kMIDIMsgObjectAdded | kMIDIMsgObjectRemoved => Self::from_object_added_removed(notification, backend),
+            kMIDIMsgPropertyChanged => Self::from_property_changed(notification, backend),
             kMIDIMsgThruConnectionsChanged => Ok(Notification::ThruConnectionsChanged),
             kMIDIMsgSerialPortOwnerChanged => Ok(Notification::SerialPortOwnerChanged),
-            kMIDIMsgIOError => Self::from_io_error(notification),
+            kMIDIMsgIOError => Self::from_io_error(notification, backend),
             unknown => Err(unknown as i32)
         }
     }
 
-    fn from_object_added_removed(notification: &MIDINotification) -> Result<Notification, i32> {
+    fn from_object_added_removed(notification: &MIDINotification, backend: &Arc<dyn Backend>) -> Result<Notification, i32> {
         let add_remove_notification = unsafe { &*(notification as *const _ as *const MIDIObjectAddRemoveNotification) };
         let parent_type = ObjectType::from(add_remove_notification.parentType);
         let child_type = ObjectType::from(add_remove_notification.childType);
         if parent_type.is_ok() && child_type.is_ok() {
             let add_remove_info = AddedRemovedInfo {
-                parent: Object(add_remove_notification.parent),
+                parent: Object(add_remove_notification.parent, backend.clone()),
                 parent_type: parent_type.unwrap(),
-                child: Object(add_remove_notification.child),
+                child: Object(add_remove_notification.child, backend.clone()),
                 child_type: child_type.unwrap()
             };
             match notification.messageID as ::libc::c_uint {
@@ -93,17 +119,13 @@ impl Notification {
         else { Err(notification.messageID as i32) }
     }
 
-    fn from_property_changed(notification: &MIDINotification) -> Result<Notification, i32> {
+    fn from_property_changed(notification: &MIDINotification, backend: &Arc<dyn Backend>) -> Result<Notification, i32> {
         let property_changed_notification = unsafe { &*(notification as *const _ as *const MIDIObjectPropertyChangeNotification) };
         match ObjectType::from(property_changed_notification.objectType) {
             Ok(object_type) => {
-                let property_name = {
-                    let name_ref: CFStringRef = property_changed_notification.propertyName;
-                    let name: CFString = unsafe { TCFType::wrap_under_create_rule(name_ref) };
-                    format!("{}", name)
-                };
+                let property_name = unsafe { string_from_cfstring(property_changed_notification.propertyName) };
                 let property_changed_info = PropertyChangedInfo {
-                    object: Object(property_changed_notification.object),
+                    object: Object(property_changed_notification.object, backend.clone()),
                     object_type: object_type,
                     property_name: property_name
                 };
@@ -114,23 +136,112 @@ impl Notification {
 
     }
 
-    fn from_io_error(notification: &MIDINotification) -> Result<Notification, i32> {
+    fn from_io_error(notification: &MIDINotification, backend: &Arc<dyn Backend>) -> Result<Notification, i32> {
         let io_error_notification = unsafe { &*(notification as *const _ as *const MIDIIOErrorNotification) };
         let io_error_info = IOErrorInfo {
-            driver_device: Device { object: Object(io_error_notification.driverDevice) },
+            driver_device: Device { object: Object(io_error_notification.driverDevice, backend.clone()) },
             error_code: io_error_notification.errorCode
         };
         Ok(Notification::IOError(io_error_info))
     }
 }
 
+/// A stream of the notifications of a client.
+/// See [Client::notifications](struct.Client.html#method.notifications).
+///
+/// As with a [Receiver](struct.Receiver.html), the notifications are dropped when the stream is full,
+/// and counted as overflows.
+///
+#[cfg(feature = "async")]
+pub struct NotificationStream {
+    receiver: mpsc::Receiver<Notification>,
+    overflows: Arc<AtomicUsize>,
+}
+
+#[cfg(feature = "async")]
+impl NotificationStream {
+    /// The number of notifications that were dropped because the stream was full.
+    ///
+    pub fn overflow_count(&self) -> usize {
+        self.overflows.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(feature = "async")]
+impl Stream for NotificationStream {
+    type Item = Notification;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Notification>> {
+        self.receiver.poll_next_unpin(cx)
+    }
+}
+
+// Every subscriber returns whether it is still interested in notifications.
+type Subscriber = Box<dyn FnMut(&Notification) -> bool + Send>;
+
+/// The receivers and streams of notifications created from a client.
+///
+pub struct NotificationSubscribers {
+    subscribers: Mutex<Vec<Subscriber>>,
+}
+
+impl NotificationSubscribers {
+    pub fn new() -> NotificationSubscribers {
+        NotificationSubscribers { subscribers: Mutex::new(Vec::new()) }
+    }
+
+    pub fn receiver(&self, capacity: usize) -> Receiver<Notification> {
+        let (sender, receiver) = receiver::channel(capacity);
+        self.subscribe(move |notification| sender.send(notification.clone()));
+        receiver
+    }
+
+    // The channel has room for one value per sender on top of its buffer, and there is only one sender.
+    // A capacity of 0 is taken as 1, like for the receivers.
+    #[cfg(feature = "async")]
+    pub fn stream(&self, capacity: usize) -> NotificationStream {
+        let (mut sender, receiver) = mpsc::channel(cmp::max(capacity, 1) - 1);
+        let overflows = Arc::new(AtomicUsize::new(0));
+        let sender_overflows = overflows.clone();
+        self.subscribe(move |notification| match sender.try_send(notification.clone()) {
+            Ok(()) => true,
+            Err(ref error) if error.is_full() => {
+                sender_overflows.fetch_add(1, Ordering::Relaxed);
+                true
+            },
+            Err(_) => false
+        });
+        NotificationStream { receiver: receiver, overflows: overflows }
+    }
+
+    fn subscribe<F>(&self, subscriber: F) where F: FnMut(&Notification) -> bool + Send + 'static {
+        self.subscribers.lock().unwrap().push(Box::new(subscriber));
+    }
+
+    pub fn dispatch(&self, notification: &Notification) {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        let mut index = 0;
+        while index < subscribers.len() {
+            if (subscribers[index])(notification) {
+                index += 1;
+            } else {
+                drop(subscribers.remove(index));
+            }
+        }
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.subscribers.lock().unwrap().len()
+    }
+}
+
 #[cfg(test)]
 mod tests {
 
-    use core_foundation::string::CFString;
-    use core_foundation::base::{TCFType, OSStatus};
-
-    use coremidi_sys::{
+    use sys::{
+        OSStatus,
+        CFStringRef,
         MIDIObjectRef,
         MIDINotification,
         MIDINotificationMessageID,
@@ -149,8 +260,26 @@ mod tests {
 
     use Object;
     use Device;
+    use backend;
     use object::ObjectType;
-    use notifications::{Notification, AddedRemovedInfo, PropertyChangedInfo, IOErrorInfo};
+    use notifications::{Notification, AddedRemovedInfo, PropertyChangedInfo, IOErrorInfo, NotificationSubscribers};
+
+    // A property name as CoreMIDI passes it, which is leaked so it outlives the notification.
+    #[cfg(target_os = "macos")]
+    fn property_name(name: &'static str) -> CFStringRef {
+        use core_foundation::base::TCFType;
+        use core_foundation::string::CFString;
+
+        let name = CFString::from_static_string(name);
+        let name_ref = name.as_concrete_TypeRef();
+        ::std::mem::forget(name);
+        name_ref
+    }
+
+    #[cfg(not(target_os = "macos"))]
+    fn property_name(name: &'static str) -> CFStringRef {
+        ::std::ffi::CString::new(name).unwrap().into_raw()
+    }
 
     #[test]
     fn notification_from_error() {
@@ -191,9 +320,9 @@ mod tests {
         assert!(notification.is_ok());
 
         let info = AddedRemovedInfo {
-            parent: Object(1),
+            parent: Object(1, backend::current()),
             parent_type: ObjectType::Device,
-            child: Object(2),
+            child: Object(2, backend::current()),
             child_type: ObjectType::Other
         };
 
@@ -217,9 +346,9 @@ mod tests {
         assert!(notification.is_ok());
 
         let info = AddedRemovedInfo {
-            parent: Object(1),
+            parent: Object(1, backend::current()),
             parent_type: ObjectType::Device,
-            child: Object(2),
+            child: Object(2, backend::current()),
             child_type: ObjectType::Other
         };
 
@@ -266,7 +395,7 @@ mod tests {
             messageSize: 24,
             object: 1 as MIDIObjectRef,
             objectType: kMIDIObjectType_Device,
-            propertyName: CFString::new("name").as_concrete_TypeRef()
+            propertyName: property_name("name")
         };
 
         let notification = Notification::from(
@@ -275,7 +404,7 @@ mod tests {
         assert!(notification.is_ok());
 
         let info = PropertyChangedInfo {
-            object: Object(1),
+            object: Object(1, backend::current()),
             object_type: ObjectType::Device,
             property_name: "name".to_string()
         };
@@ -290,7 +419,7 @@ mod tests {
             messageSize: 24,
             object: 1 as MIDIObjectRef,
             objectType: 0xffff,
-            propertyName: CFString::new("name").as_concrete_TypeRef()
+            propertyName: property_name("name")
         };
 
         let notification = Notification::from(
@@ -337,10 +466,90 @@ mod tests {
         assert!(notification.is_ok());
 
         let info = IOErrorInfo {
-            driver_device: Device { object: Object(1) },
+            driver_device: Device { object: Object(1, backend::current()) },
             error_code: 123 as OSStatus
         };
 
         assert_eq!(notification.unwrap(), Notification::IOError(info));
     }
+
+    #[test]
+    fn notification_subscribers_receiver() {
+        let subscribers = NotificationSubscribers::new();
+        let receiver = subscribers.receiver(8);
+
+        let notification_raw = MIDIObjectAddRemoveNotification {
+            messageID: kMIDIMsgObjectAdded as MIDINotificationMessageID,
+            messageSize: 24,
+            parent: 1 as MIDIObjectRef,
+            parentType: kMIDIObjectType_Device,
+            child: 2 as MIDIObjectRef,
+            childType: kMIDIObjectType_Other
+        };
+        let notification = Notification::from(
+            unsafe { &*(&notification_raw as *const _ as *const MIDINotification) }).unwrap();
+        subscribers.dispatch(&notification);
+
+        let setup_changed_raw = MIDINotification {
+            messageID: kMIDIMsgSetupChanged as MIDINotificationMessageID,
+            messageSize: 8
+        };
+        subscribers.dispatch(&Notification::from(&setup_changed_raw).unwrap());
+
+        assert_eq!(receiver.try_iter().collect::<Vec<_>>(), vec![notification, Notification::SetupChanged]);
+    }
+
+    #[test]
+    fn notification_subscribers_dropped() {
+        let subscribers = NotificationSubscribers::new();
+        let receiver1 = subscribers.receiver(8);
+        let receiver2 = subscribers.receiver(8);
+        assert_eq!(subscribers.len(), 2);
+
+        drop(receiver1);
+        subscribers.dispatch(&Notification::ThruConnectionsChanged);
+        assert_eq!(subscribers.len(), 1);
+        assert_eq!(receiver2.try_recv(), Ok(Notification::ThruConnectionsChanged));
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn notification_subscribers_stream() {
+        use futures::executor::block_on;
+        use futures::StreamExt;
+
+        let subscribers = NotificationSubscribers::new();
+        let stream = subscribers.stream(8);
+
+        let notification_raw = MIDIIOErrorNotification {
+            messageID: kMIDIMsgIOError as MIDINotificationMessageID,
+            messageSize: 16,
+            driverDevice: 1 as MIDIObjectRef,
+            errorCode: 123 as OSStatus
+        };
+        let notification = Notification::from(
+            unsafe { &*(&notification_raw as *const _ as *const MIDINotification) }).unwrap();
+        subscribers.dispatch(&notification);
+        subscribers.dispatch(&Notification::SerialPortOwnerChanged);
+        drop(subscribers);
+
+        assert_eq!(block_on(stream.collect::<Vec<_>>()), vec![notification, Notification::SerialPortOwnerChanged]);
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn notification_subscribers_stream_is_bounded() {
+        use futures::executor::block_on;
+        use futures::StreamExt;
+
+        let subscribers = NotificationSubscribers::new();
+        let stream = subscribers.stream(2);
+        subscribers.dispatch(&Notification::SetupChanged);
+        subscribers.dispatch(&Notification::ThruConnectionsChanged);
+        subscribers.dispatch(&Notification::SerialPortOwnerChanged);
+        assert_eq!(stream.overflow_count(), 1);
+        drop(subscribers);
+
+        assert_eq!(block_on(stream.collect::<Vec<_>>()), vec![Notification::SetupChanged, Notification::ThruConnectionsChanged]);
+    }
 }
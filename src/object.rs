@@ -1,8 +1,6 @@
 #![allow(non_upper_case_globals)]
 
-use core_foundation_sys::base::OSStatus;
-
-use coremidi_sys::{
+use sys::{
     SInt32,
     kMIDIObjectType_Other,
     kMIDIObjectType_Device,
@@ -16,14 +14,20 @@ use coremidi_sys::{
 };
 
 use std::fmt;
+use std::sync::Arc;
+
+use sys::MIDIObjectRef;
 
 use Object;
+use Error;
+use backend::{self, Backend};
 use properties::{
     PropertyGetter, PropertySetter, Properties,
     StringProperty, IntegerProperty, BooleanProperty
 };
 
 #[derive(Debug)]
+#[derive(Clone)]
 #[derive(PartialEq)]
 pub enum ObjectType {
     Other,
@@ -55,6 +59,52 @@ impl ObjectType {
 }
 
 impl Object {
+    /// Find an object from its unique id, together with its type.
+    /// See [MIDIObjectFindByUniqueID](https://developer.apple.com/reference/coremidi/midiobjectfindbyuniqueid).
+    ///
+    /// Unlike the indices, the unique ids are kept when devices are plugged or unplugged,
+    /// so they can be stored to find the objects later. The object can be converted to a more
+    /// specific type with `TryFrom`, which checks its type:
+    ///
+    /// ```rust,no_run
+    /// use std::convert::TryFrom;
+    ///
+    /// let (object, _) = coremidi::Object::find_by_unique_id(0x1234).unwrap();
+    /// let source = coremidi::Source::try_from(object).unwrap();
+    /// ```
+    ///
+    pub fn find_by_unique_id(unique_id: u32) -> Result<(Object, ObjectType), Error> {
+        let backend = backend::current();
+        match backend.object_find_by_unique_id(unique_id as SInt32) {
+            Ok((object_ref, object_type)) => {
+                let object_type = ObjectType::from(object_type).unwrap_or(ObjectType::Other);
+                Ok((Object(object_ref, backend), object_type))
+            },
+            Err(status) => Err(Error::from(status))
+        }
+    }
+
+    /// Another object from the same backend.
+    ///
+    pub(crate) fn related(&self, object_ref: MIDIObjectRef) -> Object {
+        Object(object_ref, self.1.clone())
+    }
+
+    /// The backend the object was created with.
+    ///
+    pub(crate) fn backend(&self) -> &Arc<dyn Backend> {
+        &self.1
+    }
+
+    /// Get the type of the object, looking it up by its unique id.
+    ///
+    pub(crate) fn object_type(&self) -> Option<ObjectType> {
+        match self.backend().object_find_by_unique_id(self.unique_id()? as SInt32) {
+            Ok((object_ref, object_type)) if object_ref == self.0 => ObjectType::from(object_type).ok(),
+            _ => None
+        }
+    }
+
     /// Get the name for the object.
     ///
     pub fn name(&self) -> Option<String> {
@@ -75,25 +125,25 @@ impl Object {
 
     /// Sets an object's string-type property.
     ///
-    pub fn set_property_string(&self, name: &str, value: &str) -> Result<(), OSStatus> {
+    pub fn set_property_string(&self, name: &str, value: &str) -> Result<(), Error> {
         StringProperty::new(name).set_value(self, value)
     }
 
     /// Gets an object's string-type property.
     ///
-    pub fn get_property_string(&self, name: &str) -> Result<String, OSStatus> {
+    pub fn get_property_string(&self, name: &str) -> Result<String, Error> {
         StringProperty::new(name).value_from(self)
     }
 
     /// Sets an object's integer-type property.
     ///
-    pub fn set_property_integer(&self, name: &str, value: i32) -> Result<(), OSStatus> {
+    pub fn set_property_integer(&self, name: &str, value: i32) -> Result<(), Error> {
         IntegerProperty::new(name).set_value(self, value)
     }
 
     /// Gets an object's integer-type property.
     ///
-    pub fn get_property_integer(&self, name: &str) -> Result<i32, OSStatus> {
+    pub fn get_property_integer(&self, name: &str) -> Result<i32, Error> {
         IntegerProperty::new(name).value_from(self)
     }
 
@@ -101,7 +151,7 @@ impl Object {
     ///
     /// CoreMIDI treats booleans as integers (0/1) but this API uses native bool types
     ///
-    pub fn set_property_boolean(&self, name: &str, value: bool) -> Result<(), OSStatus> {
+    pub fn set_property_boolean(&self, name: &str, value: bool) -> Result<(), Error> {
         BooleanProperty::new(name).set_value(self, value)
     }
 
@@ -109,11 +159,17 @@ impl Object {
     ///
     /// CoreMIDI treats booleans as integers (0/1) but this API uses native bool types
     ///
-    pub fn get_property_boolean(&self, name: &str) -> Result<bool, OSStatus> {
+    pub fn get_property_boolean(&self, name: &str) -> Result<bool, Error> {
         BooleanProperty::new(name).value_from(self)
     }
 }
 
+impl PartialEq for Object {
+    fn eq(&self, other: &Object) -> bool {
+        self.0 == other.0
+    }
+}
+
 impl fmt::Debug for Object {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "Object({:x})", self.0 as usize)
@@ -124,7 +180,14 @@ impl fmt::Debug for Object {
 mod tests {
     use object::ObjectType;
 
-    use coremidi_sys::{
+    use std::convert::TryFrom;
+    use std::sync::Arc;
+
+    use backend::with_backend;
+    use backend::memory::MemoryBackend;
+    use {Client, Destination, Device, Entity, Error, Object, Properties, PropertySetter, Source};
+
+    use sys::{
         kMIDIObjectType_Other,
         kMIDIObjectType_Device,
         kMIDIObjectType_Entity,
@@ -153,4 +216,50 @@ mod tests {
     fn objecttype_from_error() {
         assert_eq!(ObjectType::from(0xffff as i32), Err(0xffff));
     }
+
+    #[test]
+    fn find_by_unique_id_and_convert() {
+        let backend = Arc::new(MemoryBackend::new());
+        let device = backend.add_device("device");
+        let entity = backend.add_entity(device, "entity");
+        backend.add_entity_source(entity, "source");
+        with_backend(backend, || {
+            let client = Client::new("client").unwrap();
+            let destination = client.virtual_destination("destination", |_| ()).unwrap();
+            let source = Source::from_index(0);
+
+            let (object, object_type) = Object::find_by_unique_id(source.unique_id().unwrap()).unwrap();
+            assert_eq!(object_type, ObjectType::Source);
+            assert_eq!(Source::try_from(object.clone()).map(|source| source.name()), Ok(Some("source".to_string())));
+            assert_eq!(Destination::try_from(object.clone()).err(), Some(object));
+
+            let (object, object_type) = Object::find_by_unique_id(destination.unique_id().unwrap()).unwrap();
+            assert_eq!(object_type, ObjectType::Destination);
+            assert!(Destination::try_from(object.clone()).is_ok());
+            // The type is checked on the object itself, so it can't be converted by mistake
+            assert_eq!(Source::try_from(destination.object.clone()).err(), Some(object));
+
+            let entity = source.entity().unwrap();
+            let (object, object_type) = Object::find_by_unique_id(entity.unique_id().unwrap()).unwrap();
+            assert_eq!(object_type, ObjectType::Entity);
+            assert_eq!(Entity::try_from(object.clone()), Ok(entity.clone()));
+            assert!(Device::try_from(object).is_err());
+            let (object, _) = Object::find_by_unique_id(entity.device().unwrap().unique_id().unwrap()).unwrap();
+            assert_eq!(Device::try_from(object), Ok(entity.device().unwrap()));
+
+            // The unique ids can be changed, but only to one that no other object has
+            Properties::unique_id().set_value(&source, 42).unwrap();
+            assert_eq!(Object::find_by_unique_id(42).map(|(_, object_type)| object_type), Ok(ObjectType::Source));
+            assert_eq!(Properties::unique_id().set_value(&destination, 42), Err(Error::IDNotUnique));
+            assert_eq!(Properties::unique_id().set_value(&source, 42), Ok(()));
+            assert_eq!(Object::find_by_unique_id(destination.unique_id().unwrap()).map(|(_, object_type)| object_type),
+                       Ok(ObjectType::Destination));
+            assert_eq!(Object::find_by_unique_id(0xffff).err(), Some(Error::ObjectNotFound));
+
+            // The unique ids of the disposed objects are not found anymore
+            let unique_id = destination.unique_id().unwrap();
+            drop(destination);
+            assert_eq!(Object::find_by_unique_id(unique_id).err(), Some(Error::ObjectNotFound));
+        });
+    }
 }
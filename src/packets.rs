@@ -1,14 +1,24 @@
-use coremidi_sys_ext::{
+use sys::{
     MIDIPacketList,
 };
 
+use clock::Clock;
+use messages::{MidiMessage, MessagesIterator};
+use layout::{PacketLayout, LayoutError};
+
+use std::error;
 use std::fmt;
 use std::marker::PhantomData;
 use std::ptr;
 use std::slice;
 
+/// The host time at which a packet is to be delivered, in the ticks of the host [Clock](trait.Clock.html).
+/// A timestamp of 0 means now.
 pub type Timestamp = u64;
 
+// The maximum number of data bytes in a packet, limited by the u16 length field.
+const MAX_PACKET_DATA_LENGTH: usize = 0xffff;
+
 // From the CoreMIDI headers:
 //
 // A Packet consists of a timestamp(u64), a length(u16) and a variable amount of
@@ -19,6 +29,9 @@ pub type Timestamp = u64;
 // data.
 //
 // Both structs are marked with `#pragma pack(push, 4)`
+//
+// See `PacketLayout` for the details on how this translates into memory depending on the
+// architecture.
 
 
 /// A [list of MIDI events](https://developer.apple.com/reference/coremidi/midipacketlist) being received from, or being sent to, one endpoint.
@@ -58,8 +71,8 @@ impl<'a> PacketRef<'a> {
         unsafe { ptr::read_unaligned(self.data.offset(8) as *const _) }
     }
 
-    /// Get the packet data. This method just gives raw MIDI bytes. You would need another
-    /// library to decode them and work with higher level events.
+    /// Get the packet data. This method just gives raw MIDI bytes, see
+    /// [messages](#method.messages) to decode them into higher level events.
     ///
     ///
     /// The following example:
@@ -83,21 +96,39 @@ impl<'a> PacketRef<'a> {
         unsafe { slice::from_raw_parts(self.data.offset(10), self.data_length() as usize) }
     }
 
+    /// Get an iterator for the MIDI messages contained in the packet data.
+    ///
+    /// The following example:
+    ///
+    /// ```
+    /// let packet_list = coremidi::PacketBuffer::from_data(0, vec![0x90, 0x40, 0x7f, 0xf8]);
+    /// for packet in packet_list.as_ref().iter() {
+    ///   for message in packet.messages() {
+    ///     println!("{:?}", message);
+    ///   }
+    /// }
+    /// ```
+    ///
+    /// will print:
+    ///
+    /// ```text
+    /// Ok(NoteOn { channel: 0, note: 64, velocity: 127 })
+    /// Ok(TimingClock)
+    /// ```
+    #[inline(always)]
+    pub fn messages(&self) -> MessagesIterator<'a> {
+        MessagesIterator::new(self.data())
+    }
+
     #[inline(always)]
     unsafe fn next(&self) -> PacketRef<'a> {
+        // Packet lists are 4 byte aligned, so aligning the address is the same as aligning
+        // the offset from the start of the list.
         let unadjusted = self.data.offset(10 + self.data_length() as isize);
-
-        #[cfg(any(target_arch = "aarch64", target_arch = "arm"))]
-        return PacketRef {
-            data: ((unadjusted as usize + 3) & !3) as *const _,
-            _lt: PhantomData,
-        };
-
-        #[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
-        return PacketRef {
-            data: unadjusted,
+        PacketRef {
+            data: PacketLayout::native().align(unadjusted as usize) as *const _,
             _lt: PhantomData,
-        };
+        }
     }
 }
 
@@ -131,14 +162,44 @@ impl<'a> fmt::Display for PacketRef<'a> {
 }
 
 impl<'a> PacketListRef<'a> {
+    /// Borrow a packet list from a pointer, like the one given to a read callback.
+    ///
+    /// # Safety
+    ///
+    /// The pointer must point to a 4 byte aligned `MIDIPacketList` with the native layout
+    /// (see [PacketLayout](struct.PacketLayout.html)), whose packets stay valid and unchanged for `'a`.
+    ///
     #[inline(always)]
-    pub unsafe fn from_ptr(ptr: *const MIDIPacketList) -> PacketRef<'a> {
-        PacketRef {
+    pub unsafe fn from_ptr(ptr: *const MIDIPacketList) -> PacketListRef<'a> {
+        PacketListRef {
             data: ptr as *const _,
             _lt: PhantomData,
         }
     }
 
+    /// Create a packet list from some bytes with the native layout (see [PacketLayout](struct.PacketLayout.html)),
+    /// like a packet list that was previously captured.
+    ///
+    /// The bytes must be 4 byte aligned, and the packet count, the length of every packet and the
+    /// padding between them are checked to be within the bounds of `bytes`.
+    ///
+    /// ```
+    /// let captured = coremidi::PacketBuffer::from_data(0, vec![0x90, 0x40, 0x7f]);
+    /// let bytes = unsafe { std::slice::from_raw_parts(captured.as_ref().as_ptr() as *const u8, captured.len_bytes()) };
+    /// let packet_list = coremidi::PacketListRef::from_bytes(bytes).unwrap();
+    /// assert_eq!(packet_list.length(), 1);
+    /// ```
+    pub fn from_bytes(bytes: &'a [u8]) -> Result<PacketListRef<'a>, LayoutError> {
+        if bytes.as_ptr() as usize & 3 != 0 {
+            return Err(LayoutError::Misaligned);
+        }
+        PacketLayout::native().validate(bytes)?;
+        Ok(PacketListRef {
+            data: bytes.as_ptr(),
+            _lt: PhantomData,
+        })
+    }
+
     #[inline(always)]
     pub fn as_ptr(&self) -> *const MIDIPacketList {
         self.data as *const _
@@ -161,6 +222,29 @@ impl<'a> PacketListRef<'a> {
             packet_ref: PacketRef { data: unsafe { self.data.offset(4) }, _lt: PhantomData }
         }
     }
+
+    /// Copy the packet list into an owned [PacketList](struct.PacketList.html),
+    /// which can outlive the read callback and be sent to other threads.
+    ///
+    /// It is not named `to_owned`, as the packet list is `Copy`, so `ToOwned::to_owned` already
+    /// gives back another reference to the same packets, and the two would be easy to mix up.
+    ///
+    /// ```
+    /// let packet_buf = coremidi::PacketBuffer::from_data(0, vec![0x90, 0x40, 0x7f]);
+    /// let packet_list = packet_buf.as_ref().to_packet_list();
+    /// std::thread::spawn(move || println!("{}", packet_list)).join().unwrap();
+    /// ```
+    pub fn to_packet_list(&self) -> PacketList {
+        PacketList::from(*self)
+    }
+
+    // The number of bytes from the start of the list to the end of the data of its last packet.
+    fn len_bytes(&self) -> usize {
+        match self.iter().last() {
+            Some(packet) => packet.data as usize + 10 + packet.data_length() as usize - self.data as usize,
+            None => 4,
+        }
+    }
 }
 
 impl<'a> fmt::Debug for PacketListRef<'a> {
@@ -212,6 +296,88 @@ impl<'a> Iterator for PacketListIterator<'a> {
     }
 }
 
+/// An error while building a [PacketBuffer](struct.PacketBuffer.html).
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacketBufferError {
+    /// The storage has no room left for the packet list header or a new packet.
+    TooSmall,
+    /// The storage is not 4 byte aligned, as required for a packet list.
+    Misaligned,
+    /// The packet data is longer than the 65535 bytes a packet can hold.
+    PacketTooLarge,
+}
+
+impl fmt::Display for PacketBufferError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PacketBufferError::TooSmall => write!(f, "packet buffer too small"),
+            PacketBufferError::Misaligned => write!(f, "packet buffer misaligned"),
+            PacketBufferError::PacketTooLarge => write!(f, "packet too large"),
+        }
+    }
+}
+
+impl error::Error for PacketBufferError {}
+
+/// An owned [list of MIDI events](https://developer.apple.com/reference/coremidi/midipacketlist).
+///
+/// It can be created from a [PacketListRef](struct.PacketListRef.html) with `to_packet_list()`,
+/// iterated in the same way, and borrowed again with `as_ref()` to send it.
+///
+#[derive(Clone, PartialEq, Eq)]
+pub struct PacketList {
+    // Words rather than bytes to guarantee the 4 byte alignment of the list
+    data: Vec<u32>,
+}
+
+impl PacketList {
+    #[inline(always)]
+    pub fn as_ref(&self) -> PacketListRef<'_> {
+        PacketListRef {
+            data: self.data.as_ptr() as *const u8,
+            _lt: PhantomData,
+        }
+    }
+
+    /// Get the number of packets in the list.
+    ///
+    #[inline(always)]
+    pub fn length(&self) -> u32 {
+        self.data[0]
+    }
+
+    /// Get an iterator for the packets in the list.
+    ///
+    #[inline(always)]
+    pub fn iter(&self) -> PacketListIterator<'_> {
+        self.as_ref().iter()
+    }
+}
+
+impl<'a> From<PacketListRef<'a>> for PacketList {
+    fn from(packet_list: PacketListRef<'a>) -> PacketList {
+        let len_bytes = packet_list.len_bytes();
+        let mut data = vec![0u32; len_bytes.div_ceil(4)];
+        unsafe {
+            ptr::copy_nonoverlapping(packet_list.data, data.as_mut_ptr() as *mut u8, len_bytes);
+        }
+        PacketList { data: data }
+    }
+}
+
+impl fmt::Debug for PacketList {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&self.as_ref(), f)
+    }
+}
+
+impl fmt::Display for PacketList {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.as_ref(), f)
+    }
+}
+
 pub struct PacketBuffer<T> {
     buffer: T,
 }
@@ -231,6 +397,12 @@ pub trait PacketBufferStorage {
     fn ptr_mut(&mut self) -> *mut u8;
 
     fn request(&mut self, length: usize) -> Option<&mut [u8]>;
+
+    fn len(&self) -> usize;
+
+    fn capacity(&self) -> usize;
+
+    fn truncate(&mut self, length: usize);
 }
 
 impl<'a> PacketBufferStorage for FixedStorage<'a> {
@@ -246,7 +418,7 @@ impl<'a> PacketBufferStorage for FixedStorage<'a> {
 
     #[inline(always)]
     fn request(&mut self, length: usize) -> Option<&mut [u8]> {
-        if self.used + length < self.data.len() {
+        if self.used + length <= self.data.len() {
             let data = &mut self.data[self.used..self.used + length];
             self.used += length;
             Some(data)
@@ -254,6 +426,23 @@ impl<'a> PacketBufferStorage for FixedStorage<'a> {
             None
         }
     }
+
+    #[inline(always)]
+    fn len(&self) -> usize {
+        self.used
+    }
+
+    #[inline(always)]
+    fn capacity(&self) -> usize {
+        self.data.len()
+    }
+
+    #[inline(always)]
+    fn truncate(&mut self, length: usize) {
+        if length < self.used {
+            self.used = length;
+        }
+    }
 }
 
 impl PacketBufferStorage for DynStorage {
@@ -274,6 +463,21 @@ impl PacketBufferStorage for DynStorage {
         unsafe { self.data.set_len(prev_len + length); }
         Some(&mut self.data[prev_len..])
     }
+
+    #[inline(always)]
+    fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    #[inline(always)]
+    fn capacity(&self) -> usize {
+        self.data.capacity()
+    }
+
+    #[inline(always)]
+    fn truncate(&mut self, length: usize) {
+        self.data.truncate(length);
+    }
 }
 
 pub type DynPacketBuffer = PacketBuffer<DynStorage>;
@@ -300,12 +504,45 @@ impl PacketBuffer<DynStorage> {
             data: vec![],
         })
     }
+
+    /// Create a packet buffer with room for at least `bytes` bytes (including the 4 bytes of
+    /// the packet list header) before it needs to allocate again.
+    ///
+    /// ```
+    /// let packet_buf = coremidi::PacketBuffer::with_capacity(1024);
+    /// assert!(packet_buf.remaining_capacity() >= 1020);
+    /// ```
+    #[inline(always)]
+    pub fn with_capacity(bytes: usize) -> Self {
+        PacketBuffer::new_with_buf(DynStorage {
+            data: Vec::with_capacity(bytes),
+        })
+    }
 }
 
 impl<'a> PacketBuffer<FixedStorage<'a>> {
+    /// Create a packet buffer on top of some pre-allocated memory.
+    ///
+    /// It panics if the memory is too small or not 4 byte aligned,
+    /// see [try_fixed](#method.try_fixed) for a non-panicking version.
+    ///
     #[inline(always)]
     pub fn fixed(data: &'a mut [u8]) -> Self {
-        PacketBuffer::new_with_buf(FixedStorage {
+        match Self::try_fixed(data) {
+            Ok(packet_buf) => packet_buf,
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    /// Create a packet buffer on top of some pre-allocated memory, which must be 4 byte aligned
+    /// and have room for at least the packet list header (4 bytes).
+    ///
+    #[inline(always)]
+    pub fn try_fixed(data: &'a mut [u8]) -> Result<Self, PacketBufferError> {
+        if data.len() < 4 {
+            return Err(PacketBufferError::TooSmall);
+        }
+        PacketBuffer::try_new_with_buf(FixedStorage {
             data: data,
             used: 0,
         })
@@ -314,63 +551,241 @@ impl<'a> PacketBuffer<FixedStorage<'a>> {
 
 impl<T: PacketBufferStorage> PacketBuffer<T> {
     #[inline(always)]
-    fn new_with_buf(mut buffer: T) -> Self {
+    fn new_with_buf(buffer: T) -> Self {
+        match Self::try_new_with_buf(buffer) {
+            Ok(packet_buf) => packet_buf,
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    #[inline(always)]
+    fn try_new_with_buf(mut buffer: T) -> Result<Self, PacketBufferError> {
         {
             let num_packets = match buffer.request(4) {
                 Some(buf) => buf,
-                None => panic!("BufferTooSmall"),
+                None => return Err(PacketBufferError::TooSmall),
             };
 
-            unsafe {
-                ptr::copy_nonoverlapping(&0u32 as *const _ as *const u8,
-                                         &mut num_packets[0] as *mut _, 4);
-            }
+            PacketLayout::native().write_list_header(num_packets, 0);
         }
 
-        assert!(buffer.ptr() as usize % 4 == 0, "BufferMisaligned");
+        if buffer.ptr() as usize & 3 != 0 {
+            return Err(PacketBufferError::Misaligned);
+        }
 
-        PacketBuffer {
+        Ok(PacketBuffer {
             buffer: buffer,
-        }
+        })
     }
 
     #[inline(always)]
-    pub fn as_ref(&self) -> PacketListRef {
+    pub fn as_ref(&self) -> PacketListRef<'_> {
         PacketListRef {
             data: self.buffer.ptr(),
             _lt: PhantomData,
         }
     }
 
+    /// Get the number of packets in the buffer.
+    ///
+    #[inline(always)]
+    pub fn packet_count(&self) -> u32 {
+        // We check 4 byte alignment in `::try_new_with_buf()`
+        unsafe { *(self.buffer.ptr() as *const u32) }
+    }
+
+    /// Get the number of bytes used by the packet list, including its header.
+    ///
+    #[inline(always)]
+    pub fn len_bytes(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Get the number of bytes that can still be added without allocating (for dynamic buffers)
+    /// or before running out of memory (for fixed buffers).
+    ///
+    #[inline(always)]
+    pub fn remaining_capacity(&self) -> usize {
+        self.buffer.capacity() - self.buffer.len()
+    }
+
+    /// Remove all the packets, keeping the allocated memory so the buffer can be reused.
+    ///
+    /// ```
+    /// let mut packet_buf = coremidi::PacketBuffer::with_capacity(64);
+    /// packet_buf.push_packet(0, &[0x90, 0x40, 0x7f]);
+    /// packet_buf.clear();
+    /// assert_eq!(packet_buf.packet_count(), 0);
+    /// assert_eq!(packet_buf.len_bytes(), 4);
+    /// ```
+    #[inline(always)]
+    pub fn clear(&mut self) {
+        self.buffer.truncate(4);
+        unsafe {
+            *(self.buffer.ptr_mut() as *mut u32) = 0;
+        }
+    }
+
+    /// Add a packet with some raw MIDI data.
+    ///
+    /// The timestamp is usually 0 to send it now, or taken from a [Clock](trait.Clock.html) to schedule it,
+    /// like `clock.after(ticks)`, see [push_packet_after](#method.push_packet_after).
+    ///
+    /// It panics if there is no room left for the packet or the data is too large,
+    /// see [try_push_packet](#method.try_push_packet) for a non-panicking version.
+    ///
     #[inline(always)]
     pub fn push_packet(&mut self, timestamp: Timestamp, packet: &[u8]) -> &mut Self {
-        assert!(packet.len() <= u16::max_value() as usize);
+        match self.try_push_packet(timestamp, packet) {
+            Ok(packet_buf) => packet_buf,
+            Err(err) => panic!("{}", err),
+        }
+    }
 
-        let req_size = 10 + packet.len();
+    /// Add a packet with some raw MIDI data, or fail if there is no room left for it
+    /// or the data is too large. The buffer is left untouched on failure.
+    ///
+    #[inline(always)]
+    pub fn try_push_packet(&mut self, timestamp: Timestamp, packet: &[u8]) -> Result<&mut Self, PacketBufferError> {
+        self.try_push_packet_with(timestamp, packet.len(), |data| data.copy_from_slice(packet))?;
+        Ok(self)
+    }
 
-        #[cfg(any(target_arch = "aarch64", target_arch = "arm"))]
-        let req_size = (req_size + 3) & !3;
+    /// Add a packet with some raw MIDI data, scheduled some ticks after the current time of a clock.
+    ///
+    /// ```
+    /// use coremidi::{Clock, ManualClock};
+    /// let clock = ManualClock::new(1000);
+    /// let mut packet_buf = coremidi::PacketBuffer::dyn();
+    /// packet_buf.push_packet_after(&clock, 10, &[0xf8]);
+    /// assert_eq!(packet_buf.as_ref().iter().next().unwrap().timestamp(), 1010);
+    /// ```
+    #[inline(always)]
+    pub fn push_packet_after(&mut self, clock: &dyn Clock, ticks: u64, packet: &[u8]) -> &mut Self {
+        self.push_packet(clock.after(ticks), packet)
+    }
+
+    /// Add a packet with a single MIDI message.
+    ///
+    /// ```
+    /// let mut packet_buf = coremidi::PacketBuffer::dyn();
+    /// packet_buf.push_message(0, &coremidi::MidiMessage::NoteOn { channel: 0, note: 0x40, velocity: 0x7f });
+    /// ```
+    #[inline(always)]
+    pub fn push_message(&mut self, timestamp: Timestamp, message: &MidiMessage) -> &mut Self {
+        match self.try_push_message(timestamp, message) {
+            Ok(packet_buf) => packet_buf,
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    /// Add a packet with a single MIDI message, or fail if there is no room left for it.
+    /// The buffer is left untouched on failure.
+    ///
+    #[inline(always)]
+    pub fn try_push_message(&mut self, timestamp: Timestamp, message: &MidiMessage) -> Result<&mut Self, PacketBufferError> {
+        self.try_push_packet_with(timestamp, message.encoded_len(), |data| { message.encode(data); })?;
+        Ok(self)
+    }
+
+    /// Add a packet with a single MIDI message, scheduled some ticks after the current time of a clock.
+    ///
+    #[inline(always)]
+    pub fn push_message_after(&mut self, clock: &dyn Clock, ticks: u64, message: &MidiMessage) -> &mut Self {
+        self.push_message(clock.after(ticks), message)
+    }
+
+    /// Add several MIDI messages sharing the same timestamp.
+    ///
+    /// Short messages are packed together in as few packets as possible, while every SysEx
+    /// message goes into its own packet, as required by CoreMIDI. A SysEx message longer than
+    /// a packet can hold is continued in the following packets.
+    ///
+    /// It panics if there is no room left for all the messages,
+    /// see [try_push_messages](#method.try_push_messages) for a non-panicking version.
+    ///
+    /// ```
+    /// use coremidi::MidiMessage;
+    /// let mut packet_buf = coremidi::PacketBuffer::dyn();
+    /// packet_buf.push_messages(0, &[
+    ///     MidiMessage::NoteOn { channel: 0, note: 0x40, velocity: 0x7f },
+    ///     MidiMessage::NoteOn { channel: 0, note: 0x43, velocity: 0x7f }]);
+    /// assert_eq!(packet_buf.as_ref().length(), 1);
+    /// ```
+    pub fn push_messages(&mut self, timestamp: Timestamp, messages: &[MidiMessage]) -> &mut Self {
+        match self.try_push_messages(timestamp, messages) {
+            Ok(packet_buf) => packet_buf,
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    /// Add several MIDI messages sharing the same timestamp, as [push_messages](#method.push_messages) does,
+    /// or fail if there is no room left for all of them. The buffer is left untouched on failure,
+    /// without any of the messages.
+    ///
+    pub fn try_push_messages(&mut self, timestamp: Timestamp, messages: &[MidiMessage]) -> Result<&mut Self, PacketBufferError> {
+        let length = self.buffer.len();
+        let packet_count = self.packet_count();
+        if let Err(err) = self.try_push_message_groups(timestamp, messages) {
+            self.buffer.truncate(length);
+            unsafe {
+                // We check 4 byte alignment in `::try_new_with_buf()`
+                *(self.buffer.ptr_mut() as *mut u32) = packet_count;
+            }
+            return Err(err);
+        }
+        Ok(self)
+    }
+
+    fn try_push_message_groups(&mut self, timestamp: Timestamp, messages: &[MidiMessage]) -> Result<(), PacketBufferError> {
+        let mut start = 0;
+        while start < messages.len() {
+            let end = packet_messages_end(messages, start);
+            let group = &messages[start..end];
+            match group[0] {
+                MidiMessage::SysEx(data) if data.len() > MAX_PACKET_DATA_LENGTH => {
+                    for chunk in data.chunks(MAX_PACKET_DATA_LENGTH) {
+                        self.try_push_packet_with(timestamp, chunk.len(), |data| data.copy_from_slice(chunk))?;
+                    }
+                },
+                _ => {
+                    let length = group.iter().map(|message| message.encoded_len()).sum();
+                    self.try_push_packet_with(timestamp, length, |data| {
+                        group.iter().fold(0, |offset, message| offset + message.encode(&mut data[offset..]));
+                    })?;
+                }
+            }
+            start = end;
+        }
+        Ok(())
+    }
+
+    // Adds a packet with `length` data bytes, which are filled in by `fill`.
+    #[inline(always)]
+    fn try_push_packet_with<F>(&mut self, timestamp: Timestamp, length: usize, fill: F) -> Result<(), PacketBufferError>
+            where F: FnOnce(&mut [u8]) {
+
+        if length > MAX_PACKET_DATA_LENGTH {
+            return Err(PacketBufferError::PacketTooLarge);
+        }
 
         {
-            let req = match self.buffer.request(req_size) {
+            let layout = PacketLayout::native();
+            let req = match self.buffer.request(layout.packet_size(length)) {
                 Some(buf) => buf,
-                None => panic!("BufferTooSmall"),
+                None => return Err(PacketBufferError::TooSmall),
             };
 
-            unsafe {
-                let length = packet.len() as u16;
-                ptr::copy_nonoverlapping(&timestamp as *const _ as *const u8, &mut req[0] as *mut _, 8);
-                ptr::copy_nonoverlapping(&length as *const _ as *const u8, &mut req[8] as *mut _, 2);
-                ptr::copy_nonoverlapping(&packet[0] as *const _, &mut req[10] as *mut _, packet.len());
-            }
+            layout.write_packet_header(req, timestamp, length as u16);
+            fill(&mut req[10..10 + length]);
         }
 
         unsafe {
-            // We assert 4 byte alignment in `::new()`
+            // We check 4 byte alignment in `::try_new_with_buf()`
             *(self.buffer.ptr_mut() as *mut u32) += 1;
         }
 
-        self
+        Ok(())
     }
 
     #[inline(always)]
@@ -381,12 +796,45 @@ impl<T: PacketBufferStorage> PacketBuffer<T> {
     }
 }
 
+// The end of the group of messages starting at `start` that can share a single packet.
+fn packet_messages_end(messages: &[MidiMessage], start: usize) -> usize {
+    if let MidiMessage::SysEx(_) = messages[start] {
+        return start + 1;
+    }
+
+    let mut length = 0;
+    let mut end = start;
+    while end < messages.len() {
+        match messages[end] {
+            MidiMessage::SysEx(_) => break,
+            ref message => {
+                length += message.encoded_len();
+                if length > MAX_PACKET_DATA_LENGTH {
+                    break;
+                }
+            }
+        }
+        end += 1;
+    }
+    end
+}
+
 #[cfg(test)]
 mod tests {
-    use coremidi_sys::MIDITimeStamp;
-    use coremidi_sys_ext::MIDIPacketList;
+    use sys::MIDIPacketList;
+    use std::slice;
     use PacketListRef;
     use PacketBuffer;
+    use MidiMessage;
+    use clock::ManualClock;
+    use packets::{PacketList, PacketBufferError};
+    use layout::{PacketLayout, LayoutError};
+    use std::thread;
+
+    // A 4 byte aligned storage for fixed packet buffers.
+    fn aligned_bytes(words: &mut [u32]) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(words.as_mut_ptr() as *mut u8, words.len() * 4) }
+    }
 
     #[test]
     pub fn packet_buffer_new() {
@@ -398,14 +846,34 @@ mod tests {
     #[test]
     pub fn packet_buffer_with_data() {
         let packet_buf = PacketBuffer::new()
-            .with_data(0x0102030405060708 as MIDITimeStamp, vec![0x90u8, 0x40, 0x7f]);
-        assert_eq!(packet_buf.buffer.data.len(), 17);
-        // FIXME This is platform endianess dependent
-        assert_eq!(packet_buf.buffer.data, &[
-            0x01, 0x00, 0x00, 0x00,
+            .with_data(0x0102030405060708, vec![0x90u8, 0x40, 0x7f])
+            .with_data(0x1112131415161718, vec![0xf8u8]);
+        #[cfg(not(any(target_arch = "aarch64", target_arch = "arm")))]
+        assert_eq!(packet_buf.buffer.data, vec![
+            0x02, 0x00, 0x00, 0x00,
             0x08, 0x07, 0x06, 0x05, 0x04, 0x03, 0x02, 0x01,
             0x03, 0x00,
-            0x90, 0x40, 0x7f]);
+            0x90, 0x40, 0x7f,
+            0x18, 0x17, 0x16, 0x15, 0x14, 0x13, 0x12, 0x11,
+            0x01, 0x00,
+            0xf8]);
+
+        #[cfg(any(target_arch = "aarch64", target_arch = "arm"))]
+        assert_eq!(packet_buf.buffer.data, vec![
+            0x02, 0x00, 0x00, 0x00,
+            0x08, 0x07, 0x06, 0x05, 0x04, 0x03, 0x02, 0x01,
+            0x03, 0x00,
+            0x90, 0x40, 0x7f,
+            0x00, 0x00, 0x00,
+            0x18, 0x17, 0x16, 0x15, 0x14, 0x13, 0x12, 0x11,
+            0x01, 0x00,
+            0xf8,
+            0x00]);
+
+        let layout = PacketLayout::native();
+        assert_eq!(packet_buf.buffer.data, layout.encode(&[
+            (0x0102030405060708, &[0x90, 0x40, 0x7f]),
+            (0x1112131415161718, &[0xf8])]));
     }
 
     #[test]
@@ -425,4 +893,246 @@ mod tests {
             .with_data(0, vec![0x81u8, 0x40, 0x7f]);
         assert_eq!(packet_buf.as_ref().length(), 4);
     }
+
+    #[test]
+    fn packet_buffer_push_message() {
+        let mut packet_buf = PacketBuffer::dyn();
+        packet_buf.push_message(0, &MidiMessage::NoteOn { channel: 1, note: 0x40, velocity: 0x7f });
+        let packet_list = packet_buf.as_ref();
+        assert_eq!(packet_list.length(), 1);
+        assert_eq!(packet_list.iter().next().unwrap().data(), &[0x91, 0x40, 0x7f]);
+    }
+
+    #[test]
+    fn packet_buffer_push_messages() {
+        let mut packet_buf = PacketBuffer::dyn();
+        packet_buf.push_messages(1, &[
+            MidiMessage::NoteOn { channel: 0, note: 0x40, velocity: 0x7f },
+            MidiMessage::TimingClock,
+            MidiMessage::SysEx(&[0xf0, 0x01, 0xf7]),
+            MidiMessage::ProgramChange { channel: 2, program: 0x10 }]);
+        let packets = packet_buf.as_ref().iter()
+            .map(|packet| (packet.timestamp(), packet.data().to_vec()))
+            .collect::<Vec<_>>();
+        assert_eq!(packets, vec![
+            (1, vec![0x90, 0x40, 0x7f, 0xf8]),
+            (1, vec![0xf0, 0x01, 0xf7]),
+            (1, vec![0xc2, 0x10])]);
+    }
+
+    #[test]
+    fn packet_buffer_push_messages_splits_long_packets() {
+        let messages = vec![MidiMessage::NoteOn { channel: 0, note: 0x40, velocity: 0x7f }; 21846];
+        let mut packet_buf = PacketBuffer::dyn();
+        packet_buf.push_messages(0, &messages);
+        let lengths = packet_buf.as_ref().iter().map(|packet| packet.data_length()).collect::<Vec<_>>();
+        assert_eq!(lengths, vec![65535, 3]);
+    }
+
+    #[test]
+    fn packet_buffer_push_messages_splits_long_sysex() {
+        let mut sysex = vec![0x01; 0x10000 + 10];
+        sysex[0] = 0xf0;
+        *sysex.last_mut().unwrap() = 0xf7;
+        let mut packet_buf = PacketBuffer::dyn();
+        packet_buf.push_messages(0, &[MidiMessage::TimingClock, MidiMessage::SysEx(&sysex), MidiMessage::TimingClock]);
+        let packets = packet_buf.as_ref().iter().map(|packet| packet.data().to_vec()).collect::<Vec<_>>();
+        assert_eq!(packets.iter().map(|data| data.len()).collect::<Vec<_>>(), vec![1, 65535, 11, 1]);
+        assert_eq!(packets[1..3].concat(), sysex);
+    }
+
+    #[test]
+    fn packet_buffer_push_after_follows_the_clock() {
+        let clock = ManualClock::new(1000);
+        let mut packet_buf = PacketBuffer::dyn();
+        packet_buf.push_message_after(&clock, 10, &MidiMessage::TimingClock);
+        clock.advance(5);
+        packet_buf.push_packet_after(&clock, 10, &[0xf8]);
+        let timestamps = packet_buf.as_ref().iter().map(|packet| packet.timestamp()).collect::<Vec<_>>();
+        assert_eq!(timestamps, vec![1010, 1015]);
+    }
+
+    #[test]
+    fn packet_buffer_try_push_messages_all_or_nothing() {
+        let mut words = [0u32; 8];
+        let mut packet_buf = PacketBuffer::fixed(aligned_bytes(&mut words));
+        packet_buf.push_packet(1, &[0xf8]);
+        let before = packet_buf.as_ref().to_packet_list();
+
+        // The first packet fits, but not the second one
+        let result = packet_buf.try_push_messages(2, &[
+            MidiMessage::TimingClock,
+            MidiMessage::SysEx(&[0xf0, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0xf7])]).map(|_| ());
+        assert_eq!(result, Err(PacketBufferError::TooSmall));
+        assert_eq!(packet_buf.as_ref().to_packet_list(), before);
+
+        assert!(packet_buf.try_push_messages(2, &[MidiMessage::TimingClock]).is_ok());
+        assert_eq!(packet_buf.packet_count(), 2);
+    }
+
+    #[test]
+    fn packet_buffer_push_messages_fixed() {
+        let mut words = [0u32; 8];
+        let mut packet_buf = PacketBuffer::fixed(aligned_bytes(&mut words));
+        packet_buf.push_messages(0, &[
+            MidiMessage::NoteOff { channel: 0, note: 0x40, velocity: 0 },
+            MidiMessage::NoteOff { channel: 1, note: 0x40, velocity: 0 }]);
+        let packet_list = packet_buf.as_ref();
+        assert_eq!(packet_list.length(), 1);
+        assert_eq!(packet_list.iter().next().unwrap().data(), &[0x80, 0x40, 0x00, 0x81, 0x40, 0x00]);
+    }
+
+    #[test]
+    fn packet_buffer_try_fixed() {
+        let mut words = [0u32; 2];
+        let bytes = aligned_bytes(&mut words);
+        assert_eq!(PacketBuffer::try_fixed(&mut bytes[..3]).err(), Some(PacketBufferError::TooSmall));
+        assert_eq!(PacketBuffer::try_fixed(&mut bytes[1..]).err(), Some(PacketBufferError::Misaligned));
+        assert_eq!(PacketBuffer::try_fixed(&mut bytes[..4]).unwrap().as_ref().length(), 0);
+    }
+
+    #[test]
+    fn packet_buffer_try_push_packet_exact_fit() {
+        let size = 4 + PacketLayout::native().packet_size(3);
+        let mut words = [0u32; 8];
+        let bytes = aligned_bytes(&mut words);
+
+        {
+            let mut packet_buf = PacketBuffer::try_fixed(&mut bytes[..size]).unwrap();
+            assert!(packet_buf.try_push_packet(0, &[0x90, 0x40, 0x7f]).is_ok());
+            assert_eq!(packet_buf.try_push_packet(0, &[]).err(), Some(PacketBufferError::TooSmall));
+            assert_eq!(packet_buf.as_ref().length(), 1);
+        }
+
+        let mut packet_buf = PacketBuffer::try_fixed(&mut bytes[..size - 1]).unwrap();
+        assert_eq!(packet_buf.try_push_packet(0, &[0x90, 0x40, 0x7f]).err(), Some(PacketBufferError::TooSmall));
+        assert!(packet_buf.try_push_packet(0, &[0x90, 0x40]).is_ok());
+        assert_eq!(packet_buf.as_ref().length(), 1);
+    }
+
+    #[test]
+    fn packet_buffer_try_push_message_exact_fit() {
+        let size = 4 + PacketLayout::native().packet_size(1);
+        let mut words = [0u32; 8];
+        let mut packet_buf = PacketBuffer::try_fixed(&mut aligned_bytes(&mut words)[..size]).unwrap();
+        assert!(packet_buf.try_push_message(0, &MidiMessage::TimingClock).is_ok());
+        assert_eq!(packet_buf.try_push_message(0, &MidiMessage::TimingClock).err(), Some(PacketBufferError::TooSmall));
+    }
+
+    #[test]
+    fn packet_buffer_try_push_packet_too_large() {
+        let mut packet_buf = PacketBuffer::dyn();
+        let data = vec![0u8; 0x10000];
+        assert_eq!(packet_buf.try_push_packet(0, &data).err(), Some(PacketBufferError::PacketTooLarge));
+        assert!(packet_buf.try_push_packet(0, &data[1..]).is_ok());
+        assert_eq!(packet_buf.as_ref().length(), 1);
+    }
+
+    #[test]
+    #[should_panic]
+    fn packet_buffer_push_packet_panics() {
+        let mut words = [0u32; 2];
+        let mut packet_buf = PacketBuffer::fixed(aligned_bytes(&mut words));
+        packet_buf.push_packet(0, &[0x90, 0x40, 0x7f]);
+    }
+
+    #[test]
+    fn packet_buffer_clear_dyn() {
+        let mut packet_buf = PacketBuffer::with_capacity(64);
+        let ptr = packet_buf.as_ref().data;
+        packet_buf.push_packet(0, &[0x90, 0x40, 0x7f]).push_packet(1, &[0x80, 0x40, 0x7f]);
+        assert_eq!(packet_buf.packet_count(), 2);
+        assert_eq!(packet_buf.len_bytes(), 4 + 2 * PacketLayout::native().packet_size(3));
+
+        packet_buf.clear();
+        assert_eq!(packet_buf.packet_count(), 0);
+        assert_eq!(packet_buf.len_bytes(), 4);
+        assert!(packet_buf.remaining_capacity() >= 60);
+
+        packet_buf.push_packet(2, &[0xf8]);
+        assert_eq!(packet_buf.as_ref().data, ptr);
+        assert_eq!(packet_buf.as_ref().iter().map(|p| p.timestamp()).collect::<Vec<_>>(), vec![2]);
+    }
+
+    #[test]
+    fn packet_buffer_clear_fixed() {
+        let mut words = [0u32; 8];
+        let mut packet_buf = PacketBuffer::fixed(aligned_bytes(&mut words));
+        assert_eq!(packet_buf.remaining_capacity(), 28);
+        packet_buf.push_packet(0, &[0x90, 0x40, 0x7f]);
+        assert_eq!(packet_buf.remaining_capacity(), 28 - PacketLayout::native().packet_size(3));
+
+        packet_buf.clear();
+        assert_eq!(packet_buf.packet_count(), 0);
+        assert_eq!(packet_buf.len_bytes(), 4);
+        assert_eq!(packet_buf.remaining_capacity(), 28);
+    }
+
+    #[test]
+    fn packet_list_ref_to_packet_list() {
+        let packet_list = {
+            let mut packet_buf = PacketBuffer::dyn();
+            packet_buf.push_packet(1, &[0x90, 0x40, 0x7f]).push_packet(2, &[0xf0, 0x01, 0x02, 0xf7]);
+            packet_buf.as_ref().to_packet_list()
+        };
+        assert_eq!(packet_list.length(), 2);
+        let packets = packet_list.iter()
+            .map(|packet| (packet.timestamp(), packet.data().to_vec()))
+            .collect::<Vec<_>>();
+        assert_eq!(packets, vec![(1, vec![0x90, 0x40, 0x7f]), (2, vec![0xf0, 0x01, 0x02, 0xf7])]);
+        assert_eq!(packet_list.as_ref().to_packet_list(), packet_list);
+    }
+
+    #[test]
+    fn packet_list_ref_to_packet_list_empty() {
+        let packet_list = PacketBuffer::dyn().as_ref().to_packet_list();
+        assert_eq!(packet_list.length(), 0);
+        assert_eq!(packet_list.iter().count(), 0);
+    }
+
+    #[test]
+    fn packet_list_send() {
+        let mut packet_buf = PacketBuffer::dyn();
+        packet_buf.push_packet(1, &[0xf8]);
+        let packet_list: PacketList = packet_buf.as_ref().into();
+        let data = thread::spawn(move || {
+            packet_list.iter().next().unwrap().data().to_vec()
+        }).join().unwrap();
+        assert_eq!(data, vec![0xf8]);
+    }
+
+    #[test]
+    fn packet_list_from_bytes() {
+        let encoded = PacketLayout::native().encode(&[(1, &[0x90, 0x40, 0x7f]), (2, &[0xf8])]);
+        let mut words = [0u32; 16];
+        let bytes = aligned_bytes(&mut words);
+        bytes[..encoded.len()].copy_from_slice(&encoded);
+
+        let packet_list = PacketListRef::from_bytes(&bytes[..encoded.len()]).unwrap();
+        let packets = packet_list.iter()
+            .map(|packet| (packet.timestamp(), packet.data().to_vec()))
+            .collect::<Vec<_>>();
+        assert_eq!(packets, vec![(1, vec![0x90, 0x40, 0x7f]), (2, vec![0xf8])]);
+    }
+
+    #[test]
+    fn packet_list_from_bytes_errors() {
+        let encoded = PacketLayout::native().encode(&[(1, &[0x90, 0x40, 0x7f]), (2, &[0xf8])]);
+        let mut words = [0u32; 16];
+        let bytes = aligned_bytes(&mut words);
+        bytes[..encoded.len()].copy_from_slice(&encoded);
+
+        assert_eq!(PacketListRef::from_bytes(&bytes[..2]).err(), Some(LayoutError::MissingHeader));
+        assert_eq!(PacketListRef::from_bytes(&bytes[1..encoded.len()]).err(), Some(LayoutError::Misaligned));
+        assert_eq!(PacketListRef::from_bytes(&bytes[..encoded.len() - 4]).err(), Some(LayoutError::PacketOutOfBounds(1)));
+
+        // Declare more packets than there are
+        bytes[0] = 3;
+        assert_eq!(PacketListRef::from_bytes(&bytes[..encoded.len()]).err(), Some(LayoutError::PacketOutOfBounds(2)));
+
+        // Declare a first packet longer than the buffer
+        bytes[0] = 2;
+        bytes[12] = 0xff;
+        assert_eq!(PacketListRef::from_bytes(&bytes[..encoded.len()]).err(), Some(LayoutError::PacketOutOfBounds(0)));
+    }
 }
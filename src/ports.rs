@@ -1,23 +1,16 @@
-use core_foundation::base::OSStatus;
-
-use coremidi_sys::{
-    MIDIPortConnectSource, MIDIPortDisconnectSource, MIDIPortDispose
-};
-
-use coremidi_sys_ext::{
-    MIDISend
-};
-
-use std::ptr;
 use std::ops::Deref;
+use std::sync::Arc;
 
 use Object;
+use Error;
 use Port;
 use OutputPort;
 use InputPort;
 use Destination;
 use Source;
 use PacketListRef;
+use clock::{Clock, HostClock};
+use sysex_send::{self, SysExRequest, SysExProgress, SysExTransfer};
 
 impl Deref for Port {
     type Target = Object;
@@ -29,7 +22,7 @@ impl Deref for Port {
 
 impl Drop for Port {
     fn drop(&mut self) {
-        unsafe { MIDIPortDispose(self.object.0) };
+        self.object.backend().port_dispose(self.object.0);
     }
 }
 
@@ -37,13 +30,46 @@ impl OutputPort {
     /// Send a list of packets to a destination.
     /// See [MIDISend](https://developer.apple.com/reference/coremidi/1495289-midisend).
     ///
-    pub fn send(&self, destination: &Destination, packet_list: PacketListRef) -> Result<(), OSStatus> {
-        let status = unsafe { MIDISend(
+    pub fn send(&self, destination: &Destination, packet_list: PacketListRef) -> Result<(), Error> {
+        let status = self.port.object.backend().send(
+            self.port.object.0,
+            destination.endpoint.object.0,
+            packet_list);
+        if status == 0 { Ok(()) } else { Err(Error::from(status)) }
+    }
+
+    /// Send a large SysEx message to a destination in the background, in chunks paced at the speed of the request.
+    /// It is the equivalent of MIDISendSysex.
+    ///
+    /// The progress is reported from another thread, once for every chunk sent, and finally with how the transfer finished.
+    /// The transfer borrows the port, and it is cancelled if it is dropped before finishing.
+    ///
+    /// ```rust,no_run
+    /// let client = coremidi::Client::new("example-client").unwrap();
+    /// let output_port = client.output_port("example-port").unwrap();
+    /// let destination = coremidi::Destination::from_index(0);
+    /// let request = coremidi::SysExRequest::for_destination(vec![0xf0, 0x7e, 0x7f, 0x09, 0x01, 0xf7], &destination);
+    /// let transfer = output_port.send_sysex(&destination, request, |progress| println!("{:?}", progress));
+    /// println!("{:?}", transfer.wait());
+    /// ```
+    pub fn send_sysex<F>(&self, destination: &Destination, request: SysExRequest, progress: F) -> SysExTransfer<'_>
+            where F: FnMut(SysExProgress) + Send + 'static {
+        self.send_sysex_with_clock(destination, request, Arc::new(HostClock), progress)
+    }
+
+    /// Send a large SysEx message like [send_sysex](#method.send_sysex), pacing the chunks with the given clock,
+    /// for example a [ManualClock](struct.ManualClock.html) in tests.
+    ///
+    pub fn send_sysex_with_clock<F>(&self, destination: &Destination, request: SysExRequest, clock: Arc<dyn Clock>,
+                                    progress: F) -> SysExTransfer<'_>
+            where F: FnMut(SysExProgress) + Send + 'static {
+        sysex_send::spawn(
+            self.port.object.backend().clone(),
+            clock,
             self.port.object.0,
             destination.endpoint.object.0,
-            packet_list.as_ptr())
-        };
-        if status == 0 { Ok(()) } else { Err(status) }
+            request,
+            progress)
     }
 }
 
@@ -57,21 +83,18 @@ impl Deref for OutputPort {
 
 impl InputPort {
 
-    pub fn connect_source(&self, source: &Source) -> Result<(), OSStatus> {
-        let status = unsafe { MIDIPortConnectSource(
+    pub fn connect_source(&self, source: &Source) -> Result<(), Error> {
+        let status = self.object.backend().port_connect_source(
             self.object.0,
-            source.object.0,
-            ptr::null_mut())
-        };
-        if status == 0 { Ok(()) } else { Err(status) }
+            source.object.0);
+        if status == 0 { Ok(()) } else { Err(Error::from(status)) }
     }
 
-    pub fn disconnect_source(&self, source: &Source) -> Result<(), OSStatus> {
-        let status = unsafe { MIDIPortDisconnectSource(
+    pub fn disconnect_source(&self, source: &Source) -> Result<(), Error> {
+        let status = self.object.backend().port_disconnect_source(
             self.object.0,
-            source.object.0)
-        };
-        if status == 0 { Ok(()) } else { Err(status) }
+            source.object.0);
+        if status == 0 { Ok(()) } else { Err(Error::from(status)) }
     }
 }
 
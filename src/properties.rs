@@ -1,206 +1,240 @@
-use core_foundation::string::{CFString, CFStringRef};
-use core_foundation::base::{TCFType, OSStatus};
-
-use coremidi_sys::*;
-
-use std::mem;
+use sys::SInt32;
 
 use Object;
+use Error;
 
 pub trait PropertyGetter<T> {
-    fn value_from(&self, object: &Object) -> Result<T, OSStatus>;
+    fn value_from(&self, object: &Object) -> Result<T, Error>;
 }
 
 pub trait PropertySetter<T> {
-    fn set_value(&self, object: &Object, value: T) -> Result<(), OSStatus>;
+    fn set_value(&self, object: &Object, value: T) -> Result<(), Error>;
 }
 
 /// A MIDI object property which value is an String
 ///
-pub struct StringProperty(CFStringRef);
+pub struct StringProperty(PropertyKey);
 
 impl StringProperty {
     pub fn new(name: &str) -> Self {
-        StringProperty(CFString::new(name).as_concrete_TypeRef())
+        StringProperty(PropertyKey::Custom(name.to_string()))
     }
 }
 
 impl<T> PropertyGetter<T> for StringProperty where T: From<String> {
-    fn value_from(&self, object: &Object) -> Result<T, OSStatus> {
-        unsafe {
-            let mut string_ref: CFStringRef = mem::uninitialized();
-            let status = MIDIObjectGetStringProperty(object.0, self.0, &mut string_ref);
-            if status == 0 {
-                let string: CFString = TCFType::wrap_under_create_rule(string_ref);
-                Ok(From::<String>::from(format!("{}", string)))
-            }
-            else { Err(status) }
+    fn value_from(&self, object: &Object) -> Result<T, Error> {
+        match object.backend().get_string_property(object.0, &self.0) {
+            Ok(string) => Ok(From::<String>::from(string)),
+            Err(status) => Err(Error::from(status))
         }
     }
 }
 
 impl<'a, T> PropertySetter<T> for StringProperty where T: Into<String> {
-    fn set_value(&self, object: &Object, value: T) -> Result<(), OSStatus> {
-        unsafe {
-            let value: String = value.into();
-            let string = CFString::new(&value);
-            let string_ref = string.as_concrete_TypeRef();
-            let status = MIDIObjectSetStringProperty(object.0, self.0, string_ref);
-            if status == 0 { Ok(()) } else { Err(status) }
-        }
+    fn set_value(&self, object: &Object, value: T) -> Result<(), Error> {
+        let value: String = value.into();
+        let status = object.backend().set_string_property(object.0, &self.0, &value);
+        if status == 0 { Ok(()) } else { Err(Error::from(status)) }
     }
 }
 
 /// A MIDI object property which value is an Integer
 ///
-pub struct IntegerProperty(CFStringRef);
+pub struct IntegerProperty(PropertyKey);
 
 impl IntegerProperty {
     pub fn new(name: &str) -> Self {
-        IntegerProperty(CFString::new(name).as_concrete_TypeRef())
+        IntegerProperty(PropertyKey::Custom(name.to_string()))
     }
 }
 
 impl<T> PropertyGetter<T> for IntegerProperty where T: From<SInt32> {
-    fn value_from(&self, object: &Object) -> Result<T, OSStatus> {
-        unsafe {
-            let mut value: SInt32 = mem::uninitialized();
-            let status = MIDIObjectGetIntegerProperty(object.0, self.0, &mut value);
-            if status == 0 { Ok(From::from(value)) } else { Err(status) }
+    fn value_from(&self, object: &Object) -> Result<T, Error> {
+        match object.backend().get_integer_property(object.0, &self.0) {
+            Ok(value) => Ok(From::from(value)),
+            Err(status) => Err(Error::from(status))
         }
     }
 }
 
 impl <T> PropertySetter<T> for IntegerProperty where T: Into<SInt32> {
-    fn set_value(&self, object: &Object, value: T) -> Result<(), OSStatus> {
-        unsafe {
-            let status = MIDIObjectSetIntegerProperty(object.0, self.0, value.into());
-            if status == 0 { Ok(()) } else { Err(status) }
-        }
+    fn set_value(&self, object: &Object, value: T) -> Result<(), Error> {
+        let status = object.backend().set_integer_property(object.0, &self.0, value.into());
+        if status == 0 { Ok(()) } else { Err(Error::from(status)) }
     }
 }
 
 /// A MIDI object property which value is a Boolean
 ///
-pub struct BooleanProperty(CFStringRef);
+pub struct BooleanProperty(PropertyKey);
 
 impl BooleanProperty {
     pub fn new(name: &str) -> Self {
-        BooleanProperty(CFString::new(name).as_concrete_TypeRef())
+        BooleanProperty(PropertyKey::Custom(name.to_string()))
     }
 }
 
 impl<T> PropertyGetter<T> for BooleanProperty where T: From<bool> {
-    fn value_from(&self, object: &Object) -> Result<T, OSStatus> {
-        unsafe {
-            let mut value: SInt32 = mem::uninitialized();
-            let status = MIDIObjectGetIntegerProperty(object.0, self.0, &mut value);
-            if status == 0 { Ok(From::from(value == 1)) } else { Err(status) }
+    fn value_from(&self, object: &Object) -> Result<T, Error> {
+        match object.backend().get_integer_property(object.0, &self.0) {
+            Ok(value) => Ok(From::from(value == 1)),
+            Err(status) => Err(Error::from(status))
         }
     }
 }
 
 impl<T> PropertySetter<T> for BooleanProperty where T: Into<bool> {
-    fn set_value(&self, object: &Object, value: T) -> Result<(), OSStatus> {
-        unsafe {
-            let value: SInt32 = if value.into() { 1 } else { 0 };
-            let status = MIDIObjectSetIntegerProperty(object.0, self.0, value);
-            if status == 0 { Ok(()) } else { Err(status) }
-        }
+    fn set_value(&self, object: &Object, value: T) -> Result<(), Error> {
+        let value: SInt32 = if value.into() { 1 } else { 0 };
+        let status = object.backend().set_integer_property(object.0, &self.0, value);
+        if status == 0 { Ok(()) } else { Err(Error::from(status)) }
     }
 }
 
+/// The key of a MIDI object property.
+///
+/// The properties known by the crate have their own variant, named after the CoreMIDI constant
+/// (`Name` is `kMIDIPropertyName`), see [Properties](struct.Properties.html). Any other property
+/// is `Custom`, by its name.
+///
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum PropertyKey {
+    Name,
+    Manufacturer,
+    Model,
+    UniqueID,
+    DeviceID,
+    ReceiveChannels,
+    TransmitChannels,
+    MaxSysExSpeed,
+    AdvanceScheduleTimeMuSec,
+    IsEmbeddedEntity,
+    IsBroadcast,
+    SingleRealtimeEntity,
+    ConnectionUniqueID,
+    Offline,
+    Private,
+    DriverOwner,
+    DriverVersion,
+    SupportsGeneralMIDI,
+    SupportsMMC,
+    CanRoute,
+    ReceivesClock,
+    ReceivesMTC,
+    ReceivesNotes,
+    ReceivesProgramChanges,
+    ReceivesBankSelectMSB,
+    ReceivesBankSelectLSB,
+    TransmitsBankSelectMSB,
+    TransmitsBankSelectLSB,
+    TransmitsClock,
+    TransmitsMTC,
+    TransmitsNotes,
+    TransmitsProgramChanges,
+    PanDisruptsStereo,
+    IsSampler,
+    IsDrumMachine,
+    IsMixer,
+    IsEffectUnit,
+    MaxReceiveChannels,
+    MaxTransmitChannels,
+    DriverDeviceEditorApp,
+    SupportsShowControl,
+    DisplayName,
+    Custom(String)
+}
+
 /// The set of properties that might be available for MIDI objects.
 ///
 pub struct Properties;
 
 impl Properties {
     /// See [kMIDIPropertyName](https://developer.apple.com/reference/coremidi/kmidipropertyname)
-    pub fn name()               -> StringProperty { unsafe { StringProperty(kMIDIPropertyName) } }
+    pub fn name()               -> StringProperty { StringProperty(PropertyKey::Name) }
     /// See [kMIDIPropertyManufacturer](https://developer.apple.com/reference/coremidi/kmidipropertymanufacturer)
-    pub fn manufacturer()       -> StringProperty { unsafe { StringProperty(kMIDIPropertyManufacturer) } }
+    pub fn manufacturer()       -> StringProperty { StringProperty(PropertyKey::Manufacturer) }
     /// See [kMIDIPropertyModel](https://developer.apple.com/reference/coremidi/kmidipropertymodel)
-    pub fn model()              -> StringProperty { unsafe { StringProperty(kMIDIPropertyModel) } }
+    pub fn model()              -> StringProperty { StringProperty(PropertyKey::Model) }
     /// See [kMIDIPropertyUniqueID](https://developer.apple.com/reference/coremidi/kmidipropertyuniqueid)
-    pub fn unique_id()          -> IntegerProperty { unsafe { IntegerProperty(kMIDIPropertyUniqueID) } }
+    pub fn unique_id()          -> IntegerProperty { IntegerProperty(PropertyKey::UniqueID) }
     /// See [kMIDIPropertyDeviceID](https://developer.apple.com/reference/coremidi/kmidipropertydeviceid)
-    pub fn device_id()          -> IntegerProperty { unsafe { IntegerProperty(kMIDIPropertyDeviceID) } }
+    pub fn device_id()          -> IntegerProperty { IntegerProperty(PropertyKey::DeviceID) }
     /// See [kMIDIPropertyReceiveChannels](https://developer.apple.com/reference/coremidi/kmidipropertyreceivechannels)
-    pub fn receive_channels()   -> IntegerProperty { unsafe { IntegerProperty(kMIDIPropertyReceiveChannels) } }
+    pub fn receive_channels()   -> IntegerProperty { IntegerProperty(PropertyKey::ReceiveChannels) }
     /// See [kMIDIPropertyTransmitChannels](https://developer.apple.com/reference/coremidi/kmidipropertytransmitchannels)
-    pub fn transmit_channels()  -> IntegerProperty { unsafe { IntegerProperty(kMIDIPropertyTransmitChannels) } }
+    pub fn transmit_channels()  -> IntegerProperty { IntegerProperty(PropertyKey::TransmitChannels) }
     /// See [kMIDIPropertyMaxSysExSpeed](https://developer.apple.com/reference/coremidi/kmidipropertymaxsysexspeed)
-    pub fn max_sysex_speed()    -> IntegerProperty { unsafe { IntegerProperty(kMIDIPropertyMaxSysExSpeed) } }
+    pub fn max_sysex_speed()    -> IntegerProperty { IntegerProperty(PropertyKey::MaxSysExSpeed) }
     /// See [kMIDIPropertyAdvanceScheduleTimeMuSec](https://developer.apple.com/reference/coremidi/kMIDIPropertyAdvanceScheduleTimeMuSec)
-    pub fn advance_schedule_time_musec() -> IntegerProperty { unsafe { IntegerProperty(kMIDIPropertyAdvanceScheduleTimeMuSec) } }
+    pub fn advance_schedule_time_musec() -> IntegerProperty { IntegerProperty(PropertyKey::AdvanceScheduleTimeMuSec) }
     /// See [kMIDIPropertyIsEmbeddedEntity](https://developer.apple.com/reference/coremidi/kMIDIPropertyIsEmbeddedEntity)
-    pub fn is_embedded_entity() -> BooleanProperty { unsafe { BooleanProperty(kMIDIPropertyIsEmbeddedEntity) } }
+    pub fn is_embedded_entity() -> BooleanProperty { BooleanProperty(PropertyKey::IsEmbeddedEntity) }
     /// See [kMIDIPropertyIsBroadcast](https://developer.apple.com/reference/coremidi/kMIDIPropertyIsBroadcast)
-    pub fn is_broadcast()       -> BooleanProperty { unsafe { BooleanProperty(kMIDIPropertyIsBroadcast) } }
+    pub fn is_broadcast()       -> BooleanProperty { BooleanProperty(PropertyKey::IsBroadcast) }
     /// See [kMIDIPropertySingleRealtimeEntity](https://developer.apple.com/reference/coremidi/kMIDIPropertySingleRealtimeEntity)
-    pub fn single_realtime_entity() -> IntegerProperty { unsafe { IntegerProperty(kMIDIPropertySingleRealtimeEntity) } }
+    pub fn single_realtime_entity() -> IntegerProperty { IntegerProperty(PropertyKey::SingleRealtimeEntity) }
     /// See [kMIDIPropertyConnectionUniqueID](https://developer.apple.com/reference/coremidi/kMIDIPropertyConnectionUniqueID)
-    pub fn connection_unique_id() -> IntegerProperty { unsafe { IntegerProperty(kMIDIPropertyConnectionUniqueID) } }
+    pub fn connection_unique_id() -> IntegerProperty { IntegerProperty(PropertyKey::ConnectionUniqueID) }
     /// See [kMIDIPropertyOffline](https://developer.apple.com/reference/coremidi/kMIDIPropertyOffline)
-    pub fn offline()            -> BooleanProperty { unsafe { BooleanProperty(kMIDIPropertyOffline) } }
+    pub fn offline()            -> BooleanProperty { BooleanProperty(PropertyKey::Offline) }
     /// See [kMIDIPropertyPrivate](https://developer.apple.com/reference/coremidi/kMIDIPropertyPrivate)
-    pub fn private()            -> BooleanProperty { unsafe { BooleanProperty(kMIDIPropertyPrivate) } }
+    pub fn private()            -> BooleanProperty { BooleanProperty(PropertyKey::Private) }
     /// See [kMIDIPropertyDriverOwner](https://developer.apple.com/reference/coremidi/kMIDIPropertyDriverOwner)
-    pub fn driver_owner()       -> StringProperty { unsafe { StringProperty(kMIDIPropertyDriverOwner) } }
+    pub fn driver_owner()       -> StringProperty { StringProperty(PropertyKey::DriverOwner) }
     // /// See [kMIDIPropertyNameConfiguration](https://developer.apple.com/reference/coremidi/kMIDIPropertyNameConfiguration)
-    // pub fn name_configuration() -> Property { unsafe { Property(kMIDIPropertyNameConfiguration) } }
+    // pub fn name_configuration() -> Property { Property(PropertyKey::NameConfiguration) }
     // /// See [kMIDIPropertyImage](https://developer.apple.com/reference/coremidi/kMIDIPropertyImage)
-    // pub fn image() -> Property { unsafe { Property(kMIDIPropertyImage) } }
+    // pub fn image() -> Property { Property(PropertyKey::Image) }
     /// See [kMIDIPropertyDriverVersion](https://developer.apple.com/reference/coremidi/kMIDIPropertyDriverVersion)
-    pub fn driver_version()     -> IntegerProperty { unsafe { IntegerProperty(kMIDIPropertyDriverVersion) } }
+    pub fn driver_version()     -> IntegerProperty { IntegerProperty(PropertyKey::DriverVersion) }
     /// See [kMIDIPropertySupportsGeneralMIDI](https://developer.apple.com/reference/coremidi/kMIDIPropertySupportsGeneralMIDI)
-    pub fn supports_general_midi() -> BooleanProperty { unsafe { BooleanProperty(kMIDIPropertySupportsGeneralMIDI) } }
+    pub fn supports_general_midi() -> BooleanProperty { BooleanProperty(PropertyKey::SupportsGeneralMIDI) }
     /// See [kMIDIPropertySupportsMMC](https://developer.apple.com/reference/coremidi/kMIDIPropertySupportsMMC)
-    pub fn supports_mmc()       -> BooleanProperty { unsafe { BooleanProperty(kMIDIPropertySupportsMMC) } }
+    pub fn supports_mmc()       -> BooleanProperty { BooleanProperty(PropertyKey::SupportsMMC) }
     /// See [kMIDIPropertyCanRoute](https://developer.apple.com/reference/coremidi/kMIDIPropertyCanRoute)
-    pub fn can_route()          -> BooleanProperty { unsafe { BooleanProperty(kMIDIPropertyCanRoute) } }
+    pub fn can_route()          -> BooleanProperty { BooleanProperty(PropertyKey::CanRoute) }
     /// See [kMIDIPropertyReceivesClock](https://developer.apple.com/reference/coremidi/kMIDIPropertyReceivesClock)
-    pub fn receives_clock()     -> BooleanProperty { unsafe { BooleanProperty(kMIDIPropertyReceivesClock) } }
+    pub fn receives_clock()     -> BooleanProperty { BooleanProperty(PropertyKey::ReceivesClock) }
     /// See [kMIDIPropertyReceivesMTC](https://developer.apple.com/reference/coremidi/kMIDIPropertyReceivesMTC)
-    pub fn receives_mtc()       -> BooleanProperty { unsafe { BooleanProperty(kMIDIPropertyReceivesMTC) } }
+    pub fn receives_mtc()       -> BooleanProperty { BooleanProperty(PropertyKey::ReceivesMTC) }
     /// See [kMIDIPropertyReceivesNotes](https://developer.apple.com/reference/coremidi/kMIDIPropertyReceivesNotes)
-    pub fn receives_notes()     -> BooleanProperty { unsafe { BooleanProperty(kMIDIPropertyReceivesNotes) } }
+    pub fn receives_notes()     -> BooleanProperty { BooleanProperty(PropertyKey::ReceivesNotes) }
     /// See [kMIDIPropertyReceivesProgramChanges](https://developer.apple.com/reference/coremidi/kMIDIPropertyReceivesProgramChanges)
-    pub fn receives_program_changes() -> BooleanProperty { unsafe { BooleanProperty(kMIDIPropertyReceivesProgramChanges) } }
+    pub fn receives_program_changes() -> BooleanProperty { BooleanProperty(PropertyKey::ReceivesProgramChanges) }
     /// See [kMIDIPropertyReceivesBankSelectMSB](https://developer.apple.com/reference/coremidi/kMIDIPropertyReceivesBankSelectMSB)
-    pub fn receives_bank_select_msb() -> BooleanProperty { unsafe { BooleanProperty(kMIDIPropertyReceivesBankSelectMSB) } }
+    pub fn receives_bank_select_msb() -> BooleanProperty { BooleanProperty(PropertyKey::ReceivesBankSelectMSB) }
     /// See [kMIDIPropertyReceivesBankSelectLSB](https://developer.apple.com/reference/coremidi/kMIDIPropertyReceivesBankSelectLSB)
-    pub fn receives_bank_select_lsb() -> BooleanProperty { unsafe { BooleanProperty(kMIDIPropertyReceivesBankSelectLSB) } }
+    pub fn receives_bank_select_lsb() -> BooleanProperty { BooleanProperty(PropertyKey::ReceivesBankSelectLSB) }
     /// See [kMIDIPropertyTransmitsBankSelectMSB](https://developer.apple.com/reference/coremidi/kMIDIPropertyTransmitsBankSelectMSB)
-    pub fn transmits_bank_select_msb() -> BooleanProperty { unsafe { BooleanProperty(kMIDIPropertyTransmitsBankSelectMSB) } }
+    pub fn transmits_bank_select_msb() -> BooleanProperty { BooleanProperty(PropertyKey::TransmitsBankSelectMSB) }
     /// See [kMIDIPropertyTransmitsBankSelectLSB](https://developer.apple.com/reference/coremidi/kMIDIPropertyTransmitsBankSelectLSB)
-    pub fn transmits_bank_select_lsb() -> BooleanProperty { unsafe { BooleanProperty(kMIDIPropertyTransmitsBankSelectLSB) } }
+    pub fn transmits_bank_select_lsb() -> BooleanProperty { BooleanProperty(PropertyKey::TransmitsBankSelectLSB) }
     /// See [kMIDIPropertyTransmitsClock](https://developer.apple.com/reference/coremidi/kMIDIPropertyTransmitsClock)
-    pub fn transmits_clock()    -> BooleanProperty { unsafe { BooleanProperty(kMIDIPropertyTransmitsClock) } }
+    pub fn transmits_clock()    -> BooleanProperty { BooleanProperty(PropertyKey::TransmitsClock) }
     /// See [kMIDIPropertyTransmitsMTC](https://developer.apple.com/reference/coremidi/kMIDIPropertyTransmitsMTC)
-    pub fn transmits_mtc()      -> BooleanProperty { unsafe { BooleanProperty(kMIDIPropertyTransmitsMTC) } }
+    pub fn transmits_mtc()      -> BooleanProperty { BooleanProperty(PropertyKey::TransmitsMTC) }
     /// See [kMIDIPropertyTransmitsNotes](https://developer.apple.com/reference/coremidi/kMIDIPropertyTransmitsNotes)
-    pub fn transmits_notes()    -> BooleanProperty { unsafe { BooleanProperty(kMIDIPropertyTransmitsNotes) } }
+    pub fn transmits_notes()    -> BooleanProperty { BooleanProperty(PropertyKey::TransmitsNotes) }
     /// See [kMIDIPropertyTransmitsProgramChanges](https://developer.apple.com/reference/coremidi/kMIDIPropertyTransmitsProgramChanges)
-    pub fn transmits_program_changes() -> BooleanProperty { unsafe { BooleanProperty(kMIDIPropertyTransmitsProgramChanges) } }
+    pub fn transmits_program_changes() -> BooleanProperty { BooleanProperty(PropertyKey::TransmitsProgramChanges) }
     /// See [kMIDIPropertyPanDisruptsStereo](https://developer.apple.com/reference/coremidi/kMIDIPropertyPanDisruptsStereo)
-    pub fn pan_disrupts_stereo() -> BooleanProperty { unsafe { BooleanProperty(kMIDIPropertyPanDisruptsStereo) } }
+    pub fn pan_disrupts_stereo() -> BooleanProperty { BooleanProperty(PropertyKey::PanDisruptsStereo) }
     /// See [kMIDIPropertyIsSampler](https://developer.apple.com/reference/coremidi/kMIDIPropertyIsSampler)
-    pub fn is_sampler()          -> BooleanProperty { unsafe { BooleanProperty(kMIDIPropertyIsSampler) } }
+    pub fn is_sampler()          -> BooleanProperty { BooleanProperty(PropertyKey::IsSampler) }
     /// See [kMIDIPropertyIsDrumMachine](https://developer.apple.com/reference/coremidi/kMIDIPropertyIsDrumMachine)
-    pub fn is_drum_machine()     -> BooleanProperty { unsafe { BooleanProperty(kMIDIPropertyIsDrumMachine) } }
+    pub fn is_drum_machine()     -> BooleanProperty { BooleanProperty(PropertyKey::IsDrumMachine) }
     /// See [kMIDIPropertyIsMixer](https://developer.apple.com/reference/coremidi/kMIDIPropertyIsMixer)
-    pub fn is_mixer()            -> BooleanProperty { unsafe { BooleanProperty(kMIDIPropertyIsMixer) } }
+    pub fn is_mixer()            -> BooleanProperty { BooleanProperty(PropertyKey::IsMixer) }
     /// See [kMIDIPropertyIsEffectUnit](https://developer.apple.com/reference/coremidi/kMIDIPropertyIsEffectUnit)
-    pub fn is_effect_unit()      -> BooleanProperty { unsafe { BooleanProperty(kMIDIPropertyIsEffectUnit) } }
+    pub fn is_effect_unit()      -> BooleanProperty { BooleanProperty(PropertyKey::IsEffectUnit) }
     /// See [kMIDIPropertyMaxReceiveChannels](https://developer.apple.com/reference/coremidi/kMIDIPropertyMaxReceiveChannels)
-    pub fn max_receive_channels() -> IntegerProperty { unsafe { IntegerProperty(kMIDIPropertyMaxReceiveChannels) } }
+    pub fn max_receive_channels() -> IntegerProperty { IntegerProperty(PropertyKey::MaxReceiveChannels) }
     /// See [kMIDIPropertyMaxTransmitChannels](https://developer.apple.com/reference/coremidi/kMIDIPropertyMaxTransmitChannels)
-    pub fn max_transmit_channels() -> IntegerProperty { unsafe { IntegerProperty(kMIDIPropertyMaxTransmitChannels) } }
+    pub fn max_transmit_channels() -> IntegerProperty { IntegerProperty(PropertyKey::MaxTransmitChannels) }
     /// See [kMIDIPropertyDriverDeviceEditorApp](https://developer.apple.com/reference/coremidi/kMIDIPropertyDriverDeviceEditorApp)
-    pub fn driver_device_editor_app() -> StringProperty { unsafe { StringProperty(kMIDIPropertyDriverDeviceEditorApp) } }
+    pub fn driver_device_editor_app() -> StringProperty { StringProperty(PropertyKey::DriverDeviceEditorApp) }
     /// See [kMIDIPropertySupportsShowControl](https://developer.apple.com/reference/coremidi/kMIDIPropertySupportsShowControl)
-    pub fn supports_show_control() -> BooleanProperty { unsafe { BooleanProperty(kMIDIPropertySupportsShowControl) } }
+    pub fn supports_show_control() -> BooleanProperty { BooleanProperty(PropertyKey::SupportsShowControl) }
     /// See [kMIDIPropertyDisplayName](https://developer.apple.com/reference/coremidi/kMIDIPropertyDisplayName)
-    pub fn display_name()        -> StringProperty { unsafe { StringProperty(kMIDIPropertyDisplayName) } }
+    pub fn display_name()        -> StringProperty { StringProperty(PropertyKey::DisplayName) }
 }
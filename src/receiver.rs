@@ -0,0 +1,159 @@
+use std::cmp;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, SyncSender, TrySendError, RecvError, TryRecvError, RecvTimeoutError};
+use std::time::Duration;
+
+use packets::{PacketRef, Timestamp};
+use messages::MessagesIterator;
+
+/// The default number of packets that a receiver can hold before it starts dropping them.
+///
+pub const DEFAULT_RECEIVER_CAPACITY: usize = 1024;
+
+/// A copy of the data of a received packet, together with its timestamp.
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimestampedMessage {
+    pub timestamp: Timestamp,
+    pub data: Vec<u8>,
+}
+
+impl TimestampedMessage {
+    /// Get an iterator for the MIDI messages in the data.
+    ///
+    pub fn messages(&self) -> MessagesIterator<'_> {
+        MessagesIterator::new(&self.data)
+    }
+}
+
+impl<'a> From<PacketRef<'a>> for TimestampedMessage {
+    fn from(packet: PacketRef<'a>) -> Self {
+        TimestampedMessage {
+            timestamp: packet.timestamp(),
+            data: packet.data().to_vec(),
+        }
+    }
+}
+
+/// The receiving half of a bounded channel fed from the CoreMIDI thread.
+///
+/// The sending side never blocks. When the channel is full the incoming values are dropped,
+/// and counted as overflows.
+///
+pub struct Receiver<T> {
+    receiver: mpsc::Receiver<T>,
+    overflows: Arc<AtomicUsize>,
+}
+
+impl<T> Receiver<T> {
+    /// Block until a value is received, or return an error if the sender is gone (the port was dropped).
+    ///
+    pub fn recv(&self) -> Result<T, RecvError> {
+        self.receiver.recv()
+    }
+
+    /// Return a value if there is one available, without blocking.
+    ///
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        self.receiver.try_recv()
+    }
+
+    /// Block until a value is received or the timeout expires.
+    ///
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        self.receiver.recv_timeout(timeout)
+    }
+
+    /// Get an iterator over the values that are already available, without blocking.
+    ///
+    pub fn try_iter(&self) -> mpsc::TryIter<'_, T> {
+        self.receiver.try_iter()
+    }
+
+    /// The number of values that were dropped because the channel was full.
+    ///
+    pub fn overflow_count(&self) -> usize {
+        self.overflows.load(Ordering::Relaxed)
+    }
+}
+
+pub struct Sender<T> {
+    sender: SyncSender<T>,
+    overflows: Arc<AtomicUsize>,
+}
+
+impl<T> Sender<T> {
+    // Returns whether the receiver is still connected.
+    pub fn send(&self, value: T) -> bool {
+        match self.sender.try_send(value) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                self.overflows.fetch_add(1, Ordering::Relaxed);
+                true
+            },
+            Err(TrySendError::Disconnected(_)) => false
+        }
+    }
+}
+
+// A capacity of 0 is taken as 1, as a rendezvous channel would drop every value sent from a callback.
+pub fn channel<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    let (sender, receiver) = mpsc::sync_channel(cmp::max(capacity, 1));
+    let overflows = Arc::new(AtomicUsize::new(0));
+    (Sender { sender: sender, overflows: overflows.clone() },
+     Receiver { receiver: receiver, overflows: overflows })
+}
+
+#[cfg(test)]
+mod tests {
+    use receiver::{channel, TimestampedMessage};
+    use packets::PacketBuffer;
+    use messages::MidiMessage;
+
+    #[test]
+    fn channel_counts_overflows() {
+        let (sender, receiver) = channel(2);
+        sender.send(1);
+        sender.send(2);
+        sender.send(3);
+        assert_eq!(receiver.overflow_count(), 1);
+        assert_eq!(receiver.try_iter().collect::<Vec<_>>(), vec![1, 2]);
+
+        sender.send(4);
+        assert_eq!(receiver.overflow_count(), 1);
+        assert_eq!(receiver.try_recv(), Ok(4));
+    }
+
+    #[test]
+    fn channel_capacity_is_at_least_one() {
+        let (sender, receiver) = channel(0);
+        sender.send(1);
+        sender.send(2);
+        assert_eq!(receiver.overflow_count(), 1);
+        assert_eq!(receiver.try_iter().collect::<Vec<_>>(), vec![1]);
+    }
+
+    #[test]
+    fn channel_disconnected() {
+        let (sender, receiver) = channel::<u8>(2);
+        assert!(sender.send(1));
+        drop(receiver);
+        assert!(!sender.send(2));
+
+        let (sender, receiver) = channel::<u8>(2);
+        drop(sender);
+        assert!(receiver.recv().is_err());
+    }
+
+    #[test]
+    fn timestamped_message_from_packet() {
+        let mut packet_buffer = PacketBuffer::dyn();
+        packet_buffer.push_packet(42, &[0x90, 0x40, 0x7f]);
+        let packet = packet_buffer.as_ref().iter().next().unwrap();
+        let message = TimestampedMessage::from(packet);
+        assert_eq!(message, TimestampedMessage { timestamp: 42, data: vec![0x90, 0x40, 0x7f] });
+        assert_eq!(message.messages().collect::<Vec<_>>(),
+            vec![Ok(MidiMessage::NoteOn { channel: 0, note: 0x40, velocity: 0x7f })]);
+    }
+}
@@ -0,0 +1,284 @@
+use std::cmp;
+use std::ptr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use packets::{PacketRef, Timestamp};
+
+const RECORD_HEADER_SIZE: usize = 10;
+
+/// Create a wait-free single-producer/single-consumer ring buffer of packets.
+///
+/// The capacity is given in bytes, and rounded up to the next power of two. Every packet takes
+/// 10 bytes for its timestamp and length, plus its data.
+///
+/// The producer never allocates nor locks, so it can be used from the callback of an input port,
+/// while the consumer drains the packets from another thread:
+///
+/// ```rust,no_run
+/// let (mut producer, mut consumer) = coremidi::packet_ring(64 * 1024);
+/// let client = coremidi::Client::new("example-client").unwrap();
+/// let input_port = client.input_port("example-port", move |packet_list| {
+///     for packet in packet_list.iter() {
+///         producer.push(packet);
+///     }
+/// }).unwrap();
+/// input_port.connect_source(&coremidi::Source::from_index(0)).unwrap();
+///
+/// let mut data = Vec::new();
+/// loop {
+///     while let Some(timestamp) = consumer.pop_into(&mut data) {
+///         println!("{}: {:?}", timestamp, data);
+///     }
+///     std::thread::sleep(std::time::Duration::from_millis(1));
+/// }
+/// ```
+pub fn packet_ring(capacity: usize) -> (PacketProducer, PacketConsumer) {
+    let capacity = cmp::max(capacity, RECORD_HEADER_SIZE).next_power_of_two();
+    let shared = Arc::new(Shared::new(capacity));
+    (PacketProducer { shared: shared.clone() }, PacketConsumer { shared: shared })
+}
+
+/// The writing half of a [packet_ring](fn.packet_ring.html).
+///
+pub struct PacketProducer {
+    shared: Arc<Shared>,
+}
+
+/// The reading half of a [packet_ring](fn.packet_ring.html).
+///
+pub struct PacketConsumer {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    data: *mut u8,
+    capacity: usize,
+    // Positions are only ever incremented, and wrap around usize.
+    // The capacity being a power of two keeps them consistent with the indices in data.
+    head: AtomicUsize,
+    tail: AtomicUsize,
+    dropped: AtomicUsize,
+}
+
+// data is only written by the producer in the free region between head and tail,
+// and only read by the consumer in the used region, as published through head and tail.
+unsafe impl Send for Shared {}
+unsafe impl Sync for Shared {}
+
+impl Shared {
+    fn new(capacity: usize) -> Shared {
+        let data = vec![0u8; capacity].into_boxed_slice();
+        Shared {
+            data: Box::into_raw(data) as *mut u8,
+            capacity: capacity,
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            dropped: AtomicUsize::new(0),
+        }
+    }
+
+    unsafe fn write_at(&self, position: usize, bytes: &[u8]) {
+        let start = position & (self.capacity - 1);
+        let first = cmp::min(bytes.len(), self.capacity - start);
+        ptr::copy_nonoverlapping(bytes.as_ptr(), self.data.add(start), first);
+        ptr::copy_nonoverlapping(bytes.as_ptr().add(first), self.data, bytes.len() - first);
+    }
+
+    unsafe fn read_at(&self, position: usize, bytes: &mut [u8]) {
+        let start = position & (self.capacity - 1);
+        let first = cmp::min(bytes.len(), self.capacity - start);
+        ptr::copy_nonoverlapping(self.data.add(start), bytes.as_mut_ptr(), first);
+        ptr::copy_nonoverlapping(self.data, bytes.as_mut_ptr().add(first), bytes.len() - first);
+    }
+}
+
+impl Drop for Shared {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = Box::from_raw(ptr::slice_from_raw_parts_mut(self.data, self.capacity));
+        }
+    }
+}
+
+impl PacketProducer {
+    /// Push a copy of a packet, or drop it if there is not enough space left.
+    ///
+    /// It returns whether the packet was pushed.
+    ///
+    pub fn push(&mut self, packet: PacketRef) -> bool {
+        self.push_data(packet.timestamp(), packet.data())
+    }
+
+    /// Push a packet with some data, or drop it if there is not enough space left.
+    ///
+    /// It returns whether the packet was pushed.
+    ///
+    pub fn push_data(&mut self, timestamp: Timestamp, data: &[u8]) -> bool {
+        let shared = &*self.shared;
+        let head = shared.head.load(Ordering::Relaxed);
+        let tail = shared.tail.load(Ordering::Acquire);
+        let free = shared.capacity - head.wrapping_sub(tail);
+        let record_size = RECORD_HEADER_SIZE + data.len();
+        if data.len() > 0xffff || record_size > free {
+            shared.dropped.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+
+        let mut header = [0u8; RECORD_HEADER_SIZE];
+        for (i, byte) in header[..8].iter_mut().enumerate() {
+            *byte = (timestamp >> (8 * i)) as u8;
+        }
+        header[8] = data.len() as u8;
+        header[9] = (data.len() >> 8) as u8;
+
+        unsafe {
+            shared.write_at(head, &header);
+            shared.write_at(head.wrapping_add(RECORD_HEADER_SIZE), data);
+        }
+        shared.head.store(head.wrapping_add(record_size), Ordering::Release);
+        true
+    }
+
+    /// The number of packets that were dropped because there was not enough space left.
+    ///
+    pub fn dropped_count(&self) -> usize {
+        self.shared.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl PacketConsumer {
+    /// Pop the oldest packet, copying its data into `data` and returning its timestamp.
+    ///
+    /// It returns `None` when there are no packets available.
+    ///
+    pub fn pop_into(&mut self, data: &mut Vec<u8>) -> Option<Timestamp> {
+        let shared = &*self.shared;
+        let tail = shared.tail.load(Ordering::Relaxed);
+        let head = shared.head.load(Ordering::Acquire);
+        if head == tail {
+            return None;
+        }
+
+        let mut header = [0u8; RECORD_HEADER_SIZE];
+        unsafe { shared.read_at(tail, &mut header) };
+        let timestamp = (0..8).fold(0, |timestamp, i| timestamp | (header[i] as Timestamp) << (8 * i));
+        let length = header[8] as usize | (header[9] as usize) << 8;
+
+        data.clear();
+        data.resize(length, 0);
+        unsafe { shared.read_at(tail.wrapping_add(RECORD_HEADER_SIZE), data) };
+        shared.tail.store(tail.wrapping_add(RECORD_HEADER_SIZE + length), Ordering::Release);
+        Some(timestamp)
+    }
+
+    /// Whether there are no packets available.
+    ///
+    pub fn is_empty(&self) -> bool {
+        self.shared.head.load(Ordering::Acquire) == self.shared.tail.load(Ordering::Relaxed)
+    }
+
+    /// The number of packets that were dropped by the producer because there was not enough space left.
+    ///
+    pub fn dropped_count(&self) -> usize {
+        self.shared.dropped.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use ring::packet_ring;
+    use packets::PacketBuffer;
+
+    #[test]
+    fn push_and_pop() {
+        let (mut producer, mut consumer) = packet_ring(64);
+        let mut data = Vec::new();
+        assert!(consumer.is_empty());
+        assert_eq!(consumer.pop_into(&mut data), None);
+
+        let mut packet_buffer = PacketBuffer::dyn();
+        packet_buffer.push_packet(42, &[0x90, 0x40, 0x7f]);
+        assert!(producer.push(packet_buffer.as_ref().iter().next().unwrap()));
+        assert!(producer.push_data(43, &[0xf8]));
+        assert!(!consumer.is_empty());
+
+        assert_eq!(consumer.pop_into(&mut data), Some(42));
+        assert_eq!(data, vec![0x90, 0x40, 0x7f]);
+        assert_eq!(consumer.pop_into(&mut data), Some(43));
+        assert_eq!(data, vec![0xf8]);
+        assert_eq!(consumer.pop_into(&mut data), None);
+        assert!(consumer.is_empty());
+    }
+
+    #[test]
+    fn drops_when_full() {
+        let (mut producer, mut consumer) = packet_ring(32);
+        assert!(producer.push_data(1, &[0; 10]));
+        assert!(!producer.push_data(2, &[0; 3]));
+        assert!(producer.push_data(3, &[0; 2]));
+        assert!(!producer.push_data(4, &[]));
+        assert!(!producer.push_data(5, &[0; 40]));
+        assert_eq!(producer.dropped_count(), 3);
+        assert_eq!(consumer.dropped_count(), 3);
+
+        let mut data = Vec::new();
+        assert_eq!(consumer.pop_into(&mut data), Some(1));
+        assert!(producer.push_data(6, &[0; 5]));
+        assert_eq!(consumer.pop_into(&mut data), Some(3));
+        assert_eq!(consumer.pop_into(&mut data), Some(6));
+        assert_eq!(consumer.pop_into(&mut data), None);
+    }
+
+    #[test]
+    fn wraps_around() {
+        let (mut producer, mut consumer) = packet_ring(16);
+        let mut data = Vec::new();
+        for i in 0..100u8 {
+            let packet = [i, i.wrapping_add(1), i.wrapping_add(2)];
+            assert!(producer.push_data(i as u64, &packet[..(i % 4) as usize]));
+            assert_eq!(consumer.pop_into(&mut data), Some(i as u64));
+            assert_eq!(&data[..], &packet[..(i % 4) as usize]);
+        }
+    }
+
+    #[test]
+    fn stress_between_threads() {
+        const COUNT: u64 = 100_000;
+        let (mut producer, mut consumer) = packet_ring(256);
+
+        let producer_thread = thread::spawn(move || {
+            let mut packet = [0u8; 16];
+            for i in 0..COUNT {
+                let length = (i % 17) as usize;
+                for (j, byte) in packet[..length].iter_mut().enumerate() {
+                    *byte = (i as usize + j) as u8;
+                }
+                while !producer.push_data(i, &packet[..length]) {
+                    thread::yield_now();
+                }
+            }
+        });
+
+        let mut data = Vec::new();
+        let mut expected = 0;
+        while expected < COUNT {
+            match consumer.pop_into(&mut data) {
+                Some(timestamp) => {
+                    assert_eq!(timestamp, expected);
+                    assert_eq!(data.len(), (expected % 17) as usize);
+                    for (j, byte) in data.iter().enumerate() {
+                        assert_eq!(*byte, (expected as usize + j) as u8);
+                    }
+                    expected += 1;
+                },
+                None => thread::yield_now()
+            }
+        }
+
+        producer_thread.join().unwrap();
+        assert!(consumer.is_empty());
+    }
+}
@@ -0,0 +1,348 @@
+use std::error;
+use std::fmt;
+use std::io;
+
+use packets::{DynPacketBuffer, PacketBuffer, Timestamp};
+use time::Timebase;
+
+mod reader;
+mod writer;
+
+pub use self::writer::SmfWriter;
+
+// The maximum number of data bytes in a packet.
+const MAX_PACKET_DATA_LENGTH: usize = 0xffff;
+
+// The tempo until the first tempo event, in microseconds per quarter note (120 bpm).
+const DEFAULT_TEMPO: u32 = 500_000;
+
+/// The organisation of the tracks of a [Standard MIDI File](https://www.midi.org/specifications).
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SmfFormat {
+    /// Format 0: a single track.
+    SingleTrack,
+    /// Format 1: simultaneous tracks, with the tempo map in the first one.
+    MultipleTrack,
+    /// Format 2: independent single-track patterns.
+    MultipleSong,
+}
+
+/// The meaning of the delta times of a Standard MIDI File.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SmfTiming {
+    /// Ticks per quarter note, the actual time depending on the tempo.
+    TicksPerQuarterNote(u16),
+    /// Ticks per frame of SMPTE time code, with 29 meaning 29.97 frames per second (drop frame).
+    Smpte { frames_per_second: u8, ticks_per_frame: u8 },
+}
+
+/// An error found while reading or writing a Standard MIDI File.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SmfError {
+    /// The data doesn't start with a `MThd` header chunk.
+    NotSmf,
+    /// The header chunk is shorter than required, or has invalid values.
+    InvalidHeader,
+    /// The format in the header is not 0, 1 or 2.
+    UnsupportedFormat(u16),
+    /// The data ends before the end of a chunk, or before all the tracks announced by the header.
+    UnexpectedEnd,
+    /// A variable-length quantity is longer than 4 bytes.
+    InvalidVarLen,
+    /// A data byte was found at the beginning of an event, without a running status.
+    MissingRunningStatus,
+    /// The status byte is not valid for an event of a track.
+    InvalidStatus(u8),
+    /// The meta event of this type has an invalid length.
+    InvalidMetaEvent(u8),
+    /// A format 0 file can only have one track.
+    TooManyTracks,
+    /// An error reading or writing the data.
+    Io(io::ErrorKind),
+}
+
+impl fmt::Display for SmfError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SmfError::NotSmf => write!(f, "not a Standard MIDI File"),
+            SmfError::InvalidHeader => write!(f, "invalid header chunk"),
+            SmfError::UnsupportedFormat(format) => write!(f, "unsupported format {}", format),
+            SmfError::UnexpectedEnd => write!(f, "unexpected end of data"),
+            SmfError::InvalidVarLen => write!(f, "invalid variable-length quantity"),
+            SmfError::MissingRunningStatus => write!(f, "data byte without running status"),
+            SmfError::InvalidStatus(status) => write!(f, "invalid status byte {:02x}", status),
+            SmfError::InvalidMetaEvent(kind) => write!(f, "invalid meta event {:02x}", kind),
+            SmfError::TooManyTracks => write!(f, "too many tracks for format 0"),
+            SmfError::Io(kind) => write!(f, "I/O error: {:?}", kind),
+        }
+    }
+}
+
+impl error::Error for SmfError {}
+
+impl From<io::Error> for SmfError {
+    fn from(err: io::Error) -> SmfError {
+        if err.kind() == io::ErrorKind::UnexpectedEof { SmfError::UnexpectedEnd } else { SmfError::Io(err.kind()) }
+    }
+}
+
+/// A meta event, carrying information that is not sent as MIDI.
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MetaEvent {
+    SequenceNumber(u16),
+    /// A text event of the given type, from `0x01` (text) to `0x0f`,
+    /// like `0x03` for the track name or `0x05` for lyrics.
+    Text(u8, Vec<u8>),
+    ChannelPrefix(u8),
+    Port(u8),
+    EndOfTrack,
+    /// The tempo in microseconds per quarter note.
+    Tempo(u32),
+    SmpteOffset { hours: u8, minutes: u8, seconds: u8, frames: u8, fractional_frames: u8 },
+    /// A time signature, with the denominator as a power of two.
+    TimeSignature { numerator: u8, denominator: u8, clocks_per_click: u8, thirty_seconds_per_quarter: u8 },
+    /// A key signature, with the number of sharps (or flats, when negative).
+    KeySignature { sharps: i8, minor: bool },
+    SequencerSpecific(Vec<u8>),
+    Unknown(u8, Vec<u8>),
+}
+
+/// What happens at an event of a track.
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SmfEventKind {
+    /// A channel message, with its status byte even when it was stored with running status.
+    Midi(Vec<u8>),
+    /// A SysEx message, starting with `F0`, and usually ending with `F7` unless it continues in escape events.
+    SysEx(Vec<u8>),
+    /// Any bytes to be sent as they are, like the continuation of a SysEx message.
+    Escape(Vec<u8>),
+    Meta(MetaEvent),
+}
+
+/// An event of a track.
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SmfEvent {
+    /// The ticks since the previous event of the track.
+    pub delta: u32,
+    /// The ticks since the beginning of the track.
+    pub tick: u64,
+    pub kind: SmfEventKind,
+}
+
+impl SmfEvent {
+    /// The bytes to be sent for the event, or `None` for meta events.
+    ///
+    pub fn midi_data(&self) -> Option<&[u8]> {
+        match self.kind {
+            SmfEventKind::Midi(ref data) |
+            SmfEventKind::SysEx(ref data) |
+            SmfEventKind::Escape(ref data) => Some(data),
+            SmfEventKind::Meta(_) => None
+        }
+    }
+}
+
+/// A track of a Standard MIDI File.
+///
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct SmfTrack {
+    pub events: Vec<SmfEvent>,
+}
+
+/// An event of a Standard MIDI File, with its absolute time.
+/// See [Smf::events](struct.Smf.html#method.events).
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimedEvent<'a> {
+    /// The index of the track of the event.
+    pub track: usize,
+    /// The microseconds since the beginning of the file, according to the tempo map.
+    pub micros: u64,
+    pub event: &'a SmfEvent,
+}
+
+impl<'a> TimedEvent<'a> {
+    /// The host time for the event when the file starts playing at `start`.
+    ///
+    pub fn timestamp(&self, start: Timestamp, timebase: &Timebase) -> Timestamp {
+        start.saturating_add(timebase.nanos_to_ticks(self.micros.saturating_mul(1000)))
+    }
+}
+
+/// The conversion from ticks to microseconds, given the timing and the tempo events of a file.
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TempoMap {
+    timing: SmfTiming,
+    // The tick where every tempo starts, the microseconds at that tick, and the tempo.
+    changes: Vec<(u64, u64, u32)>,
+}
+
+impl TempoMap {
+    /// Create a tempo map from the tempo events found in some tracks.
+    ///
+    pub fn new<'a, I>(timing: SmfTiming, tracks: I) -> TempoMap where I: IntoIterator<Item=&'a SmfTrack> {
+        let mut tempos = tracks.into_iter()
+            .flat_map(|track| track.events.iter())
+            .filter_map(|event| match event.kind {
+                SmfEventKind::Meta(MetaEvent::Tempo(tempo)) => Some((event.tick, tempo)),
+                _ => None
+            })
+            .collect::<Vec<_>>();
+        tempos.sort_by_key(|&(tick, _)| tick);
+
+        let mut tempo_map = TempoMap { timing: timing, changes: vec![(0, 0, DEFAULT_TEMPO)] };
+        for (tick, tempo) in tempos {
+            let micros = tempo_map.micros(tick);
+            if tick == tempo_map.changes[tempo_map.changes.len() - 1].0 {
+                tempo_map.changes.pop();
+            }
+            tempo_map.changes.push((tick, micros, tempo));
+        }
+        tempo_map
+    }
+
+    /// The microseconds at a tick.
+    ///
+    pub fn micros(&self, tick: u64) -> u64 {
+        let index = self.changes.iter().take_while(|&&(change_tick, _, _)| change_tick <= tick).count() - 1;
+        let (change_tick, change_micros, tempo) = self.changes[index];
+        let ticks = (tick - change_tick) as u128;
+        let micros = match self.timing {
+            SmfTiming::TicksPerQuarterNote(ticks_per_quarter) =>
+                ticks * tempo as u128 / non_zero(ticks_per_quarter as u128),
+            SmfTiming::Smpte { frames_per_second, ticks_per_frame } => {
+                // 29 stands for 30 * 1000 / 1001 frames per second
+                let (numer, denom) = if frames_per_second == 29 { (30_000, 1001) } else { (frames_per_second as u128, 1) };
+                ticks * 1_000_000 * denom / non_zero(numer * ticks_per_frame as u128)
+            }
+        };
+        change_micros.saturating_add(if micros > u64::MAX as u128 { u64::MAX } else { micros as u64 })
+    }
+}
+
+fn non_zero(value: u128) -> u128 {
+    if value == 0 { 1 } else { value }
+}
+
+/// A [Standard MIDI File](https://www.midi.org/specifications).
+///
+/// It can be parsed from its bytes, and its events converted into packets to be sent:
+///
+/// ```rust,no_run
+/// use coremidi::{HostTime, Timestamp};
+/// let bytes = std::fs::read("performance.mid").unwrap();
+/// let smf = coremidi::Smf::parse(&bytes).unwrap();
+/// let packets = smf.to_packet_buffer(Timestamp::now(), &coremidi::Timebase::host());
+///
+/// let client = coremidi::Client::new("example-client").unwrap();
+/// let output_port = client.output_port("example-port").unwrap();
+/// output_port.send(&coremidi::Destination::from_index(0), packets.as_ref()).unwrap();
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Smf {
+    pub format: SmfFormat,
+    pub timing: SmfTiming,
+    pub tracks: Vec<SmfTrack>,
+}
+
+impl Smf {
+    /// The tempo map from the tempo events of all the tracks, as used for formats 0 and 1.
+    ///
+    pub fn tempo_map(&self) -> TempoMap {
+        TempoMap::new(self.timing, &self.tracks)
+    }
+
+    /// The events of all the tracks, ordered by time, and by track for the same time.
+    ///
+    /// The tracks are played simultaneously, as for formats 0 and 1. For format 2, see [track_events](#method.track_events).
+    ///
+    pub fn events(&self) -> Vec<TimedEvent<'_>> {
+        let tempo_map = self.tempo_map();
+        let mut events = self.tracks.iter().enumerate()
+            .flat_map(|(index, track)| track.events.iter().map(move |event| (index, event)))
+            .collect::<Vec<_>>();
+        events.sort_by_key(|&(index, event)| (event.tick, index));
+        events.into_iter()
+            .map(|(index, event)| TimedEvent { track: index, micros: tempo_map.micros(event.tick), event: event })
+            .collect()
+    }
+
+    /// The events of a track, timed with the tempo events of the same track, as for format 2.
+    ///
+    pub fn track_events(&self, index: usize) -> Vec<TimedEvent<'_>> {
+        let track = &self.tracks[index];
+        let tempo_map = TempoMap::new(self.timing, Some(track));
+        track.events.iter()
+            .map(|event| TimedEvent { track: index, micros: tempo_map.micros(event.tick), event: event })
+            .collect()
+    }
+
+    /// A packet buffer with the MIDI events of the file (see [events](#method.events)), timestamped
+    /// for the file to start playing at `start`.
+    ///
+    /// The meta events are left out, and the events larger than a packet are split.
+    ///
+    pub fn to_packet_buffer(&self, start: Timestamp, timebase: &Timebase) -> DynPacketBuffer {
+        let mut packet_buffer = PacketBuffer::dyn();
+        for timed_event in self.events() {
+            if let Some(data) = timed_event.event.midi_data() {
+                let timestamp = timed_event.timestamp(start, timebase);
+                for chunk in data.chunks(MAX_PACKET_DATA_LENGTH) {
+                    packet_buffer.push_packet(timestamp, chunk);
+                }
+            }
+        }
+        packet_buffer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use smf::{SmfTiming, SmfTrack, SmfEvent, SmfEventKind, MetaEvent, TempoMap};
+
+    fn tempo_track(tempos: &[(u64, u32)]) -> SmfTrack {
+        SmfTrack {
+            events: tempos.iter().map(|&(tick, tempo)| SmfEvent {
+                delta: 0,
+                tick: tick,
+                kind: SmfEventKind::Meta(MetaEvent::Tempo(tempo))
+            }).collect()
+        }
+    }
+
+    #[test]
+    fn tempo_map_default_tempo() {
+        let tempo_map = TempoMap::new(SmfTiming::TicksPerQuarterNote(96), &[]);
+        assert_eq!(tempo_map.micros(0), 0);
+        assert_eq!(tempo_map.micros(96), 500_000);
+        assert_eq!(tempo_map.micros(48 * 5), 1_250_000);
+    }
+
+    #[test]
+    fn tempo_map_changes() {
+        let tracks = [tempo_track(&[(0, 1_000_000), (96, 250_000)]), tempo_track(&[(192, 500_000), (192, 2_000_000)])];
+        let tempo_map = TempoMap::new(SmfTiming::TicksPerQuarterNote(96), &tracks);
+        assert_eq!(tempo_map.micros(48), 500_000);
+        assert_eq!(tempo_map.micros(96), 1_000_000);
+        assert_eq!(tempo_map.micros(144), 1_125_000);
+        assert_eq!(tempo_map.micros(192), 1_250_000);
+        assert_eq!(tempo_map.micros(288), 3_250_000);
+    }
+
+    #[test]
+    fn tempo_map_smpte() {
+        let tempo_map = TempoMap::new(SmfTiming::Smpte { frames_per_second: 25, ticks_per_frame: 40 }, &[tempo_track(&[(0, 1)])]);
+        assert_eq!(tempo_map.micros(1), 1_000);
+        assert_eq!(tempo_map.micros(1000), 1_000_000);
+
+        let tempo_map = TempoMap::new(SmfTiming::Smpte { frames_per_second: 29, ticks_per_frame: 100 }, &[]);
+        assert_eq!(tempo_map.micros(3000), 1_001_000);
+    }
+}
@@ -0,0 +1,350 @@
+use std::io::Read;
+
+use smf::{Smf, SmfError, SmfFormat, SmfTiming, SmfTrack, SmfEvent, SmfEventKind, MetaEvent};
+
+impl Smf {
+    /// Parse the bytes of a Standard MIDI File.
+    ///
+    /// Chunks other than the header and the tracks are skipped, as required by the specification.
+    ///
+    pub fn parse(data: &[u8]) -> Result<Smf, SmfError> {
+        let mut cursor = Cursor { data: data, position: 0 };
+
+        let (chunk_type, header) = cursor.chunk()?;
+        if chunk_type != b"MThd" {
+            return Err(SmfError::NotSmf);
+        }
+        if header.len() < 6 {
+            return Err(SmfError::InvalidHeader);
+        }
+        let format = match u16::from_be_bytes([header[0], header[1]]) {
+            0 => SmfFormat::SingleTrack,
+            1 => SmfFormat::MultipleTrack,
+            2 => SmfFormat::MultipleSong,
+            format => return Err(SmfError::UnsupportedFormat(format)),
+        };
+        let track_count = u16::from_be_bytes([header[2], header[3]]) as usize;
+        if format == SmfFormat::SingleTrack && track_count > 1 {
+            return Err(SmfError::TooManyTracks);
+        }
+        let timing = if header[4] & 0x80 == 0 {
+            SmfTiming::TicksPerQuarterNote(u16::from_be_bytes([header[4], header[5]]))
+        }
+        else {
+            // The SMPTE format is stored as a negative number: -24, -25, -29 or -30
+            SmfTiming::Smpte { frames_per_second: (header[4] as i8).wrapping_neg() as u8, ticks_per_frame: header[5] }
+        };
+        match timing {
+            SmfTiming::TicksPerQuarterNote(0) |
+            SmfTiming::Smpte { ticks_per_frame: 0, .. } => return Err(SmfError::InvalidHeader),
+            SmfTiming::Smpte { frames_per_second, .. } if ![24, 25, 29, 30].contains(&frames_per_second) =>
+                return Err(SmfError::InvalidHeader),
+            _ => {}
+        }
+
+        let mut tracks = Vec::with_capacity(track_count);
+        while tracks.len() < track_count {
+            let (chunk_type, data) = cursor.chunk()?;
+            if chunk_type == b"MTrk" {
+                tracks.push(parse_track(data)?);
+            }
+        }
+
+        Ok(Smf { format: format, timing: timing, tracks: tracks })
+    }
+
+    /// Read and parse a Standard MIDI File.
+    ///
+    pub fn read<R: Read>(mut reader: R) -> Result<Smf, SmfError> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        Smf::parse(&data)
+    }
+}
+
+struct Cursor<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn is_empty(&self) -> bool {
+        self.position >= self.data.len()
+    }
+
+    fn byte(&mut self) -> Result<u8, SmfError> {
+        let byte = *self.data.get(self.position).ok_or(SmfError::UnexpectedEnd)?;
+        self.position += 1;
+        Ok(byte)
+    }
+
+    fn peek(&self) -> Result<u8, SmfError> {
+        self.data.get(self.position).cloned().ok_or(SmfError::UnexpectedEnd)
+    }
+
+    fn bytes(&mut self, length: usize) -> Result<&'a [u8], SmfError> {
+        if self.data.len() - self.position < length {
+            return Err(SmfError::UnexpectedEnd);
+        }
+        let bytes = &self.data[self.position..self.position + length];
+        self.position += length;
+        Ok(bytes)
+    }
+
+    // A variable-length quantity: 7 bits per byte, most significant first, with the top bit set in all but the last byte.
+    fn var_len(&mut self) -> Result<u32, SmfError> {
+        let mut value = 0u32;
+        for _ in 0..4 {
+            let byte = self.byte()?;
+            value = (value << 7) | (byte & 0x7f) as u32;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(SmfError::InvalidVarLen)
+    }
+
+    fn chunk(&mut self) -> Result<(&'a [u8], &'a [u8]), SmfError> {
+        let chunk_type = self.bytes(4)?;
+        let length = self.bytes(4)?;
+        let length = u32::from_be_bytes([length[0], length[1], length[2], length[3]]) as usize;
+        Ok((chunk_type, self.bytes(length)?))
+    }
+}
+
+fn parse_track(data: &[u8]) -> Result<SmfTrack, SmfError> {
+    let mut cursor = Cursor { data: data, position: 0 };
+    let mut events = Vec::new();
+    let mut tick = 0u64;
+    let mut running_status = None;
+
+    while !cursor.is_empty() {
+        let delta = cursor.var_len()?;
+        tick += delta as u64;
+
+        let kind = match cursor.peek()? {
+            0xff => {
+                cursor.byte()?;
+                running_status = None;
+                let meta_type = cursor.byte()?;
+                let length = cursor.var_len()? as usize;
+                SmfEventKind::Meta(parse_meta_event(meta_type, cursor.bytes(length)?)?)
+            },
+            status @ 0xf0 | status @ 0xf7 => {
+                cursor.byte()?;
+                running_status = None;
+                let length = cursor.var_len()? as usize;
+                let data = cursor.bytes(length)?;
+                if status == 0xf0 {
+                    let mut sysex = Vec::with_capacity(length + 1);
+                    sysex.push(0xf0);
+                    sysex.extend_from_slice(data);
+                    SmfEventKind::SysEx(sysex)
+                }
+                else {
+                    SmfEventKind::Escape(data.to_vec())
+                }
+            },
+            status @ 0xf1 ..= 0xfe => return Err(SmfError::InvalidStatus(status)),
+            byte => {
+                let status = if byte & 0x80 != 0 {
+                    cursor.byte()?;
+                    running_status = Some(byte);
+                    byte
+                }
+                else {
+                    running_status.ok_or(SmfError::MissingRunningStatus)?
+                };
+                let length = match status & 0xf0 {
+                    0xc0 | 0xd0 => 1,
+                    _ => 2,
+                };
+                let mut message = Vec::with_capacity(length + 1);
+                message.push(status);
+                for _ in 0..length {
+                    let data_byte = cursor.byte()?;
+                    if data_byte & 0x80 != 0 {
+                        return Err(SmfError::InvalidStatus(data_byte));
+                    }
+                    message.push(data_byte);
+                }
+                SmfEventKind::Midi(message)
+            }
+        };
+
+        let end_of_track = kind == SmfEventKind::Meta(MetaEvent::EndOfTrack);
+        events.push(SmfEvent { delta: delta, tick: tick, kind: kind });
+        if end_of_track {
+            break;
+        }
+    }
+
+    Ok(SmfTrack { events: events })
+}
+
+fn parse_meta_event(meta_type: u8, data: &[u8]) -> Result<MetaEvent, SmfError> {
+    let expect_length = |length: usize| if data.len() == length { Ok(()) } else { Err(SmfError::InvalidMetaEvent(meta_type)) };
+    let meta_event = match meta_type {
+        0x00 => {
+            expect_length(2)?;
+            MetaEvent::SequenceNumber(u16::from_be_bytes([data[0], data[1]]))
+        },
+        0x01 ..= 0x0f => MetaEvent::Text(meta_type, data.to_vec()),
+        0x20 => {
+            expect_length(1)?;
+            MetaEvent::ChannelPrefix(data[0])
+        },
+        0x21 => {
+            expect_length(1)?;
+            MetaEvent::Port(data[0])
+        },
+        0x2f => {
+            expect_length(0)?;
+            MetaEvent::EndOfTrack
+        },
+        0x51 => {
+            expect_length(3)?;
+            MetaEvent::Tempo(u32::from_be_bytes([0, data[0], data[1], data[2]]))
+        },
+        0x54 => {
+            expect_length(5)?;
+            MetaEvent::SmpteOffset { hours: data[0], minutes: data[1], seconds: data[2], frames: data[3], fractional_frames: data[4] }
+        },
+        0x58 => {
+            expect_length(4)?;
+            MetaEvent::TimeSignature { numerator: data[0], denominator: data[1], clocks_per_click: data[2], thirty_seconds_per_quarter: data[3] }
+        },
+        0x59 => {
+            expect_length(2)?;
+            MetaEvent::KeySignature { sharps: data[0] as i8, minor: data[1] != 0 }
+        },
+        0x7f => MetaEvent::SequencerSpecific(data.to_vec()),
+        _ => MetaEvent::Unknown(meta_type, data.to_vec()),
+    };
+    Ok(meta_event)
+}
+
+#[cfg(test)]
+mod tests {
+    use smf::{Smf, SmfError, SmfFormat, SmfTiming, SmfEventKind, MetaEvent};
+    use time::Timebase;
+
+    const FORMAT_0: &[u8] = include_bytes!("../../tests/fixtures/format0.mid");
+    const FORMAT_1: &[u8] = include_bytes!("../../tests/fixtures/format1.mid");
+    const FORMAT_2: &[u8] = include_bytes!("../../tests/fixtures/format2.mid");
+    const SMPTE: &[u8] = include_bytes!("../../tests/fixtures/smpte.mid");
+
+    fn kinds(smf: &Smf, track: usize) -> Vec<(u32, SmfEventKind)> {
+        smf.tracks[track].events.iter().map(|event| (event.delta, event.kind.clone())).collect()
+    }
+
+    #[test]
+    fn format_0() {
+        let smf = Smf::parse(FORMAT_0).unwrap();
+        assert_eq!(smf.format, SmfFormat::SingleTrack);
+        assert_eq!(smf.timing, SmfTiming::TicksPerQuarterNote(96));
+        assert_eq!(smf.tracks.len(), 1);
+        assert_eq!(kinds(&smf, 0), vec![
+            (0, SmfEventKind::Meta(MetaEvent::Text(0x03, b"Piano".to_vec()))),
+            (0, SmfEventKind::Meta(MetaEvent::TimeSignature { numerator: 4, denominator: 2, clocks_per_click: 24, thirty_seconds_per_quarter: 8 })),
+            (0, SmfEventKind::Meta(MetaEvent::KeySignature { sharps: -3, minor: true })),
+            (0, SmfEventKind::Midi(vec![0x90, 0x3c, 0x64])),
+            (96, SmfEventKind::Midi(vec![0x80, 0x3c, 0x40])),
+            (0, SmfEventKind::Meta(MetaEvent::EndOfTrack))]);
+        assert_eq!(smf.tracks[0].events[4].tick, 96);
+    }
+
+    #[test]
+    fn format_1_with_running_status_and_sysex() {
+        let smf = Smf::parse(FORMAT_1).unwrap();
+        assert_eq!(smf.format, SmfFormat::MultipleTrack);
+        assert_eq!(smf.tracks.len(), 2);
+        assert_eq!(kinds(&smf, 0), vec![
+            (0, SmfEventKind::Meta(MetaEvent::Tempo(1_000_000))),
+            (192, SmfEventKind::Meta(MetaEvent::Tempo(250_000))),
+            (0, SmfEventKind::Meta(MetaEvent::EndOfTrack))]);
+        assert_eq!(kinds(&smf, 1), vec![
+            (0, SmfEventKind::SysEx(vec![0xf0, 0x7e, 0x7f, 0x09, 0x01, 0xf7])),
+            (0, SmfEventKind::Midi(vec![0xc1, 0x05])),
+            (96, SmfEventKind::Midi(vec![0x91, 0x3c, 0x64])),
+            (0, SmfEventKind::Midi(vec![0x91, 0x40, 0x64])),
+            (192, SmfEventKind::Midi(vec![0x91, 0x3c, 0x00])),
+            (0, SmfEventKind::Midi(vec![0x91, 0x40, 0x00])),
+            (0, SmfEventKind::Midi(vec![0xb1, 0x40, 0x7f])),
+            (0, SmfEventKind::Meta(MetaEvent::EndOfTrack))]);
+    }
+
+    #[test]
+    fn format_1_timed_events() {
+        let smf = Smf::parse(FORMAT_1).unwrap();
+        let events = smf.events().into_iter()
+            .filter(|event| event.event.midi_data().is_some())
+            .map(|event| (event.track, event.micros))
+            .collect::<Vec<_>>();
+        assert_eq!(events, vec![(1, 0), (1, 0), (1, 1_000_000), (1, 1_000_000), (1, 2_250_000), (1, 2_250_000), (1, 2_250_000)]);
+
+        let packet_buffer = smf.to_packet_buffer(1000, &Timebase::new(1000, 1));
+        let packets = packet_buffer.as_ref().iter()
+            .map(|packet| (packet.timestamp(), packet.data().to_vec()))
+            .collect::<Vec<_>>();
+        assert_eq!(packets, vec![
+            (1000, vec![0xf0, 0x7e, 0x7f, 0x09, 0x01, 0xf7]),
+            (1000, vec![0xc1, 0x05]),
+            (1_001_000, vec![0x91, 0x3c, 0x64]),
+            (1_001_000, vec![0x91, 0x40, 0x64]),
+            (2_251_000, vec![0x91, 0x3c, 0x00]),
+            (2_251_000, vec![0x91, 0x40, 0x00]),
+            (2_251_000, vec![0xb1, 0x40, 0x7f])]);
+    }
+
+    #[test]
+    fn format_2_tracks_are_independent() {
+        let smf = Smf::parse(FORMAT_2).unwrap();
+        assert_eq!(smf.format, SmfFormat::MultipleSong);
+        assert_eq!(smf.tracks.len(), 2);
+        let micros = |track| smf.track_events(track).iter().map(|event| event.micros).collect::<Vec<_>>();
+        // The first pattern is at 60 bpm, the second one at the default 120 bpm
+        assert_eq!(micros(0), vec![0, 0, 1_000_000, 1_000_000]);
+        assert_eq!(micros(1), vec![0, 500_000, 500_000]);
+    }
+
+    #[test]
+    fn smpte_timing_and_unknown_chunks() {
+        let smf = Smf::parse(SMPTE).unwrap();
+        assert_eq!(smf.timing, SmfTiming::Smpte { frames_per_second: 25, ticks_per_frame: 40 });
+        assert_eq!(smf.tracks.len(), 1);
+        assert_eq!(kinds(&smf, 0)[0], (0, SmfEventKind::Meta(MetaEvent::SmpteOffset { hours: 1, minutes: 0, seconds: 0, frames: 0, fractional_frames: 0 })));
+        let micros = smf.events().iter().map(|event| event.micros).collect::<Vec<_>>();
+        assert_eq!(micros, vec![0, 0, 250_000, 250_000]);
+    }
+
+    #[test]
+    fn read_from_reader() {
+        assert_eq!(Smf::read(FORMAT_0).unwrap(), Smf::parse(FORMAT_0).unwrap());
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(Smf::parse(b"RIFF\0\0\0\x06\0\0\0\x01\0\x60"), Err(SmfError::NotSmf));
+        assert_eq!(Smf::parse(b"MThd\0\0\0\x04\0\0\0\x01"), Err(SmfError::InvalidHeader));
+        assert_eq!(Smf::parse(b"MThd\0\0\0\x06\0\x03\0\x01\0\x60"), Err(SmfError::UnsupportedFormat(3)));
+        assert_eq!(Smf::parse(b"MThd\0\0\0\x06\0\0\0\x02\0\x60"), Err(SmfError::TooManyTracks));
+        assert_eq!(Smf::parse(b"MThd\0\0\0\x06\0\0\0\x01\0\0"), Err(SmfError::InvalidHeader));
+        assert_eq!(Smf::parse(b"MThd\0\0\0\x06\0\0\0\x01\0\x60"), Err(SmfError::UnexpectedEnd));
+        assert_eq!(Smf::parse(&FORMAT_0[..FORMAT_0.len() - 1]), Err(SmfError::UnexpectedEnd));
+
+        let track = |events: &[u8]| {
+            let mut data = b"MThd\0\0\0\x06\0\0\0\x01\0\x60MTrk".to_vec();
+            data.extend_from_slice(&(events.len() as u32).to_be_bytes());
+            data.extend_from_slice(events);
+            Smf::parse(&data)
+        };
+        assert_eq!(track(&[0x00, 0x40, 0x7f]), Err(SmfError::MissingRunningStatus));
+        assert_eq!(track(&[0x00, 0x90, 0x40, 0x7f, 0x00, 0xff, 0x01, 0x00, 0x00, 0x40, 0x00]), Err(SmfError::MissingRunningStatus));
+        assert_eq!(track(&[0x00, 0xf8]), Err(SmfError::InvalidStatus(0xf8)));
+        assert_eq!(track(&[0x00, 0x90, 0x40, 0x90]), Err(SmfError::InvalidStatus(0x90)));
+        assert_eq!(track(&[0x80, 0x80, 0x80, 0x80, 0x00]), Err(SmfError::InvalidVarLen));
+        assert_eq!(track(&[0x00, 0xff, 0x51, 0x02, 0x07, 0xa1]), Err(SmfError::InvalidMetaEvent(0x51)));
+        assert_eq!(track(&[0x00, 0xff, 0x01, 0x05, 0x41]), Err(SmfError::UnexpectedEnd));
+    }
+}
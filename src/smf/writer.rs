@@ -0,0 +1,503 @@
+use std::fmt;
+use std::io::{self, Write};
+use std::sync::Arc;
+
+use clock::{Clock, HostClock};
+use messages::MidiMessage;
+use packets::{PacketListRef, PacketRef, Timestamp};
+use smf::{Smf, SmfError, SmfFormat, SmfTiming, SmfTrack, SmfEvent, SmfEventKind, MetaEvent, DEFAULT_TEMPO};
+use time::Timebase;
+
+// The largest value of a variable-length quantity of 4 bytes.
+const MAX_VAR_LEN: u64 = 0x0fff_ffff;
+
+impl Smf {
+    /// Write the file.
+    ///
+    /// The delta times are written from the ticks of the events, channel messages use running status
+    /// whenever possible, and an End of Track meta event is added to the tracks that lack one.
+    ///
+    pub fn write<W: Write>(&self, mut writer: W) -> Result<(), SmfError> {
+        if self.format == SmfFormat::SingleTrack && self.tracks.len() > 1 {
+            return Err(SmfError::TooManyTracks);
+        }
+        if self.tracks.len() > u16::MAX as usize {
+            return Err(SmfError::InvalidHeader);
+        }
+
+        let format: u16 = match self.format {
+            SmfFormat::SingleTrack => 0,
+            SmfFormat::MultipleTrack => 1,
+            SmfFormat::MultipleSong => 2,
+        };
+        let division = match self.timing {
+            SmfTiming::TicksPerQuarterNote(ticks_per_quarter) if ticks_per_quarter != 0 && ticks_per_quarter < 0x8000 =>
+                [(ticks_per_quarter >> 8) as u8, ticks_per_quarter as u8],
+            SmfTiming::Smpte { frames_per_second, ticks_per_frame } if [24, 25, 29, 30].contains(&frames_per_second) && ticks_per_frame != 0 =>
+                [(frames_per_second as i8).wrapping_neg() as u8, ticks_per_frame],
+            _ => return Err(SmfError::InvalidHeader),
+        };
+
+        let mut header = Vec::with_capacity(6);
+        header.extend_from_slice(&format.to_be_bytes());
+        header.extend_from_slice(&(self.tracks.len() as u16).to_be_bytes());
+        header.extend_from_slice(&division);
+        write_chunk(&mut writer, b"MThd", &header)?;
+
+        for track in &self.tracks {
+            write_chunk(&mut writer, b"MTrk", &encode_track(track)?)?;
+        }
+        Ok(())
+    }
+
+    /// The bytes of the file. See [write](#method.write).
+    ///
+    pub fn to_bytes(&self) -> Result<Vec<u8>, SmfError> {
+        let mut data = Vec::new();
+        self.write(&mut data)?;
+        Ok(data)
+    }
+}
+
+fn write_chunk<W: Write>(writer: &mut W, chunk_type: &[u8], data: &[u8]) -> Result<(), SmfError> {
+    if data.len() > u32::MAX as usize {
+        return Err(SmfError::Io(io::ErrorKind::InvalidInput));
+    }
+    writer.write_all(chunk_type)?;
+    writer.write_all(&(data.len() as u32).to_be_bytes())?;
+    writer.write_all(data)?;
+    Ok(())
+}
+
+fn encode_track(track: &SmfTrack) -> Result<Vec<u8>, SmfError> {
+    let mut data = Vec::new();
+    let mut tick = 0u64;
+    let mut running_status = None;
+
+    for event in &track.events {
+        if event.kind == SmfEventKind::Meta(MetaEvent::EndOfTrack) {
+            // Written below, after all the other events
+            tick = tick.max(event.tick);
+            continue;
+        }
+
+        write_var_len(&mut data, event.tick.saturating_sub(tick))?;
+        tick = tick.max(event.tick);
+
+        match event.kind {
+            SmfEventKind::Midi(ref message) => {
+                let status = message.first().cloned();
+                match status {
+                    Some(0x80 ..= 0xef) if status == running_status => data.extend_from_slice(&message[1..]),
+                    Some(0x80 ..= 0xef) => {
+                        running_status = status;
+                        data.extend_from_slice(message);
+                    },
+                    _ => {
+                        running_status = None;
+                        data.extend_from_slice(message);
+                    }
+                }
+            },
+            SmfEventKind::SysEx(ref sysex) => {
+                running_status = None;
+                let body = if sysex.first() == Some(&0xf0) { &sysex[1..] } else { &sysex[..] };
+                data.push(0xf0);
+                write_var_len(&mut data, body.len() as u64)?;
+                data.extend_from_slice(body);
+            },
+            SmfEventKind::Escape(ref bytes) => {
+                running_status = None;
+                data.push(0xf7);
+                write_var_len(&mut data, bytes.len() as u64)?;
+                data.extend_from_slice(bytes);
+            },
+            SmfEventKind::Meta(ref meta_event) => {
+                running_status = None;
+                let (meta_type, bytes) = encode_meta_event(meta_event);
+                data.push(0xff);
+                data.push(meta_type);
+                write_var_len(&mut data, bytes.len() as u64)?;
+                data.extend_from_slice(&bytes);
+            }
+        }
+    }
+
+    let last_tick = track.events.iter().map(|event| event.tick).max().unwrap_or(0);
+    write_var_len(&mut data, last_tick.saturating_sub(tick))?;
+    data.extend_from_slice(&[0xff, 0x2f, 0x00]);
+    Ok(data)
+}
+
+fn write_var_len(data: &mut Vec<u8>, value: u64) -> Result<(), SmfError> {
+    if value > MAX_VAR_LEN {
+        return Err(SmfError::InvalidVarLen);
+    }
+    let mut shift = 21;
+    while shift > 0 && value >> shift == 0 {
+        shift -= 7;
+    }
+    while shift > 0 {
+        data.push(0x80 | ((value >> shift) as u8 & 0x7f));
+        shift -= 7;
+    }
+    data.push(value as u8 & 0x7f);
+    Ok(())
+}
+
+fn encode_meta_event(meta_event: &MetaEvent) -> (u8, Vec<u8>) {
+    match *meta_event {
+        MetaEvent::SequenceNumber(number) => (0x00, number.to_be_bytes().to_vec()),
+        MetaEvent::Text(text_type, ref text) => (text_type, text.clone()),
+        MetaEvent::ChannelPrefix(channel) => (0x20, vec![channel]),
+        MetaEvent::Port(port) => (0x21, vec![port]),
+        MetaEvent::EndOfTrack => (0x2f, vec![]),
+        MetaEvent::Tempo(tempo) => (0x51, tempo.to_be_bytes()[1..].to_vec()),
+        MetaEvent::SmpteOffset { hours, minutes, seconds, frames, fractional_frames } =>
+            (0x54, vec![hours, minutes, seconds, frames, fractional_frames]),
+        MetaEvent::TimeSignature { numerator, denominator, clocks_per_click, thirty_seconds_per_quarter } =>
+            (0x58, vec![numerator, denominator, clocks_per_click, thirty_seconds_per_quarter]),
+        MetaEvent::KeySignature { sharps, minor } => (0x59, vec![sharps as u8, minor as u8]),
+        MetaEvent::SequencerSpecific(ref data) => (0x7f, data.clone()),
+        MetaEvent::Unknown(meta_type, ref data) => (meta_type, data.clone()),
+    }
+}
+
+/// Records the packets received from CoreMIDI into a Standard MIDI File.
+///
+/// The host timestamps of the packets are converted into ticks at a fixed tempo, counting from the
+/// timestamp of the first recorded packet, or from the one given with [with_start](#method.with_start).
+/// Packets with a timestamp of 0 are recorded at the time they are recorded, as read from the
+/// [host clock](struct.HostClock.html), or the one given with [with_clock](#method.with_clock).
+///
+/// Every recorded track becomes a track of a format 1 file, after a first track with the tempo,
+/// while all of them are merged into a single track for a format 0 file.
+///
+/// System common and real-time messages are written as escape events, and the parts of a SysEx message
+/// split across packets as a SysEx event followed by escape events. Invalid MIDI data is left out.
+///
+/// ```rust,no_run
+/// let mut writer = coremidi::SmfWriter::new(coremidi::SmfFormat::SingleTrack, 480).unwrap();
+/// # let packet_list = coremidi::PacketBuffer::dyn();
+/// // For every packet list received by an input port callback
+/// writer.record(0, packet_list.as_ref());
+///
+/// writer.write(std::fs::File::create("recording.mid").unwrap()).unwrap();
+/// ```
+#[derive(Clone)]
+pub struct SmfWriter {
+    format: SmfFormat,
+    ticks_per_quarter: u16,
+    tempo: u32,
+    timebase: Timebase,
+    clock: Arc<dyn Clock>,
+    start: Option<Timestamp>,
+    tracks: Vec<Vec<(u64, SmfEventKind)>>,
+}
+
+impl SmfWriter {
+    /// Create a writer for a format 0 or 1 file with some ticks per quarter note, at 120 bpm.
+    ///
+    /// It fails for format 2, and for 0 or more than `0x7fff` ticks per quarter note.
+    ///
+    pub fn new(format: SmfFormat, ticks_per_quarter: u16) -> Result<SmfWriter, SmfError> {
+        if format == SmfFormat::MultipleSong {
+            return Err(SmfError::UnsupportedFormat(2));
+        }
+        if ticks_per_quarter == 0 || ticks_per_quarter >= 0x8000 {
+            return Err(SmfError::InvalidHeader);
+        }
+        Ok(SmfWriter {
+            format: format,
+            ticks_per_quarter: ticks_per_quarter,
+            tempo: DEFAULT_TEMPO,
+            timebase: Timebase::host(),
+            clock: Arc::new(HostClock),
+            start: None,
+            tracks: Vec::new(),
+        })
+    }
+
+    /// Set the tempo in microseconds per quarter note, before recording any packet.
+    ///
+    /// It panics if the tempo is 0 or doesn't fit in 24 bits.
+    ///
+    pub fn with_tempo(mut self, tempo: u32) -> SmfWriter {
+        assert!(tempo != 0 && tempo <= 0xff_ffff, "invalid tempo {}", tempo);
+        self.tempo = tempo;
+        self
+    }
+
+    /// Set the timebase of the timestamps, [the host one](struct.Timebase.html#method.host) by default.
+    ///
+    pub fn with_timebase(mut self, timebase: Timebase) -> SmfWriter {
+        self.timebase = timebase;
+        self
+    }
+
+    /// Set the clock giving the time of the packets with a timestamp of 0, in the ticks of the timebase.
+    ///
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> SmfWriter {
+        self.clock = clock;
+        self
+    }
+
+    /// Set the host time for the beginning of the file.
+    ///
+    pub fn with_start(mut self, start: Timestamp) -> SmfWriter {
+        self.start = Some(start);
+        self
+    }
+
+    /// The number of recorded tracks.
+    ///
+    pub fn track_count(&self) -> usize {
+        self.tracks.len()
+    }
+
+    /// Record the packets of a packet list into a track, which is created if needed.
+    ///
+    pub fn record(&mut self, track: usize, packet_list: PacketListRef) {
+        for packet in packet_list.iter() {
+            self.record_packet(track, packet);
+        }
+    }
+
+    /// Record a packet into a track, which is created if needed.
+    ///
+    pub fn record_packet(&mut self, track: usize, packet: PacketRef) {
+        let timestamp = match packet.timestamp() {
+            0 => self.clock.now(),
+            timestamp => timestamp
+        };
+        let start = *self.start.get_or_insert(timestamp);
+        let tick = self.tick(timestamp.saturating_sub(start));
+
+        if self.tracks.len() <= track {
+            self.tracks.resize(track + 1, Vec::new());
+        }
+        let events = &mut self.tracks[track];
+        for message in packet.messages().filter_map(Result::ok) {
+            let mut bytes = vec![0; message.encoded_len()];
+            message.encode(&mut bytes);
+            let kind = match message {
+                MidiMessage::SysEx(_) if bytes[0] == 0xf0 => SmfEventKind::SysEx(bytes),
+                _ if message.channel().is_some() => SmfEventKind::Midi(bytes),
+                _ => SmfEventKind::Escape(bytes),
+            };
+            events.push((tick, kind));
+        }
+    }
+
+    // The ticks for some host ticks from the start, rounded to the nearest one.
+    fn tick(&self, host_ticks: u64) -> u64 {
+        let nanos = self.timebase.ticks_to_nanos(host_ticks) as u128;
+        let nanos_per_quarter = self.tempo as u128 * 1000;
+        let scaled = nanos * self.ticks_per_quarter as u128;
+        let tick = scaled / nanos_per_quarter + if (scaled % nanos_per_quarter) * 2 >= nanos_per_quarter { 1 } else { 0 };
+        if tick > u64::MAX as u128 { u64::MAX } else { tick as u64 }
+    }
+
+    /// The file with the packets recorded so far.
+    ///
+    pub fn to_smf(&self) -> Smf {
+        let tempo = (0, SmfEventKind::Meta(MetaEvent::Tempo(self.tempo)));
+        let tracks = match self.format {
+            SmfFormat::SingleTrack => {
+                let mut events = self.tracks.iter().flat_map(|events| events.iter().cloned()).collect::<Vec<_>>();
+                events.sort_by_key(|&(tick, _)| tick);
+                events.insert(0, tempo);
+                vec![track(events)]
+            },
+            _ => {
+                let mut tracks = vec![track(vec![tempo])];
+                for events in &self.tracks {
+                    let mut events = events.clone();
+                    events.sort_by_key(|&(tick, _)| tick);
+                    tracks.push(track(events));
+                }
+                tracks
+            }
+        };
+        Smf { format: self.format, timing: SmfTiming::TicksPerQuarterNote(self.ticks_per_quarter), tracks: tracks }
+    }
+
+    /// Write the file with the packets recorded so far.
+    ///
+    pub fn write<W: Write>(&self, writer: W) -> Result<(), SmfError> {
+        self.to_smf().write(writer)
+    }
+}
+
+impl fmt::Debug for SmfWriter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SmfWriter")
+            .field("format", &self.format)
+            .field("ticks_per_quarter", &self.ticks_per_quarter)
+            .field("tempo", &self.tempo)
+            .field("timebase", &self.timebase)
+            .field("start", &self.start)
+            .field("tracks", &self.tracks)
+            .finish()
+    }
+}
+
+// A track with some events and their ticks, ending with an End of Track.
+fn track(mut events: Vec<(u64, SmfEventKind)>) -> SmfTrack {
+    let last_tick = events.last().map_or(0, |&(tick, _)| tick);
+    events.push((last_tick, SmfEventKind::Meta(MetaEvent::EndOfTrack)));
+    let mut previous_tick = 0;
+    SmfTrack {
+        events: events.into_iter().map(|(tick, kind)| {
+            let delta = (tick - previous_tick).min(u32::MAX as u64) as u32;
+            previous_tick = tick;
+            SmfEvent { delta: delta, tick: tick, kind: kind }
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use clock::ManualClock;
+    use packets::PacketBuffer;
+    use smf::{Smf, SmfError, SmfFormat, SmfTiming, SmfTrack, SmfEvent, SmfEventKind, MetaEvent, SmfWriter};
+    use time::Timebase;
+
+    const FORMAT_0: &[u8] = include_bytes!("../../tests/fixtures/format0.mid");
+    const FORMAT_1: &[u8] = include_bytes!("../../tests/fixtures/format1.mid");
+    const FORMAT_2: &[u8] = include_bytes!("../../tests/fixtures/format2.mid");
+
+    // A quarter note lasts 500 ms at 120 bpm, so 96 ticks per quarter note are 500_000_000 / 96 ns each.
+    const QUARTER: u64 = 500_000_000;
+
+    fn record(writer: &mut SmfWriter, track: usize, packets: &[(u64, &[u8])]) {
+        let mut packet_buffer = PacketBuffer::dyn();
+        for &(timestamp, data) in packets {
+            packet_buffer.push_packet(timestamp, data);
+        }
+        writer.record(track, packet_buffer.as_ref());
+    }
+
+    #[test]
+    fn fixtures_round_trip() {
+        for &fixture in &[FORMAT_0, FORMAT_1, FORMAT_2] {
+            let smf = Smf::parse(fixture).unwrap();
+            assert_eq!(smf.to_bytes().unwrap(), fixture);
+        }
+    }
+
+    #[test]
+    fn format_0_with_running_status() {
+        let mut writer = SmfWriter::new(SmfFormat::SingleTrack, 96).unwrap()
+            .with_timebase(Timebase::new(1, 1))
+            .with_clock(Arc::new(ManualClock::new(1000 + QUARTER)));
+        record(&mut writer, 0, &[
+            (1000, &[0x90, 0x3c, 0x64, 0x90, 0x40, 0x64]),
+            (1000 + QUARTER, &[0x80, 0x3c, 0x40, 0x80, 0x40, 0x40]),
+            (0, &[0xb0, 0x40, 0x00])]);
+        record(&mut writer, 0, &[
+            (1000 + QUARTER * 3 / 2, &[0xf8]),
+            (1000 + QUARTER * 2, &[0x90, 0x3c, 0x00])]);
+
+        let data = writer.to_smf().to_bytes().unwrap();
+        assert_eq!(&data[..14], b"MThd\0\0\0\x06\0\0\0\x01\0\x60");
+        assert_eq!(&data[14..22], b"MTrk\0\0\0\x25");
+        assert_eq!(&data[22..], &[
+            0x00, 0xff, 0x51, 0x03, 0x07, 0xa1, 0x20,
+            0x00, 0x90, 0x3c, 0x64,
+            0x00, 0x40, 0x64,
+            0x60, 0x80, 0x3c, 0x40,
+            0x00, 0x40, 0x40,
+            0x00, 0xb0, 0x40, 0x00,
+            0x30, 0xf7, 0x01, 0xf8,
+            0x30, 0x90, 0x3c, 0x00,
+            0x00, 0xff, 0x2f, 0x00][..]);
+
+        let mut written = Vec::new();
+        writer.write(&mut written).unwrap();
+        assert_eq!(written, data);
+        assert_eq!(Smf::parse(&data).unwrap(), writer.to_smf());
+    }
+
+    #[test]
+    fn format_1_with_split_sysex() {
+        let mut writer = SmfWriter::new(SmfFormat::MultipleTrack, 480).unwrap()
+            .with_tempo(1_000_000)
+            .with_timebase(Timebase::new(125, 3))
+            .with_start(24_000_000);
+        // 24 MHz host ticks: 1 s is a quarter note, so 480 ticks
+        record(&mut writer, 1, &[(24_000_000, &[0xc2, 0x10]), (48_000_000, &[0x92, 0x3c, 0x64]), (72_000_000, &[0x82, 0x3c, 0x40])]);
+        record(&mut writer, 0, &[(36_000_000, &[0xf0, 0x7e, 0x01]), (36_050_000, &[0x02, 0xf7, 0xf0, 0x03, 0xf7])]);
+        record(&mut writer, 0, &[(12_000_000, &[0xfa])]);
+        assert_eq!(writer.track_count(), 2);
+
+        let smf = Smf::parse(&writer.to_smf().to_bytes().unwrap()).unwrap();
+        assert_eq!(smf, writer.to_smf());
+        assert_eq!(smf.format, SmfFormat::MultipleTrack);
+        assert_eq!(smf.timing, SmfTiming::TicksPerQuarterNote(480));
+        let events = |track: usize| smf.tracks[track].events.iter().map(|event| (event.tick, event.kind.clone())).collect::<Vec<_>>();
+        assert_eq!(events(0), vec![
+            (0, SmfEventKind::Meta(MetaEvent::Tempo(1_000_000))),
+            (0, SmfEventKind::Meta(MetaEvent::EndOfTrack))]);
+        assert_eq!(events(1), vec![
+            (0, SmfEventKind::Escape(vec![0xfa])),
+            (240, SmfEventKind::SysEx(vec![0xf0, 0x7e, 0x01])),
+            (241, SmfEventKind::Escape(vec![0x02, 0xf7])),
+            (241, SmfEventKind::SysEx(vec![0xf0, 0x03, 0xf7])),
+            (241, SmfEventKind::Meta(MetaEvent::EndOfTrack))]);
+        assert_eq!(events(2), vec![
+            (0, SmfEventKind::Midi(vec![0xc2, 0x10])),
+            (480, SmfEventKind::Midi(vec![0x92, 0x3c, 0x64])),
+            (960, SmfEventKind::Midi(vec![0x82, 0x3c, 0x40])),
+            (960, SmfEventKind::Meta(MetaEvent::EndOfTrack))]);
+    }
+
+    #[test]
+    fn first_packet_now() {
+        let clock = Arc::new(ManualClock::new(5_000_000_000));
+        let mut writer = SmfWriter::new(SmfFormat::SingleTrack, 96).unwrap()
+            .with_timebase(Timebase::new(1, 1))
+            .with_clock(clock.clone());
+        record(&mut writer, 0, &[(0, &[0x90, 0x3c, 0x64])]);
+        clock.advance(QUARTER);
+        record(&mut writer, 0, &[(0, &[0x80, 0x3c, 0x40]), (5_000_000_000 + QUARTER * 2, &[0xf8])]);
+
+        let smf = Smf::parse(&writer.to_smf().to_bytes().unwrap()).unwrap();
+        let ticks = smf.tracks[0].events.iter().map(|event| event.tick).collect::<Vec<_>>();
+        assert_eq!(ticks, vec![0, 0, 96, 192, 192]);
+    }
+
+    #[test]
+    fn end_of_track_is_added() {
+        let smf = Smf {
+            format: SmfFormat::SingleTrack,
+            timing: SmfTiming::Smpte { frames_per_second: 30, ticks_per_frame: 80 },
+            tracks: vec![SmfTrack { events: vec![
+                SmfEvent { delta: 0, tick: 200, kind: SmfEventKind::Midi(vec![0x90, 0x3c, 0x64]) },
+                SmfEvent { delta: 0, tick: 0x0100_0000, kind: SmfEventKind::Midi(vec![0x90, 0x3c, 0x00]) }]}]
+        };
+        let data = smf.to_bytes().unwrap();
+        assert_eq!(&data[8..14], &[0x00, 0x00, 0x00, 0x01, 0xe2, 0x50][..]);
+        assert_eq!(&data[22..], &[0x81, 0x48, 0x90, 0x3c, 0x64, 0x87, 0xff, 0xfe, 0x38, 0x3c, 0x00, 0x00, 0xff, 0x2f, 0x00][..]);
+
+        let parsed = Smf::parse(&data).unwrap();
+        assert_eq!(parsed.tracks[0].events.len(), 3);
+        assert_eq!(parsed.tracks[0].events[2].tick, 0x0100_0000);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(SmfWriter::new(SmfFormat::MultipleSong, 96).err(), Some(SmfError::UnsupportedFormat(2)));
+        assert_eq!(SmfWriter::new(SmfFormat::SingleTrack, 0).err(), Some(SmfError::InvalidHeader));
+
+        let mut smf = Smf::parse(FORMAT_1).unwrap();
+        smf.format = SmfFormat::SingleTrack;
+        assert_eq!(smf.to_bytes(), Err(SmfError::TooManyTracks));
+
+        let mut smf = Smf::parse(FORMAT_0).unwrap();
+        smf.tracks[0].events[4].tick = 0x1000_0000;
+        assert_eq!(smf.to_bytes(), Err(SmfError::InvalidVarLen));
+    }
+}
@@ -0,0 +1,221 @@
+use futures::Stream;
+
+use std::cmp;
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::task::{Context, Poll, Waker};
+
+use packets::PacketRef;
+use receiver::TimestampedMessage;
+
+/// A copy of a packet, as produced by a [PacketStream](struct.PacketStream.html).
+///
+pub type OwnedPacket = TimestampedMessage;
+
+/// The default number of packets that a stream can hold before applying its overflow policy.
+///
+pub const DEFAULT_STREAM_CAPACITY: usize = 1024;
+
+/// What to do with incoming packets when the stream is full.
+///
+/// The CoreMIDI thread is never blocked, so packets need to be dropped when the consumer is too slow.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Drop the incoming packet, keeping the ones already in the stream.
+    DropNewest,
+    /// Drop the oldest packet in the stream to make room for the incoming one.
+    DropOldest,
+}
+
+struct State {
+    packets: VecDeque<OwnedPacket>,
+    capacity: usize,
+    policy: OverflowPolicy,
+    overflows: usize,
+    closed: bool,
+    waker: Option<Waker>,
+}
+
+/// A stream of the packets received by an input port.
+/// See [Client::input_stream](struct.Client.html#method.input_stream).
+///
+/// The stream ends once the input port is dropped and all the pending packets have been consumed.
+///
+pub struct PacketStream {
+    state: Arc<Mutex<State>>,
+}
+
+impl PacketStream {
+    /// The number of packets that were dropped because the stream was full.
+    ///
+    pub fn overflow_count(&self) -> usize {
+        lock(&self.state).overflows
+    }
+}
+
+impl Stream for PacketStream {
+    type Item = OwnedPacket;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<OwnedPacket>> {
+        let mut state = lock(&self.state);
+        match state.packets.pop_front() {
+            Some(packet) => Poll::Ready(Some(packet)),
+            None if state.closed => Poll::Ready(None),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+pub struct PacketStreamSender {
+    state: Arc<Mutex<State>>,
+}
+
+impl PacketStreamSender {
+    pub fn send(&self, packet: PacketRef) {
+        let waker = {
+            let mut state = lock(&self.state);
+            if state.packets.len() >= state.capacity {
+                state.overflows += 1;
+                match state.policy {
+                    OverflowPolicy::DropNewest => return,
+                    OverflowPolicy::DropOldest => { state.packets.pop_front(); }
+                }
+            }
+            state.packets.push_back(OwnedPacket::from(packet));
+            state.waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+impl Drop for PacketStreamSender {
+    fn drop(&mut self) {
+        let waker = {
+            let mut state = lock(&self.state);
+            state.closed = true;
+            state.waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+// The state is consistent between any two operations, so a panic while holding the lock doesn't
+// prevent using it, and the sender doesn't panic within the callback of an input port.
+fn lock(state: &Mutex<State>) -> MutexGuard<'_, State> {
+    state.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+// A capacity of 0 is taken as 1, which is what DropOldest needs to keep the incoming packet.
+pub fn channel(capacity: usize, policy: OverflowPolicy) -> (PacketStreamSender, PacketStream) {
+    let capacity = cmp::max(capacity, 1);
+    let state = Arc::new(Mutex::new(State {
+        packets: VecDeque::with_capacity(capacity),
+        capacity: capacity,
+        policy: policy,
+        overflows: 0,
+        closed: false,
+        waker: None,
+    }));
+    (PacketStreamSender { state: state.clone() }, PacketStream { state: state })
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+    use futures::StreamExt;
+
+    use std::ops::Range;
+    use std::thread;
+
+    use stream::{channel, OverflowPolicy, OwnedPacket, PacketStreamSender};
+    use packets::PacketBuffer;
+
+    fn send_packets(sender: &PacketStreamSender, timestamps: Range<u64>) {
+        for timestamp in timestamps {
+            let mut packet_buffer = PacketBuffer::dyn();
+            packet_buffer.push_packet(timestamp, &[0xf8]);
+            for packet in packet_buffer.as_ref().iter() {
+                sender.send(packet);
+            }
+        }
+    }
+
+    fn timestamps(packets: Vec<OwnedPacket>) -> Vec<u64> {
+        packets.into_iter().map(|packet| packet.timestamp).collect()
+    }
+
+    #[test]
+    fn stream_from_another_thread() {
+        let (sender, mut stream) = channel(16, OverflowPolicy::DropNewest);
+        let producer = thread::spawn(move || {
+            for i in 0..100 {
+                send_packets(&sender, i..i + 1);
+                thread::yield_now();
+            }
+        });
+        let received = timestamps(block_on(stream.by_ref().collect()));
+        producer.join().unwrap();
+
+        assert!(received.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(received.len() + stream.overflow_count(), 100);
+    }
+
+    #[test]
+    fn stream_drop_newest() {
+        let (sender, stream) = channel(3, OverflowPolicy::DropNewest);
+        send_packets(&sender, 0..5);
+        assert_eq!(stream.overflow_count(), 2);
+        drop(sender);
+        assert_eq!(timestamps(block_on(stream.collect())), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn stream_drop_oldest() {
+        let (sender, stream) = channel(3, OverflowPolicy::DropOldest);
+        send_packets(&sender, 0..5);
+        assert_eq!(stream.overflow_count(), 2);
+        drop(sender);
+        assert_eq!(timestamps(block_on(stream.collect())), vec![2, 3, 4]);
+    }
+
+    #[test]
+    fn stream_capacity_is_at_least_one() {
+        let (sender, stream) = channel(0, OverflowPolicy::DropOldest);
+        send_packets(&sender, 0..3);
+        assert_eq!(stream.overflow_count(), 2);
+        drop(sender);
+        assert_eq!(timestamps(block_on(stream.collect())), vec![2]);
+    }
+
+    #[test]
+    fn stream_survives_a_poisoned_lock() {
+        let (sender, stream) = channel(3, OverflowPolicy::DropNewest);
+        let state = sender.state.clone();
+        assert!(thread::spawn(move || {
+            let _state = state.lock().unwrap();
+            panic!("poisoning the lock");
+        }).join().is_err());
+
+        send_packets(&sender, 0..2);
+        drop(sender);
+        assert_eq!(timestamps(block_on(stream.collect())), vec![0, 1]);
+    }
+
+    #[test]
+    fn stream_ends_when_sender_dropped() {
+        let (sender, mut stream) = channel(3, OverflowPolicy::DropNewest);
+        send_packets(&sender, 7..8);
+        drop(sender);
+        assert_eq!(block_on(stream.next()).map(|packet| packet.data), Some(vec![0xf8]));
+        assert_eq!(block_on(stream.next()), None);
+    }
+}
@@ -0,0 +1,182 @@
+#![allow(non_snake_case, non_upper_case_globals, non_camel_case_types)]
+
+// The CoreMIDI types and constants used outside of the CoreMidiBackend.
+//
+// On macOS they come from coremidi-sys. On other platforms, where only the in-memory backends are available,
+// they are defined here with the same layout, so the crate and its tests can be built anywhere.
+
+#[cfg(target_os = "macos")]
+pub use core_foundation_sys::base::OSStatus;
+#[cfg(target_os = "macos")]
+pub use core_foundation_sys::string::CFStringRef;
+
+#[cfg(target_os = "macos")]
+pub use coremidi_sys::{
+    SInt32,
+    MIDIObjectRef, MIDIClientRef, MIDIPortRef, MIDIDeviceRef, MIDIEntityRef, MIDIEndpointRef, MIDIThruConnectionRef,
+    MIDIObjectType, MIDIUniqueID,
+    kMIDIObjectType_Other, kMIDIObjectType_Device, kMIDIObjectType_Entity, kMIDIObjectType_Source,
+    kMIDIObjectType_Destination, kMIDIObjectType_ExternalDevice, kMIDIObjectType_ExternalEntity,
+    kMIDIObjectType_ExternalSource, kMIDIObjectType_ExternalDestination,
+    MIDINotificationMessageID, MIDINotification, MIDINotifyProc,
+    MIDIObjectAddRemoveNotification, MIDIObjectPropertyChangeNotification, MIDIIOErrorNotification,
+    kMIDIMsgSetupChanged, kMIDIMsgObjectAdded, kMIDIMsgObjectRemoved, kMIDIMsgPropertyChanged,
+    kMIDIMsgThruConnectionsChanged, kMIDIMsgSerialPortOwnerChanged, kMIDIMsgIOError,
+    kMIDITransform_None, kMIDITransform_FilterOut, kMIDITransform_MapControl, kMIDITransform_Add,
+    kMIDITransform_Scale, kMIDITransform_MinValue, kMIDITransform_MaxValue, kMIDITransform_MapValue,
+    kMIDIControlType_7Bit, kMIDIControlType_14Bit, kMIDIControlType_7BitRPN, kMIDIControlType_14BitRPN,
+    kMIDIControlType_7BitNRPN, kMIDIControlType_14BitNRPN
+};
+
+#[cfg(target_os = "macos")]
+pub use coremidi_sys_ext::{mach_absolute_time, mach_timebase_info, mach_timebase_info_data};
+
+pub type MIDIReadProc =
+    ::std::option::Option<extern "C" fn(pktlist: *const MIDIPacketList,
+                                        readProcRefCon: *mut ::libc::c_void,
+                                        srcConnRefCon: *mut ::libc::c_void)
+                              -> ()>;
+
+// Should only be used in a pointer
+#[repr(C)]
+pub struct MIDIPacketList(u8);
+
+/// Convert a string received from CoreMIDI, without taking its ownership.
+///
+#[cfg(target_os = "macos")]
+pub unsafe fn string_from_cfstring(string_ref: CFStringRef) -> String {
+    use core_foundation::base::TCFType;
+    use core_foundation::string::CFString;
+
+    let string: CFString = TCFType::wrap_under_get_rule(string_ref);
+    format!("{}", string)
+}
+
+/// Convert a string received from CoreMIDI, without taking its ownership.
+///
+#[cfg(not(target_os = "macos"))]
+pub unsafe fn string_from_cfstring(string_ref: CFStringRef) -> String {
+    ::std::ffi::CStr::from_ptr(string_ref).to_string_lossy().into_owned()
+}
+
+#[cfg(not(target_os = "macos"))]
+pub use self::portable::*;
+
+#[cfg(not(target_os = "macos"))]
+mod portable {
+    use std::sync::OnceLock;
+    use std::time::Instant;
+
+    pub type OSStatus = i32;
+    // A nul-terminated UTF-8 string, standing for a CFString
+    pub type CFStringRef = *const ::libc::c_char;
+
+    pub type UInt32 = ::libc::c_uint;
+    pub type SInt32 = ::libc::c_int;
+
+    pub type MIDIObjectRef = UInt32;
+    pub type MIDIClientRef = MIDIObjectRef;
+    pub type MIDIPortRef = MIDIObjectRef;
+    pub type MIDIDeviceRef = MIDIObjectRef;
+    pub type MIDIEntityRef = MIDIObjectRef;
+    pub type MIDIEndpointRef = MIDIObjectRef;
+    pub type MIDIThruConnectionRef = MIDIObjectRef;
+    pub type MIDIObjectType = SInt32;
+    pub type MIDIUniqueID = SInt32;
+
+    pub const kMIDIObjectType_Other: ::libc::c_int = -1;
+    pub const kMIDIObjectType_Device: ::libc::c_int = 0;
+    pub const kMIDIObjectType_Entity: ::libc::c_int = 1;
+    pub const kMIDIObjectType_Source: ::libc::c_int = 2;
+    pub const kMIDIObjectType_Destination: ::libc::c_int = 3;
+    pub const kMIDIObjectType_ExternalDevice: ::libc::c_int = 16;
+    pub const kMIDIObjectType_ExternalEntity: ::libc::c_int = 17;
+    pub const kMIDIObjectType_ExternalSource: ::libc::c_int = 18;
+    pub const kMIDIObjectType_ExternalDestination: ::libc::c_int = 19;
+
+    pub type MIDINotificationMessageID = SInt32;
+
+    pub type MIDINotifyProc =
+        ::std::option::Option<extern "C" fn(message: *const MIDINotification,
+                                            refCon: *mut ::libc::c_void) -> ()>;
+
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    pub struct MIDINotification {
+        pub messageID: MIDINotificationMessageID,
+        pub messageSize: UInt32,
+    }
+
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    pub struct MIDIObjectAddRemoveNotification {
+        pub messageID: MIDINotificationMessageID,
+        pub messageSize: UInt32,
+        pub parent: MIDIObjectRef,
+        pub parentType: MIDIObjectType,
+        pub child: MIDIObjectRef,
+        pub childType: MIDIObjectType,
+    }
+
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    pub struct MIDIObjectPropertyChangeNotification {
+        pub messageID: MIDINotificationMessageID,
+        pub messageSize: UInt32,
+        pub object: MIDIObjectRef,
+        pub objectType: MIDIObjectType,
+        pub propertyName: CFStringRef,
+    }
+
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    pub struct MIDIIOErrorNotification {
+        pub messageID: MIDINotificationMessageID,
+        pub messageSize: UInt32,
+        pub driverDevice: MIDIDeviceRef,
+        pub errorCode: OSStatus,
+    }
+
+    pub const kMIDIMsgSetupChanged: ::libc::c_uint = 1;
+    pub const kMIDIMsgObjectAdded: ::libc::c_uint = 2;
+    pub const kMIDIMsgObjectRemoved: ::libc::c_uint = 3;
+    pub const kMIDIMsgPropertyChanged: ::libc::c_uint = 4;
+    pub const kMIDIMsgThruConnectionsChanged: ::libc::c_uint = 5;
+    pub const kMIDIMsgSerialPortOwnerChanged: ::libc::c_uint = 6;
+    pub const kMIDIMsgIOError: ::libc::c_uint = 7;
+
+    pub const kMIDITransform_None: ::libc::c_uint = 0;
+    pub const kMIDITransform_FilterOut: ::libc::c_uint = 1;
+    pub const kMIDITransform_MapControl: ::libc::c_uint = 2;
+    pub const kMIDITransform_Add: ::libc::c_uint = 8;
+    pub const kMIDITransform_Scale: ::libc::c_uint = 9;
+    pub const kMIDITransform_MinValue: ::libc::c_uint = 10;
+    pub const kMIDITransform_MaxValue: ::libc::c_uint = 11;
+    pub const kMIDITransform_MapValue: ::libc::c_uint = 12;
+
+    pub const kMIDIControlType_7Bit: ::libc::c_uint = 0;
+    pub const kMIDIControlType_14Bit: ::libc::c_uint = 1;
+    pub const kMIDIControlType_7BitRPN: ::libc::c_uint = 2;
+    pub const kMIDIControlType_14BitRPN: ::libc::c_uint = 3;
+    pub const kMIDIControlType_7BitNRPN: ::libc::c_uint = 4;
+    pub const kMIDIControlType_14BitNRPN: ::libc::c_uint = 5;
+
+    #[repr(C)]
+    pub struct mach_timebase_info_data {
+        pub numer: u32,
+        pub denom: u32,
+    }
+
+    // The host time is in nanoseconds since the first time it was read,
+    // starting at 1 as a timestamp of 0 means now.
+    pub unsafe fn mach_absolute_time() -> u64 {
+        static EPOCH: OnceLock<Instant> = OnceLock::new();
+        EPOCH.get_or_init(Instant::now).elapsed().as_nanos() as u64 + 1
+    }
+
+    pub unsafe fn mach_timebase_info(info: *mut mach_timebase_info_data) -> ::libc::c_int {
+        (*info).numer = 1;
+        (*info).denom = 1;
+        0
+    }
+}
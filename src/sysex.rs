@@ -0,0 +1,272 @@
+use std::error;
+use std::fmt;
+
+use messages::{MidiMessage, MessageError, MessagesIterator};
+use packets::{PacketListRef, PacketRef, Timestamp};
+
+/// The default maximum size in bytes for the SysEx messages put together by a [SysExAssembler](struct.SysExAssembler.html).
+///
+pub const DEFAULT_MAX_SYSEX_SIZE: usize = 1024 * 1024;
+
+/// An error found while putting together SysEx messages.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SysExError {
+    /// The SysEx message was larger than the maximum size, the rest of it is discarded.
+    TooLarge(usize),
+    /// The SysEx message was interrupted by a status byte other than a real-time one, it is discarded.
+    Unterminated,
+    /// An error decoding the messages outside of a SysEx message.
+    Message(MessageError),
+}
+
+impl fmt::Display for SysExError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SysExError::TooLarge(max_size) => write!(f, "SysEx message larger than {} bytes", max_size),
+            SysExError::Unterminated => write!(f, "unterminated SysEx message"),
+            SysExError::Message(ref err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl error::Error for SysExError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Idle,
+    Assembling(Timestamp),
+    Discarding,
+}
+
+/// Puts together the SysEx messages split across packets and packet lists.
+///
+/// It is fed the packets as they are received, and reports every message found through a callback:
+/// complete SysEx messages, from the `F0` to the `F7`, once their last part arrives, and the other messages
+/// as they are found. The real-time messages found in the middle of a SysEx message are reported before it,
+/// as they are received.
+///
+/// A SysEx message is reported with the timestamp of the packet where it started:
+///
+/// ```
+/// let mut packet_buffer = coremidi::PacketBuffer::dyn();
+/// packet_buffer.push_packet(1, &[0xf0, 0x7e, 0x01]).push_packet(2, &[0x02, 0xf8, 0x03, 0xf7]);
+///
+/// let mut assembler = coremidi::SysExAssembler::new(1024);
+/// let mut messages = Vec::new();
+/// assembler.feed_list(packet_buffer.as_ref(), |timestamp, message| {
+///     messages.push((timestamp, format!("{:?}", message.unwrap())));
+/// });
+/// assert_eq!(messages, vec![
+///     (2, "TimingClock".to_string()),
+///     (1, "SysEx([240, 126, 1, 2, 3, 247])".to_string())]);
+/// ```
+pub struct SysExAssembler {
+    buffer: Vec<u8>,
+    max_size: usize,
+    state: State,
+}
+
+impl SysExAssembler {
+    /// Create an assembler for SysEx messages of up to `max_size` bytes, including the `F0` and `F7`.
+    ///
+    pub fn new(max_size: usize) -> SysExAssembler {
+        SysExAssembler {
+            buffer: Vec::new(),
+            max_size: max_size,
+            state: State::Idle,
+        }
+    }
+
+    /// Whether part of a SysEx message has been received, and its end is still pending.
+    ///
+    pub fn is_pending(&self) -> bool {
+        self.state != State::Idle
+    }
+
+    /// Discard any pending SysEx message.
+    ///
+    pub fn reset(&mut self) {
+        self.buffer.clear();
+        self.state = State::Idle;
+    }
+
+    /// Feed all the packets of a packet list.
+    ///
+    pub fn feed_list<F>(&mut self, packet_list: PacketListRef, mut f: F)
+            where F: FnMut(Timestamp, Result<MidiMessage, SysExError>) {
+        for packet in packet_list.iter() {
+            self.feed(packet, &mut f);
+        }
+    }
+
+    /// Feed a packet, calling `f` for every message completed by it.
+    ///
+    pub fn feed<F>(&mut self, packet: PacketRef, mut f: F)
+            where F: FnMut(Timestamp, Result<MidiMessage, SysExError>) {
+        let timestamp = packet.timestamp();
+        for result in MessagesIterator::new(packet.data()) {
+            match result {
+                Ok(MidiMessage::SysEx(data)) => self.sysex(timestamp, data, &mut f),
+                Ok(message) if message.is_realtime() => f(timestamp, Ok(message)),
+                // The undefined status bytes in the real-time range don't interrupt a SysEx message either
+                Err(err @ MessageError::UndefinedStatus(0xf8 ..= 0xff)) => f(timestamp, Err(SysExError::Message(err))),
+                result => {
+                    self.interrupt(timestamp, &mut f);
+                    f(timestamp, result.map_err(SysExError::Message));
+                }
+            }
+        }
+    }
+
+    // A SysEx message, or part of one, as found by the messages iterator.
+    fn sysex<F>(&mut self, timestamp: Timestamp, data: &[u8], f: &mut F)
+            where F: FnMut(Timestamp, Result<MidiMessage, SysExError>) {
+        if data[0] == 0xf0 {
+            self.interrupt(timestamp, f);
+            self.state = State::Assembling(timestamp);
+        }
+
+        let start_timestamp = match self.state {
+            State::Assembling(start_timestamp) => start_timestamp,
+            State::Discarding => {
+                if data[data.len() - 1] == 0xf7 {
+                    self.state = State::Idle;
+                }
+                return;
+            },
+            State::Idle => {
+                // The rest of a SysEx message which start was not received
+                let err = if data[0] == 0xf7 { MessageError::UnexpectedEndOfSysEx } else { MessageError::UnexpectedData(data[0]) };
+                f(timestamp, Err(SysExError::Message(err)));
+                return;
+            }
+        };
+
+        if self.buffer.len() + data.len() > self.max_size {
+            self.buffer.clear();
+            self.state = if data[data.len() - 1] == 0xf7 { State::Idle } else { State::Discarding };
+            f(start_timestamp, Err(SysExError::TooLarge(self.max_size)));
+            return;
+        }
+
+        self.buffer.extend_from_slice(data);
+        if data[data.len() - 1] == 0xf7 {
+            self.state = State::Idle;
+            f(start_timestamp, Ok(MidiMessage::SysEx(&self.buffer)));
+            self.buffer.clear();
+        }
+    }
+
+    // A status byte other than a real-time one or the end of the SysEx was found.
+    fn interrupt<F>(&mut self, timestamp: Timestamp, f: &mut F)
+            where F: FnMut(Timestamp, Result<MidiMessage, SysExError>) {
+        match self.state {
+            State::Assembling(_) => f(timestamp, Err(SysExError::Unterminated)),
+            State::Discarding | State::Idle => {}
+        }
+        self.reset();
+    }
+}
+
+impl Default for SysExAssembler {
+    fn default() -> Self {
+        SysExAssembler::new(DEFAULT_MAX_SYSEX_SIZE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sysex::{SysExAssembler, SysExError};
+    use messages::{MessageError, MidiMessage};
+    use packets::PacketBuffer;
+
+    #[derive(Debug, PartialEq)]
+    enum Event {
+        SysEx(u64, Vec<u8>),
+        Message(u64, String),
+        Error(u64, SysExError),
+    }
+
+    fn feed(assembler: &mut SysExAssembler, packets: &[(u64, &[u8])]) -> Vec<Event> {
+        let mut packet_buffer = PacketBuffer::dyn();
+        for &(timestamp, data) in packets {
+            packet_buffer.push_packet(timestamp, data);
+        }
+        let mut events = Vec::new();
+        assembler.feed_list(packet_buffer.as_ref(), |timestamp, result| {
+            events.push(match result {
+                Ok(MidiMessage::SysEx(data)) => Event::SysEx(timestamp, data.to_vec()),
+                Ok(message) => Event::Message(timestamp, format!("{:?}", message)),
+                Err(err) => Event::Error(timestamp, err),
+            });
+        });
+        events
+    }
+
+    #[test]
+    fn complete_sysex_in_one_packet() {
+        let mut assembler = SysExAssembler::default();
+        assert_eq!(feed(&mut assembler, &[(1, &[0x90, 0x40, 0x7f, 0xf0, 0x7e, 0x00, 0xf7, 0xf8])]), vec![
+            Event::Message(1, "NoteOn { channel: 0, note: 64, velocity: 127 }".to_string()),
+            Event::SysEx(1, vec![0xf0, 0x7e, 0x00, 0xf7]),
+            Event::Message(1, "TimingClock".to_string())]);
+        assert!(!assembler.is_pending());
+    }
+
+    #[test]
+    fn sysex_across_packets_and_lists() {
+        let mut assembler = SysExAssembler::default();
+        assert_eq!(feed(&mut assembler, &[(1, &[0xf0, 0x01]), (2, &[0x02, 0x03])]), vec![]);
+        assert!(assembler.is_pending());
+        assert_eq!(feed(&mut assembler, &[(3, &[0x04, 0xf7, 0xc0, 0x05])]), vec![
+            Event::SysEx(1, vec![0xf0, 0x01, 0x02, 0x03, 0x04, 0xf7]),
+            Event::Message(3, "ProgramChange { channel: 0, program: 5 }".to_string())]);
+        assert!(!assembler.is_pending());
+    }
+
+    #[test]
+    fn realtime_in_the_middle_of_sysex() {
+        let mut assembler = SysExAssembler::default();
+        assert_eq!(feed(&mut assembler, &[(1, &[0xf0, 0x01, 0xf8, 0x02]), (2, &[0xfe, 0x03, 0xf9, 0xf7])]), vec![
+            Event::Message(1, "TimingClock".to_string()),
+            Event::Message(2, "ActiveSensing".to_string()),
+            Event::Error(2, SysExError::Message(MessageError::UndefinedStatus(0xf9))),
+            Event::SysEx(1, vec![0xf0, 0x01, 0x02, 0x03, 0xf7])]);
+    }
+
+    #[test]
+    fn interrupted_sysex() {
+        let mut assembler = SysExAssembler::default();
+        assert_eq!(feed(&mut assembler, &[(1, &[0xf0, 0x01]), (2, &[0x02, 0x90, 0x40, 0x7f, 0x03, 0xf7])]), vec![
+            Event::Error(2, SysExError::Unterminated),
+            Event::Message(2, "NoteOn { channel: 0, note: 64, velocity: 127 }".to_string()),
+            Event::Error(2, SysExError::Message(MessageError::UnexpectedData(0x03))),
+            Event::Error(2, SysExError::Message(MessageError::UnexpectedEndOfSysEx))]);
+
+        assert_eq!(feed(&mut assembler, &[(3, &[0xf0, 0x01]), (4, &[0xf0, 0x02, 0xf7])]), vec![
+            Event::Error(4, SysExError::Unterminated),
+            Event::SysEx(4, vec![0xf0, 0x02, 0xf7])]);
+
+        feed(&mut assembler, &[(5, &[0xf0, 0x01])]);
+        assembler.reset();
+        assert!(!assembler.is_pending());
+        assert_eq!(feed(&mut assembler, &[(6, &[0x02, 0xf7])]), vec![
+            Event::Error(6, SysExError::Message(MessageError::UnexpectedData(0x02)))]);
+    }
+
+    #[test]
+    fn sysex_too_large() {
+        let mut assembler = SysExAssembler::new(6);
+        assert_eq!(feed(&mut assembler, &[(1, &[0xf0, 0x01, 0x02, 0x03, 0x04, 0xf7])]), vec![
+            Event::SysEx(1, vec![0xf0, 0x01, 0x02, 0x03, 0x04, 0xf7])]);
+
+        assert_eq!(feed(&mut assembler, &[(2, &[0xf0, 0x01, 0x02]), (3, &[0x03, 0x04, 0x05, 0x06])]), vec![
+            Event::Error(2, SysExError::TooLarge(6))]);
+        assert!(assembler.is_pending());
+        assert_eq!(feed(&mut assembler, &[(4, &[0x07, 0xf8, 0x08, 0xf7, 0xf0, 0x09, 0xf7])]), vec![
+            Event::Message(4, "TimingClock".to_string()),
+            Event::SysEx(4, vec![0xf0, 0x09, 0xf7])]);
+        assert!(!assembler.is_pending());
+    }
+}
@@ -0,0 +1,463 @@
+use std::cmp;
+use std::marker::PhantomData;
+use std::panic;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use sys::{MIDIPortRef, MIDIEndpointRef, SInt32};
+
+use backend::Backend;
+use clock::Clock;
+use packets::{PacketBuffer, Timestamp};
+use properties::{Properties, PropertyGetter};
+use time::Timebase;
+use Destination;
+use Error;
+use OutputPort;
+
+/// The default speed for sending SysEx messages, in bytes per second, as for a DIN MIDI cable.
+///
+pub const DEFAULT_SYSEX_SPEED: u32 = 3125;
+
+/// The default number of bytes sent at once for a SysEx message.
+///
+pub const DEFAULT_SYSEX_CHUNK_SIZE: usize = 256;
+
+// The maximum number of data bytes in a packet.
+const MAX_CHUNK_SIZE: usize = 0xffff;
+
+/// A large SysEx message to be sent in chunks, not faster than a given speed.
+/// It is the equivalent of [MIDISysexSendRequest](https://developer.apple.com/reference/coremidi/midisysexsendrequest).
+///
+/// It can be sent with [OutputPort::send_sysex](struct.OutputPort.html#method.send_sysex), or driven step by step
+/// with [poll](#method.poll).
+///
+pub struct SysExRequest {
+    data: Vec<u8>,
+    chunk_size: usize,
+    bytes_per_second: u32,
+    bytes_sent: usize,
+    // When the next chunk can be sent, once the previous one would have been transmitted.
+    next_due: Timestamp,
+    cancelled: bool,
+}
+
+/// What to do next for a [SysExRequest](struct.SysExRequest.html).
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SysExPoll<'a> {
+    /// Send this chunk now.
+    Send(&'a [u8]),
+    /// Wait until this timestamp to send the next chunk.
+    Wait(Timestamp),
+    /// All the data has been sent.
+    Done,
+    /// The request was cancelled.
+    Cancelled,
+}
+
+/// The progress of a SysEx message being sent, as reported by [OutputPort::send_sysex](struct.OutputPort.html#method.send_sysex).
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SysExProgress {
+    /// Another chunk was sent.
+    Sent { bytes_sent: usize, bytes_total: usize },
+    /// All the data was sent.
+    Complete,
+    /// The request was cancelled before sending all the data.
+    Cancelled { bytes_sent: usize },
+    /// A chunk could not be sent, the rest of the data is not sent.
+    Failed(Error),
+}
+
+impl SysExRequest {
+    /// Create a request for some data, with the default speed and chunk size.
+    ///
+    pub fn new(data: Vec<u8>) -> SysExRequest {
+        SysExRequest {
+            data: data,
+            chunk_size: DEFAULT_SYSEX_CHUNK_SIZE,
+            bytes_per_second: DEFAULT_SYSEX_SPEED,
+            bytes_sent: 0,
+            next_due: 0,
+            cancelled: false,
+        }
+    }
+
+    /// Create a request for some data, at the speed of the destination.
+    /// See [Properties::max_sysex_speed](struct.Properties.html#method.max_sysex_speed).
+    ///
+    pub fn for_destination(data: Vec<u8>, destination: &Destination) -> SysExRequest {
+        let speed: Result<SInt32, Error> = Properties::max_sysex_speed().value_from(destination);
+        match speed {
+            Ok(speed) if speed > 0 => SysExRequest::new(data).with_speed(speed as u32),
+            _ => SysExRequest::new(data)
+        }
+    }
+
+    /// Set the maximum speed in bytes per second.
+    ///
+    /// It panics if the speed is 0.
+    ///
+    pub fn with_speed(mut self, bytes_per_second: u32) -> SysExRequest {
+        assert!(bytes_per_second > 0, "the SysEx speed must be positive");
+        self.bytes_per_second = bytes_per_second;
+        self
+    }
+
+    /// Set the number of bytes sent at once, limited to what fits in a packet.
+    ///
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> SysExRequest {
+        self.chunk_size = chunk_size.clamp(1, MAX_CHUNK_SIZE);
+        self
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn bytes_sent(&self) -> usize {
+        self.bytes_sent
+    }
+
+    /// Whether all the data has been sent.
+    ///
+    pub fn is_complete(&self) -> bool {
+        self.bytes_sent == self.data.len()
+    }
+
+    /// Stop sending the data.
+    ///
+    pub fn cancel(&mut self) {
+        self.cancelled = true;
+    }
+
+    /// Get the next chunk to send if it is due at `now`, given the timebase of the timestamps.
+    ///
+    /// The first chunk is due on the first poll, and every following one once the previous chunk
+    /// would have been transmitted at the speed of the request, counting from when it was sent.
+    /// So a late poll sends a single chunk, rather than all the ones that would have been due by then.
+    ///
+    pub fn poll(&mut self, now: Timestamp, timebase: &Timebase) -> SysExPoll<'_> {
+        if self.cancelled {
+            return SysExPoll::Cancelled;
+        }
+        if self.is_complete() {
+            return SysExPoll::Done;
+        }
+
+        if now < self.next_due {
+            return SysExPoll::Wait(self.next_due);
+        }
+
+        let offset = self.bytes_sent;
+        self.bytes_sent = cmp::min(offset + self.chunk_size, self.data.len());
+        let nanos = (self.bytes_sent - offset) as u128 * 1_000_000_000 / self.bytes_per_second as u128;
+        self.next_due = now.saturating_add(timebase.nanos_to_ticks(cmp::min(nanos, u64::MAX as u128) as u64));
+        SysExPoll::Send(&self.data[offset..self.bytes_sent])
+    }
+}
+
+/// A SysEx message being sent in the background.
+/// See [OutputPort::send_sysex](struct.OutputPort.html#method.send_sysex).
+///
+/// It borrows the port it is sent through, so the port can't be dropped in the middle of it:
+///
+/// ```rust,compile_fail
+/// let client = coremidi::Client::new("example-client").unwrap();
+/// let output_port = client.output_port("example-port").unwrap();
+/// let request = coremidi::SysExRequest::new(vec![0xf0, 0x7e, 0x7f, 0x09, 0x01, 0xf7]);
+/// let transfer = output_port.send_sysex(&coremidi::Destination::from_index(0), request, |_| {});
+/// drop(output_port);
+/// transfer.wait();
+/// ```
+///
+/// Dropping the transfer cancels it, and waits for the chunk being sent, if any.
+///
+pub struct SysExTransfer<'a> {
+    cancelled: Arc<AtomicBool>,
+    // Only taken when waiting or dropping the transfer
+    thread: Option<thread::JoinHandle<SysExProgress>>,
+    _port: PhantomData<&'a OutputPort>,
+}
+
+impl<'a> SysExTransfer<'a> {
+    /// Stop sending the data. The chunk being sent, if any, is not interrupted.
+    ///
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    /// Wait until the transfer finishes, and get how it finished.
+    ///
+    pub fn wait(mut self) -> SysExProgress {
+        match self.thread.take().unwrap().join() {
+            Ok(progress) => progress,
+            Err(panic) => panic::resume_unwind(panic)
+        }
+    }
+}
+
+impl<'a> Drop for SysExTransfer<'a> {
+    fn drop(&mut self) {
+        if let Some(thread) = self.thread.take() {
+            self.cancel();
+            // A panic from the progress callback is already reported by the thread itself
+            let _ = thread.join();
+        }
+    }
+}
+
+pub fn spawn<'a, F>(backend: Arc<dyn Backend>, clock: Arc<dyn Clock>, port: MIDIPortRef, destination: MIDIEndpointRef,
+                    mut request: SysExRequest, mut progress: F) -> SysExTransfer<'a>
+        where F: FnMut(SysExProgress) + Send + 'static {
+
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let thread_cancelled = cancelled.clone();
+    let thread = thread::spawn(move || {
+        let timebase = Timebase::host();
+        let bytes_total = request.data().len();
+        loop {
+            if thread_cancelled.load(Ordering::Relaxed) {
+                request.cancel();
+            }
+            let now = clock.now();
+            let finished = match request.poll(now, &timebase) {
+                SysExPoll::Send(chunk) => {
+                    let mut packet_buffer = PacketBuffer::with_capacity(chunk.len() + 16);
+                    packet_buffer.push_packet(0, chunk);
+                    let status = backend.send(port, destination, packet_buffer.as_ref());
+                    if status == 0 { None } else { Some(SysExProgress::Failed(Error::from(status))) }
+                },
+                SysExPoll::Wait(due) => {
+                    thread::sleep(cmp::min(timebase.ticks_to_duration(due - now), Duration::from_millis(100)));
+                    continue;
+                },
+                SysExPoll::Done => Some(SysExProgress::Complete),
+                SysExPoll::Cancelled => Some(SysExProgress::Cancelled { bytes_sent: request.bytes_sent() }),
+            };
+            match finished {
+                Some(finished) => {
+                    progress(finished);
+                    return finished;
+                },
+                None => progress(SysExProgress::Sent { bytes_sent: request.bytes_sent(), bytes_total: bytes_total })
+            }
+        }
+    });
+    SysExTransfer { cancelled: cancelled, thread: Some(thread), _port: PhantomData }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    use backend::with_backend;
+    use backend::loopback::Loopback;
+    use clock::{Clock, ManualClock};
+    use sysex_send::{SysExRequest, SysExPoll, SysExProgress, DEFAULT_SYSEX_SPEED};
+    use time::Timebase;
+    use {Client, Destination, Error, PacketList, Properties, PropertySetter};
+
+    fn sysex(length: usize) -> Vec<u8> {
+        let mut data = vec![0x01; length];
+        data[0] = 0xf0;
+        data[length - 1] = 0xf7;
+        data
+    }
+
+    #[test]
+    fn poll_paces_chunks() {
+        // 1000 bytes per second, so a chunk of 100 bytes every 100 ms
+        let timebase = Timebase::new(1, 1);
+        let clock = ManualClock::new(5_000_000_000);
+        let data = sysex(250);
+        let mut request = SysExRequest::new(data.clone()).with_speed(1000).with_chunk_size(100);
+
+        assert_eq!(request.poll(clock.now(), &timebase), SysExPoll::Send(&data[..100]));
+        assert_eq!(request.poll(clock.now(), &timebase), SysExPoll::Wait(5_100_000_000));
+        clock.advance(99_999_999);
+        assert_eq!(request.poll(clock.now(), &timebase), SysExPoll::Wait(5_100_000_000));
+        clock.advance(1);
+        assert_eq!(request.poll(clock.now(), &timebase), SysExPoll::Send(&data[100..200]));
+        assert_eq!(request.bytes_sent(), 200);
+
+        clock.advance(500_000_000);
+        assert_eq!(request.poll(clock.now(), &timebase), SysExPoll::Send(&data[200..]));
+        assert!(request.is_complete());
+        assert_eq!(request.poll(clock.now(), &timebase), SysExPoll::Done);
+    }
+
+    #[test]
+    fn late_polls_dont_burst() {
+        let timebase = Timebase::new(1, 1);
+        let clock = ManualClock::new(5_000_000_000);
+        let data = sysex(550);
+        let mut request = SysExRequest::new(data.clone()).with_speed(1000).with_chunk_size(100);
+        assert_eq!(request.poll(clock.now(), &timebase), SysExPoll::Send(&data[..100]));
+
+        // Several chunks would have been due by now, but only one is sent, and the next one is paced from it
+        clock.advance(450_000_000);
+        assert_eq!(request.poll(clock.now(), &timebase), SysExPoll::Send(&data[100..200]));
+        assert_eq!(request.poll(clock.now(), &timebase), SysExPoll::Wait(5_550_000_000));
+        clock.advance(100_000_000);
+        assert_eq!(request.poll(clock.now(), &timebase), SysExPoll::Send(&data[200..300]));
+        assert_eq!(request.poll(clock.now(), &timebase), SysExPoll::Wait(5_650_000_000));
+
+        // Another late poll still sends one chunk at a time
+        clock.advance(1_000_000_000);
+        assert_eq!(request.poll(clock.now(), &timebase), SysExPoll::Send(&data[300..400]));
+        assert_eq!(request.poll(clock.now(), &timebase), SysExPoll::Wait(6_650_000_000));
+        clock.advance(100_000_000);
+        assert_eq!(request.poll(clock.now(), &timebase), SysExPoll::Send(&data[400..500]));
+        clock.advance(100_000_000);
+        assert_eq!(request.poll(clock.now(), &timebase), SysExPoll::Send(&data[500..]));
+        assert_eq!(request.poll(clock.now(), &timebase), SysExPoll::Done);
+    }
+
+    #[test]
+    fn poll_with_host_timebase_ticks() {
+        // 24 MHz ticks, 3125 bytes per second: 256 bytes take 81.92 ms
+        let timebase = Timebase::new(125, 3);
+        let mut request = SysExRequest::new(sysex(1000));
+        assert_eq!(request.poll(1000, &timebase), SysExPoll::Send(&sysex(1000)[..256]));
+        assert_eq!(request.poll(1000, &timebase), SysExPoll::Wait(1000 + 1_966_080));
+    }
+
+    #[test]
+    fn poll_cancelled() {
+        let timebase = Timebase::new(1, 1);
+        let mut request = SysExRequest::new(sysex(10)).with_chunk_size(4);
+        assert!(matches!(request.poll(0, &timebase), SysExPoll::Send(_)));
+        request.cancel();
+        assert_eq!(request.poll(1_000_000_000, &timebase), SysExPoll::Cancelled);
+        assert_eq!(request.bytes_sent(), 4);
+    }
+
+    #[test]
+    fn chunk_size_fits_in_a_packet() {
+        let timebase = Timebase::new(1, 1);
+        let data = sysex(200_000);
+        let mut request = SysExRequest::new(data).with_chunk_size(1_000_000);
+        assert_eq!(match request.poll(0, &timebase) { SysExPoll::Send(chunk) => chunk.len(), _ => 0 }, 0xffff);
+        let mut request = SysExRequest::new(sysex(2)).with_chunk_size(0);
+        assert_eq!(match request.poll(0, &timebase) { SysExPoll::Send(chunk) => chunk.len(), _ => 0 }, 1);
+    }
+
+    #[test]
+    fn speed_from_destination() {
+        let loopback = Arc::new(Loopback::new());
+        loopback.add_destination("slow");
+        with_backend(loopback, || {
+            let destination = Destination::from_index(0);
+            let timebase = Timebase::new(1, 1);
+
+            let mut request = SysExRequest::for_destination(sysex(512), &destination);
+            request.poll(0, &timebase);
+            assert_eq!(request.poll(0, &timebase), SysExPoll::Wait(256 * 1_000_000_000 / DEFAULT_SYSEX_SPEED as u64));
+
+            Properties::max_sysex_speed().set_value(&destination, 256).unwrap();
+            let mut request = SysExRequest::for_destination(sysex(512), &destination);
+            request.poll(0, &timebase);
+            assert_eq!(request.poll(0, &timebase), SysExPoll::Wait(1_000_000_000));
+        });
+    }
+
+    #[test]
+    fn send_sysex_in_the_background() {
+        let loopback = Arc::new(Loopback::new());
+        let external = loopback.add_destination("external");
+        let progress = Arc::new(Mutex::new(Vec::new()));
+        with_backend(loopback.clone(), || {
+            let client = Client::new("client").unwrap();
+            let output_port = client.output_port("port").unwrap();
+            let request = SysExRequest::new(sysex(10)).with_speed(1_000_000).with_chunk_size(4);
+            let thread_progress = progress.clone();
+            let transfer = output_port.send_sysex(&Destination::from_index(0), request, move |progress| {
+                thread_progress.lock().unwrap().push(progress);
+            });
+            assert_eq!(transfer.wait(), SysExProgress::Complete);
+        });
+
+        assert_eq!(*progress.lock().unwrap(), vec![
+            SysExProgress::Sent { bytes_sent: 4, bytes_total: 10 },
+            SysExProgress::Sent { bytes_sent: 8, bytes_total: 10 },
+            SysExProgress::Sent { bytes_sent: 10, bytes_total: 10 },
+            SysExProgress::Complete]);
+        let sent = loopback.sent(external).iter()
+            .flat_map(|packet_list: &PacketList| packet_list.iter().flat_map(|packet| packet.data().to_vec()).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        assert_eq!(sent, sysex(10));
+    }
+
+    #[test]
+    fn send_sysex_cancelled_or_failed() {
+        let loopback = Arc::new(Loopback::new());
+        loopback.add_destination("external");
+        with_backend(loopback, || {
+            let client = Client::new("client").unwrap();
+            let output_port = client.output_port("port").unwrap();
+
+            let request = SysExRequest::new(sysex(1000)).with_speed(1).with_chunk_size(1);
+            let transfer = output_port.send_sysex(&Destination::from_index(0), request, |_| {});
+            transfer.cancel();
+            match transfer.wait() {
+                SysExProgress::Cancelled { bytes_sent } => assert!(bytes_sent <= 1),
+                progress => panic!("unexpected {:?}", progress)
+            }
+
+            let transfer = output_port.send_sysex(&Destination::from_index(1), SysExRequest::new(sysex(10)), |_| {});
+            assert_eq!(transfer.wait(), SysExProgress::Failed(Error::WrongEndpointType));
+        });
+    }
+
+    #[test]
+    fn send_sysex_paced_by_a_clock() {
+        let loopback = Arc::new(Loopback::new());
+        loopback.add_destination("external");
+        with_backend(loopback, || {
+            let client = Client::new("client").unwrap();
+            let output_port = client.output_port("port").unwrap();
+            let clock = Arc::new(ManualClock::new(1000));
+            let (sender, progress) = mpsc::channel();
+            let request = SysExRequest::new(sysex(200)).with_speed(1000).with_chunk_size(100);
+            let transfer = output_port.send_sysex_with_clock(&Destination::from_index(0), request, clock.clone(), move |progress| {
+                sender.send(progress).unwrap();
+            });
+
+            assert_eq!(progress.recv(), Ok(SysExProgress::Sent { bytes_sent: 100, bytes_total: 200 }));
+            // The next chunk is only sent once the clock reaches it
+            assert!(progress.recv_timeout(Duration::from_millis(150)).is_err());
+            clock.advance(Timebase::host().nanos_to_ticks(100_000_000));
+            assert_eq!(progress.recv(), Ok(SysExProgress::Sent { bytes_sent: 200, bytes_total: 200 }));
+            assert_eq!(transfer.wait(), SysExProgress::Complete);
+        });
+    }
+
+    #[test]
+    fn dropping_the_transfer_cancels_it() {
+        let loopback = Arc::new(Loopback::new());
+        loopback.add_destination("external");
+        with_backend(loopback, || {
+            let client = Client::new("client").unwrap();
+            let output_port = client.output_port("port").unwrap();
+            let (sender, progress) = mpsc::channel();
+            let request = SysExRequest::new(sysex(1000)).with_speed(1).with_chunk_size(1);
+            let transfer = output_port.send_sysex(&Destination::from_index(0), request, move |progress| {
+                sender.send(progress).unwrap();
+            });
+            drop(transfer);
+
+            // The thread is finished, so its sender is gone after the last progress
+            let reported = progress.iter().collect::<Vec<_>>();
+            match reported.last() {
+                Some(&SysExProgress::Cancelled { bytes_sent }) => assert!(bytes_sent <= 1),
+                progress => panic!("unexpected {:?}", progress)
+            }
+        });
+    }
+}
@@ -0,0 +1,808 @@
+#![allow(non_upper_case_globals)]
+
+use sys::{
+    MIDIEndpointRef,
+    kMIDITransform_None, kMIDITransform_FilterOut, kMIDITransform_MapControl, kMIDITransform_Add,
+    kMIDITransform_Scale, kMIDITransform_MinValue, kMIDITransform_MaxValue, kMIDITransform_MapValue,
+    kMIDIControlType_7Bit, kMIDIControlType_14Bit, kMIDIControlType_7BitRPN, kMIDIControlType_14BitRPN,
+    kMIDIControlType_7BitNRPN, kMIDIControlType_14BitNRPN
+};
+
+use std::error;
+use std::fmt;
+use std::ops::Deref;
+
+use {Destination, Error, Object, Source, ThruConnection};
+use backend;
+
+/// The maximum number of sources, and of destinations, of a thru connection.
+///
+pub const MAX_THRU_ENDPOINTS: usize = 8;
+
+// The size of MIDIThruConnectionParams, before the variable-length control transforms and value maps.
+const PARAMS_SIZE: usize = 204;
+
+// The size of a MIDIControlTransform.
+const CONTROL_TRANSFORM_SIZE: usize = 8;
+
+// The size of a MIDIValueMap.
+const VALUE_MAP_SIZE: usize = 128;
+
+// The offsets of the fields of MIDIThruConnectionParams.
+const NUM_SOURCES: usize = 4;
+const SOURCES: usize = 8;
+const NUM_DESTINATIONS: usize = 72;
+const DESTINATIONS: usize = 76;
+const CHANNEL_MAP: usize = 140;
+const LOW_VELOCITY: usize = 156;
+const HIGH_VELOCITY: usize = 157;
+const LOW_NOTE: usize = 158;
+const HIGH_NOTE: usize = 159;
+const TRANSFORMS: usize = 160;
+const FILTER_OUT_SYSEX: usize = 184;
+const FILTER_OUT_MTC: usize = 185;
+const FILTER_OUT_BEAT_CLOCK: usize = 186;
+const FILTER_OUT_TUNE_REQUEST: usize = 187;
+const FILTER_OUT_ALL_CONTROLS: usize = 191;
+const NUM_CONTROL_TRANSFORMS: usize = 192;
+const NUM_MAPS: usize = 194;
+
+/// An error found while decoding the params of a thru connection.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThruParamsError {
+    /// The number of bytes doesn't match the size of the params, with their control transforms and value maps.
+    InvalidLength(usize),
+    /// Only the version 0 of the params is known.
+    UnsupportedVersion(u32),
+    /// There are more than [MAX_THRU_ENDPOINTS](constant.MAX_THRU_ENDPOINTS.html) sources or destinations.
+    TooManyEndpoints(u32),
+    InvalidTransform(u16),
+    InvalidControlType(u8),
+}
+
+impl fmt::Display for ThruParamsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ThruParamsError::InvalidLength(length) => write!(f, "invalid thru connection params length {}", length),
+            ThruParamsError::UnsupportedVersion(version) => write!(f, "unsupported thru connection params version {}", version),
+            ThruParamsError::TooManyEndpoints(count) => write!(f, "too many thru connection endpoints {}", count),
+            ThruParamsError::InvalidTransform(transform) => write!(f, "invalid transform type {}", transform),
+            ThruParamsError::InvalidControlType(control_type) => write!(f, "invalid control type {}", control_type),
+        }
+    }
+}
+
+impl error::Error for ThruParamsError {}
+
+/// A transformation applied by a thru connection to some kind of messages.
+/// See [MIDITransform](https://developer.apple.com/reference/coremidi/miditransform).
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThruTransform {
+    None,
+    FilterOut,
+    /// Change the control number of control change messages, only for control transforms.
+    MapControl(i16),
+    Add(i16),
+    /// Multiply by a fixed point value with 8 fractional bits, so 256 is 1.
+    Scale(i16),
+    MinValue(i16),
+    MaxValue(i16),
+    /// Map the values through the value map with this index.
+    MapValue(i16),
+}
+
+impl ThruTransform {
+    fn to_raw(self) -> (u16, i16) {
+        match self {
+            ThruTransform::None => (kMIDITransform_None as u16, 0),
+            ThruTransform::FilterOut => (kMIDITransform_FilterOut as u16, 0),
+            ThruTransform::MapControl(param) => (kMIDITransform_MapControl as u16, param),
+            ThruTransform::Add(param) => (kMIDITransform_Add as u16, param),
+            ThruTransform::Scale(param) => (kMIDITransform_Scale as u16, param),
+            ThruTransform::MinValue(param) => (kMIDITransform_MinValue as u16, param),
+            ThruTransform::MaxValue(param) => (kMIDITransform_MaxValue as u16, param),
+            ThruTransform::MapValue(param) => (kMIDITransform_MapValue as u16, param),
+        }
+    }
+
+    fn from_raw(transform: u16, param: i16) -> Result<ThruTransform, ThruParamsError> {
+        match transform as u32 {
+            kMIDITransform_None => Ok(ThruTransform::None),
+            kMIDITransform_FilterOut => Ok(ThruTransform::FilterOut),
+            kMIDITransform_MapControl => Ok(ThruTransform::MapControl(param)),
+            kMIDITransform_Add => Ok(ThruTransform::Add(param)),
+            kMIDITransform_Scale => Ok(ThruTransform::Scale(param)),
+            kMIDITransform_MinValue => Ok(ThruTransform::MinValue(param)),
+            kMIDITransform_MaxValue => Ok(ThruTransform::MaxValue(param)),
+            kMIDITransform_MapValue => Ok(ThruTransform::MapValue(param)),
+            _ => Err(ThruParamsError::InvalidTransform(transform))
+        }
+    }
+}
+
+/// The kind of controls a control transform applies to.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThruControlType {
+    SevenBit,
+    FourteenBit,
+    SevenBitRpn,
+    FourteenBitRpn,
+    SevenBitNrpn,
+    FourteenBitNrpn,
+}
+
+impl ThruControlType {
+    fn to_raw(self) -> u8 {
+        (match self {
+            ThruControlType::SevenBit => kMIDIControlType_7Bit,
+            ThruControlType::FourteenBit => kMIDIControlType_14Bit,
+            ThruControlType::SevenBitRpn => kMIDIControlType_7BitRPN,
+            ThruControlType::FourteenBitRpn => kMIDIControlType_14BitRPN,
+            ThruControlType::SevenBitNrpn => kMIDIControlType_7BitNRPN,
+            ThruControlType::FourteenBitNrpn => kMIDIControlType_14BitNRPN,
+        }) as u8
+    }
+
+    fn from_raw(control_type: u8) -> Result<ThruControlType, ThruParamsError> {
+        match control_type as u32 {
+            kMIDIControlType_7Bit => Ok(ThruControlType::SevenBit),
+            kMIDIControlType_14Bit => Ok(ThruControlType::FourteenBit),
+            kMIDIControlType_7BitRPN => Ok(ThruControlType::SevenBitRpn),
+            kMIDIControlType_14BitRPN => Ok(ThruControlType::FourteenBitRpn),
+            kMIDIControlType_7BitNRPN => Ok(ThruControlType::SevenBitNrpn),
+            kMIDIControlType_14BitNRPN => Ok(ThruControlType::FourteenBitNrpn),
+            _ => Err(ThruParamsError::InvalidControlType(control_type))
+        }
+    }
+}
+
+/// A transformation applied by a thru connection to a control.
+/// See [MIDIControlTransform](https://developer.apple.com/reference/coremidi/midicontroltransform).
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ThruControlTransform {
+    pub control_type: ThruControlType,
+    /// The type of the control after a `MapControl` transform.
+    pub remapped_control_type: ThruControlType,
+    pub control_number: u16,
+    pub transform: ThruTransform,
+}
+
+/// A source or destination of a thru connection.
+///
+/// CoreMIDI finds the endpoint by its unique ID when the reference is no longer valid,
+/// as it happens for persistent connections after a restart.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ThruEndpoint {
+    pub endpoint_ref: MIDIEndpointRef,
+    pub unique_id: u32,
+}
+
+impl ThruEndpoint {
+    fn from_object(object: &Object) -> ThruEndpoint {
+        ThruEndpoint { endpoint_ref: object.0, unique_id: object.unique_id().unwrap_or(0) }
+    }
+}
+
+/// The configuration of a [ThruConnection](struct.ThruConnection.html).
+/// See [MIDIThruConnectionParams](https://developer.apple.com/reference/coremidi/midithruconnectionparams).
+///
+/// It starts like `MIDIThruConnectionParamsInitialize`: without endpoints, with every channel
+/// mapped to itself, all the notes and velocities passing through, and no transforms nor filters.
+///
+/// ```rust,no_run
+/// use coremidi::{ThruConnection, ThruConnectionParams, ThruTransform, Source, Destination};
+/// let params = ThruConnectionParams::new()
+///     .with_source(&Source::from_index(0))
+///     .with_destination(&Destination::from_index(0))
+///     .with_channel(0, 9)
+///     .with_note_transform(ThruTransform::Add(12))
+///     .with_filter_out_beat_clock(true);
+/// let connection = ThruConnection::create_persistent("com.example.thru", &params).unwrap();
+/// ```
+///
+/// It is kept in memory as CoreMIDI does, with the control transforms and the value maps
+/// following the fixed part of the struct, see [to_bytes](#method.to_bytes).
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ThruConnectionParams {
+    sources: Vec<ThruEndpoint>,
+    destinations: Vec<ThruEndpoint>,
+    channel_map: [u8; 16],
+    low_velocity: u8,
+    high_velocity: u8,
+    low_note: u8,
+    high_note: u8,
+    note_number: ThruTransform,
+    velocity: ThruTransform,
+    key_pressure: ThruTransform,
+    channel_pressure: ThruTransform,
+    program_change: ThruTransform,
+    pitch_bend: ThruTransform,
+    filter_out_sysex: bool,
+    filter_out_mtc: bool,
+    filter_out_beat_clock: bool,
+    filter_out_tune_request: bool,
+    filter_out_all_controls: bool,
+    control_transforms: Vec<ThruControlTransform>,
+    value_maps: Vec<[u8; VALUE_MAP_SIZE]>,
+}
+
+impl ThruConnectionParams {
+    pub fn new() -> ThruConnectionParams {
+        let mut channel_map = [0; 16];
+        for (channel, mapped) in channel_map.iter_mut().enumerate() {
+            *mapped = channel as u8;
+        }
+        ThruConnectionParams {
+            sources: Vec::new(),
+            destinations: Vec::new(),
+            channel_map: channel_map,
+            low_velocity: 0,
+            high_velocity: 0,
+            low_note: 0,
+            high_note: 127,
+            note_number: ThruTransform::None,
+            velocity: ThruTransform::None,
+            key_pressure: ThruTransform::None,
+            channel_pressure: ThruTransform::None,
+            program_change: ThruTransform::None,
+            pitch_bend: ThruTransform::None,
+            filter_out_sysex: false,
+            filter_out_mtc: false,
+            filter_out_beat_clock: false,
+            filter_out_tune_request: false,
+            filter_out_all_controls: false,
+            control_transforms: Vec::new(),
+            value_maps: Vec::new(),
+        }
+    }
+
+    /// Add a source to get the messages from.
+    ///
+    /// It panics if there are already [MAX_THRU_ENDPOINTS](constant.MAX_THRU_ENDPOINTS.html) sources.
+    ///
+    pub fn with_source(self, source: &Source) -> ThruConnectionParams {
+        self.with_source_endpoint(ThruEndpoint::from_object(source))
+    }
+
+    /// Add a source given by its reference and unique ID.
+    ///
+    pub fn with_source_endpoint(mut self, endpoint: ThruEndpoint) -> ThruConnectionParams {
+        assert!(self.sources.len() < MAX_THRU_ENDPOINTS, "too many thru connection sources");
+        self.sources.push(endpoint);
+        self
+    }
+
+    /// Add a destination to send the messages to.
+    ///
+    /// It panics if there are already [MAX_THRU_ENDPOINTS](constant.MAX_THRU_ENDPOINTS.html) destinations.
+    ///
+    pub fn with_destination(self, destination: &Destination) -> ThruConnectionParams {
+        self.with_destination_endpoint(ThruEndpoint::from_object(destination))
+    }
+
+    /// Add a destination given by its reference and unique ID.
+    ///
+    pub fn with_destination_endpoint(mut self, endpoint: ThruEndpoint) -> ThruConnectionParams {
+        assert!(self.destinations.len() < MAX_THRU_ENDPOINTS, "too many thru connection destinations");
+        self.destinations.push(endpoint);
+        self
+    }
+
+    /// Send the channel messages from a channel (0 to 15) to another one, or filter them out with `None`.
+    ///
+    pub fn with_channel<C: Into<Option<u8>>>(mut self, channel: u8, mapped: C) -> ThruConnectionParams {
+        let mapped = mapped.into();
+        assert!(channel < 16 && mapped.map_or(true, |mapped| mapped < 16), "invalid channel");
+        self.channel_map[channel as usize] = mapped.unwrap_or(0xff);
+        self
+    }
+
+    /// Only let through the notes within a range, both included.
+    /// The range wraps around when `low` is greater than `high`, to filter out the notes in between.
+    ///
+    pub fn with_note_range(mut self, low: u8, high: u8) -> ThruConnectionParams {
+        self.low_note = low;
+        self.high_note = high;
+        self
+    }
+
+    /// Only let through the notes with a velocity within a range, both included.
+    /// A `high` velocity of 0 disables the filter.
+    ///
+    pub fn with_velocity_range(mut self, low: u8, high: u8) -> ThruConnectionParams {
+        self.low_velocity = low;
+        self.high_velocity = high;
+        self
+    }
+
+    /// Transform the note numbers of note and key pressure messages.
+    ///
+    pub fn with_note_transform(mut self, transform: ThruTransform) -> ThruConnectionParams {
+        self.note_number = transform;
+        self
+    }
+
+    pub fn with_velocity_transform(mut self, transform: ThruTransform) -> ThruConnectionParams {
+        self.velocity = transform;
+        self
+    }
+
+    pub fn with_key_pressure_transform(mut self, transform: ThruTransform) -> ThruConnectionParams {
+        self.key_pressure = transform;
+        self
+    }
+
+    pub fn with_channel_pressure_transform(mut self, transform: ThruTransform) -> ThruConnectionParams {
+        self.channel_pressure = transform;
+        self
+    }
+
+    pub fn with_program_change_transform(mut self, transform: ThruTransform) -> ThruConnectionParams {
+        self.program_change = transform;
+        self
+    }
+
+    pub fn with_pitch_bend_transform(mut self, transform: ThruTransform) -> ThruConnectionParams {
+        self.pitch_bend = transform;
+        self
+    }
+
+    pub fn with_control_transform(mut self, control_transform: ThruControlTransform) -> ThruConnectionParams {
+        self.control_transforms.push(control_transform);
+        self
+    }
+
+    /// Add a map from every value (0 to 127) to another one,
+    /// to be used by `MapValue` transforms with the number of maps added before it.
+    ///
+    pub fn with_value_map(mut self, value_map: [u8; VALUE_MAP_SIZE]) -> ThruConnectionParams {
+        self.value_maps.push(value_map);
+        self
+    }
+
+    pub fn with_filter_out_sysex(mut self, filter_out: bool) -> ThruConnectionParams {
+        self.filter_out_sysex = filter_out;
+        self
+    }
+
+    /// Filter out the MIDI Time Code messages.
+    ///
+    pub fn with_filter_out_mtc(mut self, filter_out: bool) -> ThruConnectionParams {
+        self.filter_out_mtc = filter_out;
+        self
+    }
+
+    /// Filter out the timing clock, start, stop, continue and song position messages.
+    ///
+    pub fn with_filter_out_beat_clock(mut self, filter_out: bool) -> ThruConnectionParams {
+        self.filter_out_beat_clock = filter_out;
+        self
+    }
+
+    pub fn with_filter_out_tune_request(mut self, filter_out: bool) -> ThruConnectionParams {
+        self.filter_out_tune_request = filter_out;
+        self
+    }
+
+    /// Filter out all the control change messages, the control transforms being ignored.
+    ///
+    pub fn with_filter_out_all_controls(mut self, filter_out: bool) -> ThruConnectionParams {
+        self.filter_out_all_controls = filter_out;
+        self
+    }
+
+    pub fn sources(&self) -> &[ThruEndpoint] {
+        &self.sources
+    }
+
+    pub fn destinations(&self) -> &[ThruEndpoint] {
+        &self.destinations
+    }
+
+    /// The channel every channel is sent to, or `None` when it is filtered out.
+    ///
+    pub fn channel(&self, channel: u8) -> Option<u8> {
+        match self.channel_map[channel as usize] {
+            mapped @ 0 ..= 15 => Some(mapped),
+            _ => None
+        }
+    }
+
+    pub fn control_transforms(&self) -> &[ThruControlTransform] {
+        &self.control_transforms
+    }
+
+    pub fn value_maps(&self) -> &[[u8; VALUE_MAP_SIZE]] {
+        &self.value_maps
+    }
+
+    /// Encode the params as the bytes of a `MIDIThruConnectionParams`, followed by the control transforms and the value maps,
+    /// in the byte order of the host.
+    ///
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![0; PARAMS_SIZE + self.control_transforms.len() * CONTROL_TRANSFORM_SIZE + self.value_maps.len() * VALUE_MAP_SIZE];
+
+        // The version at offset 0 is 0
+        write_endpoints(&mut bytes, NUM_SOURCES, SOURCES, &self.sources);
+        write_endpoints(&mut bytes, NUM_DESTINATIONS, DESTINATIONS, &self.destinations);
+        bytes[CHANNEL_MAP..CHANNEL_MAP + 16].copy_from_slice(&self.channel_map);
+        bytes[LOW_VELOCITY] = self.low_velocity;
+        bytes[HIGH_VELOCITY] = self.high_velocity;
+        bytes[LOW_NOTE] = self.low_note;
+        bytes[HIGH_NOTE] = self.high_note;
+        for (index, transform) in self.transforms().iter().enumerate() {
+            write_transform(&mut bytes, TRANSFORMS + index * 4, *transform);
+        }
+        bytes[FILTER_OUT_SYSEX] = self.filter_out_sysex as u8;
+        bytes[FILTER_OUT_MTC] = self.filter_out_mtc as u8;
+        bytes[FILTER_OUT_BEAT_CLOCK] = self.filter_out_beat_clock as u8;
+        bytes[FILTER_OUT_TUNE_REQUEST] = self.filter_out_tune_request as u8;
+        bytes[FILTER_OUT_ALL_CONTROLS] = self.filter_out_all_controls as u8;
+        write_u16(&mut bytes, NUM_CONTROL_TRANSFORMS, self.control_transforms.len() as u16);
+        write_u16(&mut bytes, NUM_MAPS, self.value_maps.len() as u16);
+
+        let mut offset = PARAMS_SIZE;
+        for control_transform in &self.control_transforms {
+            bytes[offset] = control_transform.control_type.to_raw();
+            bytes[offset + 1] = control_transform.remapped_control_type.to_raw();
+            write_u16(&mut bytes, offset + 2, control_transform.control_number);
+            write_transform(&mut bytes, offset + 4, control_transform.transform);
+            offset += CONTROL_TRANSFORM_SIZE;
+        }
+        for value_map in &self.value_maps {
+            bytes[offset..offset + VALUE_MAP_SIZE].copy_from_slice(value_map);
+            offset += VALUE_MAP_SIZE;
+        }
+        bytes
+    }
+
+    /// Decode the params from the bytes of a `MIDIThruConnectionParams`, followed by the control transforms and the value maps.
+    ///
+    pub fn from_bytes(bytes: &[u8]) -> Result<ThruConnectionParams, ThruParamsError> {
+        if bytes.len() < PARAMS_SIZE {
+            return Err(ThruParamsError::InvalidLength(bytes.len()));
+        }
+        let version = read_u32(bytes, 0);
+        if version != 0 {
+            return Err(ThruParamsError::UnsupportedVersion(version));
+        }
+        let control_transform_count = read_u16(bytes, NUM_CONTROL_TRANSFORMS) as usize;
+        let value_map_count = read_u16(bytes, NUM_MAPS) as usize;
+        if bytes.len() != PARAMS_SIZE + control_transform_count * CONTROL_TRANSFORM_SIZE + value_map_count * VALUE_MAP_SIZE {
+            return Err(ThruParamsError::InvalidLength(bytes.len()));
+        }
+
+        let mut channel_map = [0; 16];
+        channel_map.copy_from_slice(&bytes[CHANNEL_MAP..CHANNEL_MAP + 16]);
+        let transform = |index: usize| read_transform(bytes, TRANSFORMS + index * 4);
+
+        let mut offset = PARAMS_SIZE;
+        let mut control_transforms = Vec::with_capacity(control_transform_count);
+        for _ in 0..control_transform_count {
+            control_transforms.push(ThruControlTransform {
+                control_type: ThruControlType::from_raw(bytes[offset])?,
+                remapped_control_type: ThruControlType::from_raw(bytes[offset + 1])?,
+                control_number: read_u16(bytes, offset + 2),
+                transform: read_transform(bytes, offset + 4)?,
+            });
+            offset += CONTROL_TRANSFORM_SIZE;
+        }
+        let mut value_maps = Vec::with_capacity(value_map_count);
+        for _ in 0..value_map_count {
+            let mut value_map = [0; VALUE_MAP_SIZE];
+            value_map.copy_from_slice(&bytes[offset..offset + VALUE_MAP_SIZE]);
+            value_maps.push(value_map);
+            offset += VALUE_MAP_SIZE;
+        }
+
+        Ok(ThruConnectionParams {
+            sources: read_endpoints(bytes, NUM_SOURCES, SOURCES)?,
+            destinations: read_endpoints(bytes, NUM_DESTINATIONS, DESTINATIONS)?,
+            channel_map: channel_map,
+            low_velocity: bytes[LOW_VELOCITY],
+            high_velocity: bytes[HIGH_VELOCITY],
+            low_note: bytes[LOW_NOTE],
+            high_note: bytes[HIGH_NOTE],
+            note_number: transform(0)?,
+            velocity: transform(1)?,
+            key_pressure: transform(2)?,
+            channel_pressure: transform(3)?,
+            program_change: transform(4)?,
+            pitch_bend: transform(5)?,
+            filter_out_sysex: bytes[FILTER_OUT_SYSEX] != 0,
+            filter_out_mtc: bytes[FILTER_OUT_MTC] != 0,
+            filter_out_beat_clock: bytes[FILTER_OUT_BEAT_CLOCK] != 0,
+            filter_out_tune_request: bytes[FILTER_OUT_TUNE_REQUEST] != 0,
+            filter_out_all_controls: bytes[FILTER_OUT_ALL_CONTROLS] != 0,
+            control_transforms: control_transforms,
+            value_maps: value_maps,
+        })
+    }
+
+    // The transforms in the order of the struct.
+    fn transforms(&self) -> [ThruTransform; 6] {
+        [self.note_number, self.velocity, self.key_pressure, self.channel_pressure, self.program_change, self.pitch_bend]
+    }
+}
+
+impl Default for ThruConnectionParams {
+    fn default() -> Self {
+        ThruConnectionParams::new()
+    }
+}
+
+fn write_u16(bytes: &mut [u8], offset: usize, value: u16) {
+    bytes[offset..offset + 2].copy_from_slice(&value.to_ne_bytes());
+}
+
+fn write_u32(bytes: &mut [u8], offset: usize, value: u32) {
+    bytes[offset..offset + 4].copy_from_slice(&value.to_ne_bytes());
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_ne_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_ne_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]])
+}
+
+fn write_transform(bytes: &mut [u8], offset: usize, transform: ThruTransform) {
+    let (transform, param) = transform.to_raw();
+    write_u16(bytes, offset, transform);
+    write_u16(bytes, offset + 2, param as u16);
+}
+
+fn read_transform(bytes: &[u8], offset: usize) -> Result<ThruTransform, ThruParamsError> {
+    ThruTransform::from_raw(read_u16(bytes, offset), read_u16(bytes, offset + 2) as i16)
+}
+
+// The count followed by the array of MIDIThruConnectionEndpoint.
+fn write_endpoints(bytes: &mut [u8], count_offset: usize, offset: usize, endpoints: &[ThruEndpoint]) {
+    write_u32(bytes, count_offset, endpoints.len() as u32);
+    for (index, endpoint) in endpoints.iter().enumerate() {
+        write_u32(bytes, offset + index * 8, endpoint.endpoint_ref);
+        write_u32(bytes, offset + index * 8 + 4, endpoint.unique_id);
+    }
+}
+
+fn read_endpoints(bytes: &[u8], count_offset: usize, offset: usize) -> Result<Vec<ThruEndpoint>, ThruParamsError> {
+    let count = read_u32(bytes, count_offset);
+    if count as usize > MAX_THRU_ENDPOINTS {
+        return Err(ThruParamsError::TooManyEndpoints(count));
+    }
+    Ok((0..count as usize).map(|index| ThruEndpoint {
+        endpoint_ref: read_u32(bytes, offset + index * 8),
+        unique_id: read_u32(bytes, offset + index * 8 + 4),
+    }).collect())
+}
+
+impl ThruConnection {
+    /// Create a thru connection owned by the current process, which is disposed when the process exits.
+    /// See [MIDIThruConnectionCreate](https://developer.apple.com/reference/coremidi/midithruconnectioncreate).
+    ///
+    pub fn create(params: &ThruConnectionParams) -> Result<ThruConnection, Error> {
+        Self::create_with_owner(None, params)
+    }
+
+    /// Create a persistent thru connection, which stays in the system until it is disposed.
+    /// It can be found again with [find](#method.find) by the same owner ID, usually the bundle ID of the application.
+    ///
+    pub fn create_persistent(owner_id: &str, params: &ThruConnectionParams) -> Result<ThruConnection, Error> {
+        Self::create_with_owner(Some(owner_id), params)
+    }
+
+    fn create_with_owner(owner_id: Option<&str>, params: &ThruConnectionParams) -> Result<ThruConnection, Error> {
+        let backend = backend::current();
+        backend.thru_connection_create(owner_id, &params.to_bytes())
+            .map(|connection_ref| ThruConnection { object: Object(connection_ref, backend.clone()) })
+            .map_err(Error::from)
+    }
+
+    /// The persistent thru connections created with an owner ID.
+    /// See [MIDIThruConnectionFind](https://developer.apple.com/reference/coremidi/midithruconnectionfind).
+    ///
+    pub fn find(owner_id: &str) -> Result<Vec<ThruConnection>, Error> {
+        let backend = backend::current();
+        backend.thru_connection_find(owner_id)
+            .map(|connection_refs| connection_refs.into_iter()
+                .map(|connection_ref| ThruConnection { object: Object(connection_ref, backend.clone()) })
+                .collect())
+            .map_err(Error::from)
+    }
+
+    /// The current params of the connection.
+    /// See [MIDIThruConnectionGetParams](https://developer.apple.com/reference/coremidi/midithruconnectiongetparams).
+    ///
+    /// It fails with `SetupFormatErr` when the params can't be decoded.
+    ///
+    pub fn params(&self) -> Result<ThruConnectionParams, Error> {
+        let bytes = self.object.backend().thru_connection_get_params(self.object.0).map_err(Error::from)?;
+        ThruConnectionParams::from_bytes(&bytes).map_err(|_| Error::SetupFormatErr)
+    }
+
+    /// Change the params of the connection.
+    /// See [MIDIThruConnectionSetParams](https://developer.apple.com/reference/coremidi/midithruconnectionsetparams).
+    ///
+    pub fn set_params(&self, params: &ThruConnectionParams) -> Result<(), Error> {
+        let status = self.object.backend().thru_connection_set_params(self.object.0, &params.to_bytes());
+        if status == 0 { Ok(()) } else { Err(Error::from(status)) }
+    }
+
+    /// Remove the connection from the system.
+    /// See [MIDIThruConnectionDispose](https://developer.apple.com/reference/coremidi/midithruconnectiondispose).
+    ///
+    pub fn dispose(self) -> Result<(), Error> {
+        let status = self.object.backend().thru_connection_dispose(self.object.0);
+        if status == 0 { Ok(()) } else { Err(Error::from(status)) }
+    }
+}
+
+impl Deref for ThruConnection {
+    type Target = Object;
+
+    fn deref(&self) -> &Object {
+        &self.object
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use backend::with_backend;
+    use backend::memory::MemoryBackend;
+    use thru::{ThruConnectionParams, ThruControlTransform, ThruControlType, ThruEndpoint, ThruParamsError, ThruTransform};
+    use {Destination, Error, Source, ThruConnection};
+
+    fn u16_bytes(value: u16) -> [u8; 2] {
+        value.to_ne_bytes()
+    }
+
+    fn u32_bytes(value: u32) -> [u8; 4] {
+        value.to_ne_bytes()
+    }
+
+    #[test]
+    fn default_params_layout() {
+        let bytes = ThruConnectionParams::new().to_bytes();
+        let mut expected = vec![0u8; 204];
+        for channel in 0..16 {
+            expected[140 + channel] = channel as u8;
+        }
+        expected[159] = 127;
+        assert_eq!(bytes, expected);
+        assert_eq!(ThruConnectionParams::from_bytes(&bytes), Ok(ThruConnectionParams::default()));
+    }
+
+    #[test]
+    fn params_layout() {
+        let mut value_map = [0; 128];
+        for (value, mapped) in value_map.iter_mut().enumerate() {
+            *mapped = 127 - value as u8;
+        }
+        let params = ThruConnectionParams::new()
+            .with_source_endpoint(ThruEndpoint { endpoint_ref: 0x1234, unique_id: 0xdead_beef })
+            .with_destination_endpoint(ThruEndpoint { endpoint_ref: 0x10, unique_id: 1 })
+            .with_destination_endpoint(ThruEndpoint { endpoint_ref: 0x20, unique_id: 2 })
+            .with_channel(0, 9)
+            .with_channel(15, None)
+            .with_velocity_range(10, 100)
+            .with_note_range(36, 96)
+            .with_note_transform(ThruTransform::Add(-12))
+            .with_velocity_transform(ThruTransform::Scale(512))
+            .with_key_pressure_transform(ThruTransform::FilterOut)
+            .with_channel_pressure_transform(ThruTransform::MinValue(5))
+            .with_program_change_transform(ThruTransform::MaxValue(64))
+            .with_pitch_bend_transform(ThruTransform::MapValue(0))
+            .with_filter_out_sysex(true)
+            .with_filter_out_beat_clock(true)
+            .with_filter_out_all_controls(true)
+            .with_control_transform(ThruControlTransform {
+                control_type: ThruControlType::SevenBit,
+                remapped_control_type: ThruControlType::FourteenBitNrpn,
+                control_number: 7,
+                transform: ThruTransform::MapControl(11),
+            })
+            .with_value_map(value_map);
+
+        let bytes = params.to_bytes();
+        assert_eq!(bytes.len(), 204 + 8 + 128);
+        assert_eq!(&bytes[0..4], &u32_bytes(0));
+        assert_eq!(&bytes[4..8], &u32_bytes(1));
+        assert_eq!(&bytes[8..12], &u32_bytes(0x1234));
+        assert_eq!(&bytes[12..16], &u32_bytes(0xdead_beef));
+        assert!(bytes[16..72].iter().all(|&byte| byte == 0));
+        assert_eq!(&bytes[72..76], &u32_bytes(2));
+        assert_eq!(&bytes[76..80], &u32_bytes(0x10));
+        assert_eq!(&bytes[80..84], &u32_bytes(1));
+        assert_eq!(&bytes[84..88], &u32_bytes(0x20));
+        assert_eq!(&bytes[88..92], &u32_bytes(2));
+        assert!(bytes[92..140].iter().all(|&byte| byte == 0));
+        assert_eq!(&bytes[140..156], &[9, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 0xff]);
+        assert_eq!(&bytes[156..160], &[10, 100, 36, 96]);
+        assert_eq!(&bytes[160..164], &[u16_bytes(8), u16_bytes(-12i16 as u16)].concat()[..]);
+        assert_eq!(&bytes[164..168], &[u16_bytes(9), u16_bytes(512)].concat()[..]);
+        assert_eq!(&bytes[168..172], &[u16_bytes(1), u16_bytes(0)].concat()[..]);
+        assert_eq!(&bytes[172..176], &[u16_bytes(10), u16_bytes(5)].concat()[..]);
+        assert_eq!(&bytes[176..180], &[u16_bytes(11), u16_bytes(64)].concat()[..]);
+        assert_eq!(&bytes[180..184], &[u16_bytes(12), u16_bytes(0)].concat()[..]);
+        assert_eq!(&bytes[184..192], &[1, 0, 1, 0, 0, 0, 0, 1]);
+        assert_eq!(&bytes[192..194], &u16_bytes(1));
+        assert_eq!(&bytes[194..196], &u16_bytes(1));
+        assert!(bytes[196..204].iter().all(|&byte| byte == 0));
+        assert_eq!(&bytes[204..212], &[&[0, 5][..], &u16_bytes(7), &u16_bytes(2), &u16_bytes(11)].concat()[..]);
+        assert_eq!(&bytes[212..], &value_map[..]);
+
+        assert_eq!(ThruConnectionParams::from_bytes(&bytes), Ok(params.clone()));
+        assert_eq!(params.channel(0), Some(9));
+        assert_eq!(params.channel(15), None);
+        assert_eq!(params.sources().len(), 1);
+        assert_eq!(params.destinations().len(), 2);
+    }
+
+    #[test]
+    fn invalid_params() {
+        let bytes = ThruConnectionParams::new().to_bytes();
+        assert_eq!(ThruConnectionParams::from_bytes(&bytes[..203]), Err(ThruParamsError::InvalidLength(203)));
+
+        let mut invalid = bytes.clone();
+        invalid[0..4].copy_from_slice(&u32_bytes(1));
+        assert_eq!(ThruConnectionParams::from_bytes(&invalid), Err(ThruParamsError::UnsupportedVersion(1)));
+
+        let mut invalid = bytes.clone();
+        invalid[72..76].copy_from_slice(&u32_bytes(9));
+        assert_eq!(ThruConnectionParams::from_bytes(&invalid), Err(ThruParamsError::TooManyEndpoints(9)));
+
+        let mut invalid = bytes.clone();
+        invalid[192..194].copy_from_slice(&u16_bytes(1));
+        assert_eq!(ThruConnectionParams::from_bytes(&invalid), Err(ThruParamsError::InvalidLength(204)));
+        invalid.extend_from_slice(&[6, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(ThruConnectionParams::from_bytes(&invalid), Err(ThruParamsError::InvalidControlType(6)));
+
+        let mut invalid = bytes.clone();
+        invalid[164..166].copy_from_slice(&u16_bytes(3));
+        assert_eq!(ThruConnectionParams::from_bytes(&invalid), Err(ThruParamsError::InvalidTransform(3)));
+    }
+
+    #[test]
+    #[should_panic]
+    fn too_many_sources() {
+        let endpoint = ThruEndpoint { endpoint_ref: 1, unique_id: 1 };
+        (0..9).fold(ThruConnectionParams::new(), |params, _| params.with_source_endpoint(endpoint));
+    }
+
+    #[test]
+    fn create_find_and_dispose() {
+        let backend = Arc::new(MemoryBackend::new());
+        backend.add_source("source");
+        backend.add_destination("destination");
+        with_backend(backend, || {
+            let params = ThruConnectionParams::new()
+                .with_source(&Source::from_index(0))
+                .with_destination(&Destination::from_index(0));
+            let persistent = ThruConnection::create_persistent("com.example.thru", &params).unwrap();
+            let _other = ThruConnection::create_persistent("com.example.other", &params).unwrap();
+            let _transient = ThruConnection::create(&params).unwrap();
+
+            let found = ThruConnection::find("com.example.thru").unwrap();
+            assert_eq!(found, vec![persistent]);
+            assert_eq!(found[0].params(), Ok(params.clone()));
+
+            let changed = params.with_note_transform(ThruTransform::Add(7));
+            found[0].set_params(&changed).unwrap();
+            assert_eq!(found[0].params(), Ok(changed));
+
+            let connection = ThruConnection::find("com.example.thru").unwrap().remove(0);
+            connection.dispose().unwrap();
+            assert!(ThruConnection::find("com.example.thru").unwrap().is_empty());
+            assert_eq!(found[0].params(), Err(Error::ObjectNotFound));
+        });
+    }
+}
@@ -0,0 +1,251 @@
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use clock::{Clock, HostClock};
+use sys::{mach_timebase_info, mach_timebase_info_data};
+use packets::Timestamp;
+
+const NANOS_PER_SECOND: u128 = 1_000_000_000;
+
+/// The ratio between host ticks and nanoseconds.
+/// See [mach_absolute_time](https://developer.apple.com/documentation/kernel/1462446-mach_absolute_time).
+///
+/// A number of ticks multiplied by `numer / denom` gives nanoseconds. The conversions only depend on the ratio,
+/// rounding to the nearest value and saturating instead of overflowing:
+///
+/// ```
+/// use std::time::Duration;
+/// let timebase = coremidi::Timebase::new(125, 3);
+/// assert_eq!(timebase.ticks_to_nanos(24), 1000);
+/// assert_eq!(timebase.duration_to_ticks(Duration::from_micros(1)), 24);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timebase {
+    numer: u32,
+    denom: u32,
+}
+
+impl Timebase {
+    /// Create a timebase where `denom` ticks last `numer` nanoseconds.
+    ///
+    /// It panics if any of them is 0.
+    ///
+    pub fn new(numer: u32, denom: u32) -> Timebase {
+        assert!(numer != 0 && denom != 0, "invalid timebase {}/{}", numer, denom);
+        Timebase { numer: numer, denom: denom }
+    }
+
+    /// The timebase of the host, as used by the timestamps of CoreMIDI.
+    ///
+    pub fn host() -> Timebase {
+        static HOST_TIMEBASE: OnceLock<Timebase> = OnceLock::new();
+        *HOST_TIMEBASE.get_or_init(|| {
+            let mut info = mach_timebase_info_data { numer: 0, denom: 0 };
+            let status = unsafe { mach_timebase_info(&mut info) };
+            if status == 0 && info.numer != 0 && info.denom != 0 {
+                Timebase::new(info.numer, info.denom)
+            }
+            else {
+                // Host ticks are nanoseconds on Intel
+                Timebase::new(1, 1)
+            }
+        })
+    }
+
+    pub fn numer(&self) -> u32 {
+        self.numer
+    }
+
+    pub fn denom(&self) -> u32 {
+        self.denom
+    }
+
+    /// Convert host ticks into nanoseconds.
+    ///
+    pub fn ticks_to_nanos(&self, ticks: u64) -> u64 {
+        saturate(scale(ticks as u128, self.numer as u128, self.denom as u128))
+    }
+
+    /// Convert nanoseconds into host ticks.
+    ///
+    pub fn nanos_to_ticks(&self, nanos: u64) -> u64 {
+        saturate(scale(nanos as u128, self.denom as u128, self.numer as u128))
+    }
+
+    /// Convert host ticks into a duration.
+    ///
+    pub fn ticks_to_duration(&self, ticks: u64) -> Duration {
+        let nanos = scale(ticks as u128, self.numer as u128, self.denom as u128);
+        let seconds = nanos / NANOS_PER_SECOND;
+        if seconds > u64::MAX as u128 {
+            Duration::new(u64::MAX, (NANOS_PER_SECOND - 1) as u32)
+        }
+        else {
+            Duration::new(seconds as u64, (nanos % NANOS_PER_SECOND) as u32)
+        }
+    }
+
+    /// Convert a duration into host ticks.
+    ///
+    pub fn duration_to_ticks(&self, duration: Duration) -> u64 {
+        saturate(scale(duration.as_nanos(), self.denom as u128, self.numer as u128))
+    }
+}
+
+// value * numer / denom, rounded to the nearest integer.
+// It can not overflow for values coming from u64 or Duration, as numer is at most 32 bits.
+fn scale(value: u128, numer: u128, denom: u128) -> u128 {
+    let scaled = value * numer;
+    scaled / denom + if (scaled % denom) * 2 >= denom { 1 } else { 0 }
+}
+
+fn saturate(value: u128) -> u64 {
+    if value > u64::MAX as u128 { u64::MAX } else { value as u64 }
+}
+
+/// Conversions between timestamps in host ticks and time units, using the [host timebase](struct.Timebase.html#method.host).
+///
+/// As `Timestamp` is a plain `u64`, operators can not be overloaded for it, so the arithmetic
+/// with durations is done through [add_duration](#tymethod.add_duration) and [sub_duration](#tymethod.sub_duration):
+///
+/// ```rust,no_run
+/// use coremidi::{HostTime, Timestamp};
+/// use std::time::Duration;
+/// let timestamp = Timestamp::now().add_duration(Duration::from_millis(10));
+/// let mut packets = coremidi::PacketBuffer::dyn();
+/// packets.push_packet(timestamp, &[0x90, 0x40, 0x7f]);
+/// ```
+pub trait HostTime: Copy {
+    /// The current host time. See [HostClock](struct.HostClock.html).
+    ///
+    fn now() -> Self;
+
+    /// The timestamp for some nanoseconds of host time.
+    ///
+    fn from_nanos(nanos: u64) -> Self;
+
+    /// The host time of the timestamp in nanoseconds.
+    ///
+    fn as_nanos(self) -> u64;
+
+    /// The timestamp for a duration of host time.
+    ///
+    fn from_duration(duration: Duration) -> Self;
+
+    /// The host time of the timestamp as a duration.
+    ///
+    fn as_duration(self) -> Duration;
+
+    /// The timestamp a duration later, saturating at the maximum timestamp.
+    ///
+    fn add_duration(self, duration: Duration) -> Self;
+
+    /// The timestamp a duration earlier, saturating at 0.
+    ///
+    fn sub_duration(self, duration: Duration) -> Self;
+}
+
+impl HostTime for Timestamp {
+    fn now() -> Timestamp {
+        HostClock.now()
+    }
+
+    fn from_nanos(nanos: u64) -> Timestamp {
+        Timebase::host().nanos_to_ticks(nanos)
+    }
+
+    fn as_nanos(self) -> u64 {
+        Timebase::host().ticks_to_nanos(self)
+    }
+
+    fn from_duration(duration: Duration) -> Timestamp {
+        Timebase::host().duration_to_ticks(duration)
+    }
+
+    fn as_duration(self) -> Duration {
+        Timebase::host().ticks_to_duration(self)
+    }
+
+    fn add_duration(self, duration: Duration) -> Timestamp {
+        self.saturating_add(Timestamp::from_duration(duration))
+    }
+
+    fn sub_duration(self, duration: Duration) -> Timestamp {
+        self.saturating_sub(Timestamp::from_duration(duration))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use time::Timebase;
+
+    #[test]
+    fn identity_timebase() {
+        let timebase = Timebase::new(1, 1);
+        assert_eq!(timebase.ticks_to_nanos(123_456_789), 123_456_789);
+        assert_eq!(timebase.nanos_to_ticks(123_456_789), 123_456_789);
+        assert_eq!(timebase.ticks_to_duration(1_500_000_000), Duration::new(1, 500_000_000));
+        assert_eq!(timebase.duration_to_ticks(Duration::from_millis(10)), 10_000_000);
+    }
+
+    #[test]
+    fn apple_silicon_timebase() {
+        // 24 MHz ticks
+        let timebase = Timebase::new(125, 3);
+        assert_eq!(timebase.ticks_to_nanos(24_000_000), 1_000_000_000);
+        assert_eq!(timebase.nanos_to_ticks(1_000_000_000), 24_000_000);
+        assert_eq!(timebase.ticks_to_duration(240_000), Duration::from_millis(10));
+        assert_eq!(timebase.duration_to_ticks(Duration::from_millis(10)), 240_000);
+    }
+
+    #[test]
+    fn conversions_round_to_nearest() {
+        let timebase = Timebase::new(125, 3);
+        // 1 tick = 41.67 ns
+        assert_eq!(timebase.ticks_to_nanos(1), 42);
+        // 2 ticks = 83.33 ns
+        assert_eq!(timebase.ticks_to_nanos(2), 83);
+        // 20 ns = 0.48 ticks, 21 ns = 0.504 ticks
+        assert_eq!(timebase.nanos_to_ticks(20), 0);
+        assert_eq!(timebase.nanos_to_ticks(21), 1);
+
+        // 3 ticks = 1.5 ns rounds up
+        let timebase = Timebase::new(1, 2);
+        assert_eq!(timebase.ticks_to_nanos(3), 2);
+        assert_eq!(timebase.ticks_to_nanos(1), 1);
+        assert_eq!(timebase.nanos_to_ticks(3), 6);
+    }
+
+    #[test]
+    fn conversions_saturate() {
+        let timebase = Timebase::new(125, 3);
+        assert_eq!(timebase.ticks_to_nanos(u64::MAX), u64::MAX);
+        assert_eq!(timebase.nanos_to_ticks(u64::MAX), 442_721_857_769_029_239);
+        assert_eq!(timebase.duration_to_ticks(Duration::new(u64::MAX, 999_999_999)), u64::MAX);
+        assert_eq!(timebase.ticks_to_duration(u64::MAX), Duration::new(768_614_336_404, 564_650_625));
+
+        let timebase = Timebase::new(u32::MAX, 1);
+        assert_eq!(timebase.ticks_to_duration(u64::MAX), Duration::new(u64::MAX, 999_999_999));
+    }
+
+    #[test]
+    fn round_trip() {
+        for &(numer, denom) in &[(1, 1), (125, 3), (1_000_000_000, 1_000_000_000 / 3)] {
+            let timebase = Timebase::new(numer, denom);
+            for &nanos in &[0, 1_000, 10_000_000, 3_600_000_000_000] {
+                let ticks = timebase.nanos_to_ticks(nanos);
+                let back = timebase.ticks_to_nanos(ticks);
+                let tick_nanos = (numer / denom) as u64 + 1;
+                assert!(back.max(nanos) - back.min(nanos) <= tick_nanos, "{}/{}: {} -> {} -> {}", numer, denom, nanos, ticks, back);
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn invalid_timebase() {
+        Timebase::new(1, 0);
+    }
+}